@@ -0,0 +1,95 @@
+//! Compares drawing sprites onto the bit-packed `Framebuffer` with the `[[bool; 64]; 32]` display
+//! it replaced. Run with `cargo bench --bench framebuffer`.
+
+use std::{
+    hint::black_box,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use chip8::framebuffer::Framebuffer;
+
+const FRAMES: usize = 20_000;
+const SPRITES_PER_FRAME: usize = 32;
+const SPRITE: [u8; 15] = [
+    0x3C, 0x7E, 0xFF, 0xDB, 0xFF, 0x7E, 0x3C, 0x18, 0x3C, 0x7E, 0xFF, 0xDB, 0xFF, 0x7E, 0x3C,
+];
+
+/// The display as it used to be stored, drawn one pixel at a time.
+struct BoolDisplay {
+    pixels: [[bool; 64]; 32],
+}
+
+impl BoolDisplay {
+    fn draw(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool {
+        let mut collision = false;
+        for (byte_index, &byte) in sprite.iter().enumerate() {
+            for pixel_index in 0..8 {
+                let row = (y as usize + byte_index) % 32;
+                let col = (x as usize + pixel_index) % 64;
+                let bit = byte & (1 << (7 - pixel_index)) != 0;
+                collision |= self.pixels[row][col] && bit;
+                self.pixels[row][col] ^= bit;
+            }
+        }
+        collision
+    }
+}
+
+/// Sprite positions that cover aligned, unaligned and wrapping draws.
+fn positions() -> Vec<(u8, u8)> {
+    (0..SPRITES_PER_FRAME)
+        .map(|index| ((index * 13 % 64) as u8, (index * 7 % 32) as u8))
+        .collect()
+}
+
+fn time<F: FnMut() -> usize>(name: &str, mut run: F) -> Duration {
+    let start = Instant::now();
+    let collisions = black_box(run());
+    let elapsed = start.elapsed();
+    println!(
+        "{:<8} {:>10.2?} ({:.1} ns per sprite, {} collisions)",
+        name,
+        elapsed,
+        elapsed.as_nanos() as f64 / (FRAMES * SPRITES_PER_FRAME) as f64,
+        collisions
+    );
+    elapsed
+}
+
+fn main() {
+    let positions = positions();
+
+    let bool_time = time("bool", || {
+        let mut display = BoolDisplay {
+            pixels: [[false; 64]; 32],
+        };
+        let mut collisions = 0;
+        for _ in 0..FRAMES {
+            for &(x, y) in &positions {
+                collisions += display.draw(black_box(x), black_box(y), &SPRITE) as usize;
+            }
+        }
+        collisions
+    });
+
+    let packed_time = time("packed", || {
+        let mut framebuffer = Framebuffer::new(64, 32);
+        let mut collisions = 0;
+        for _ in 0..FRAMES {
+            for &(x, y) in &positions {
+                collisions +=
+                    framebuffer.draw_sprite(black_box(x) as usize, black_box(y) as usize, &SPRITE)
+                        as usize;
+            }
+        }
+        collisions
+    });
+
+    println!(
+        "speedup  {:.1}x",
+        bool_time.as_secs_f64() / packed_time.as_secs_f64()
+    );
+}
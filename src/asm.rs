@@ -0,0 +1,340 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+};
+
+/// An error in the assembly source, with the (1-based) line it was found on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    V(u16),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Value(u16),
+}
+
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+/// Assembles `source`, written in the syntax produced by the disassembler, into a program that
+/// will be loaded at `start`. Besides the instructions, the source may contain `label:`
+/// definitions, `DB` and `DW` data directives and comments starting with `;`. Numbers may be
+/// written in decimal, or in hexadecimal or binary with a `0x` or `0b` prefix.
+pub fn assemble(source: &str, start: u16) -> Result<Vec<u8>, AsmError> {
+    // First pass: find every statement's address so that labels can be used before they're
+    // defined.
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = start as usize;
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let mut code = line.split(';').next().unwrap().trim();
+
+        while let Some(colon) = code.find(':') {
+            let label = code[..colon].trim();
+            if !is_identifier(label) {
+                return Err(error(
+                    line_number,
+                    format!("'{}' is not a valid label", label),
+                ));
+            }
+            if labels
+                .insert(label.to_ascii_uppercase(), address as u16)
+                .is_some()
+            {
+                return Err(error(
+                    line_number,
+                    format!("the label '{}' is already defined", label),
+                ));
+            }
+            code = code[colon + 1..].trim();
+        }
+        if code.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match code.find(char::is_whitespace) {
+            Some(space) => (&code[..space], code[space..].trim()),
+            None => (code, ""),
+        };
+        let operands: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        address += match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => 2 * operands.len(),
+            _ => 2,
+        };
+        statements.push(Statement {
+            line: line_number,
+            mnemonic,
+            operands,
+        });
+    }
+
+    // Second pass: encode everything now that all of the labels are known.
+    let mut program = Vec::new();
+    for statement in &statements {
+        let operands = statement
+            .operands
+            .iter()
+            .map(|operand| parse_operand(operand, &labels))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|message| error(statement.line, message))?;
+
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for operand in &operands {
+                    program.push(
+                        value(*operand, 0xFF).map_err(|message| error(statement.line, message))?
+                            as u8,
+                    );
+                }
+            }
+            "DW" => {
+                for operand in &operands {
+                    let word = value(*operand, 0xFFFF)
+                        .map_err(|message| error(statement.line, message))?;
+                    program.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            mnemonic => {
+                let opcode = encode(mnemonic, &operands)
+                    .map_err(|message| error(statement.line, message))?;
+                program.extend_from_slice(&opcode.to_be_bytes());
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses a hexadecimal number, optionally prefixed with `0x` or `0X`.
+pub fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+pub(crate) fn parse_number(text: &str) -> Option<u16> {
+    let lower = text.to_ascii_lowercase();
+    if lower.starts_with("0x") {
+        parse_hex(text)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u16::from_str_radix(&binary.replace('_', ""), 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn parse_operand(text: &str, labels: &HashMap<String, u16>) -> Result<Operand, String> {
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::BigFont,
+        "B" => Operand::Bcd,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            match u16::from_str_radix(&upper[1..], 16) {
+                Ok(register) => Operand::V(register),
+                Err(_) => return Err(format!("'{}' is not a register", text)),
+            }
+        }
+        _ => match parse_number(text) {
+            Some(number) => Operand::Value(number),
+            None => match labels.get(&upper) {
+                Some(&address) => Operand::Value(address),
+                None if is_identifier(text) => return Err(format!("undefined label '{}'", text)),
+                None => return Err(format!("'{}' is not a valid operand", text)),
+            },
+        },
+    };
+    Ok(operand)
+}
+
+fn value(operand: Operand, max: u16) -> Result<u16, String> {
+    match operand {
+        Operand::Value(number) if number <= max => Ok(number),
+        Operand::Value(number) => Err(format!("{:#X} doesn't fit in {:#X}", number, max)),
+        _ => Err(format!("expected a number but found {:?}", operand)),
+    }
+}
+
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<u16, String> {
+    use Operand::*;
+
+    let xy = |x: u16, y: u16| (x << 8) | (y << 4);
+    let opcode = match (mnemonic, operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [a]) => value(*a, 0xFFF)?,
+        ("JP", [V(0), a]) => 0xB000 | value(*a, 0xFFF)?,
+        ("JP", [a]) => 0x1000 | value(*a, 0xFFF)?,
+        ("CALL", [a]) => 0x2000 | value(*a, 0xFFF)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | xy(*x, *y),
+        ("SE", [V(x), b]) => 0x3000 | (x << 8) | value(*b, 0xFF)?,
+        ("SNE", [V(x), V(y)]) => 0x9000 | xy(*x, *y),
+        ("SNE", [V(x), b]) => 0x4000 | (x << 8) | value(*b, 0xFF)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | xy(*x, *y),
+        ("LD", [V(x), DelayTimer]) => 0xF007 | (x << 8),
+        ("LD", [V(x), Key]) => 0xF00A | (x << 8),
+        ("LD", [V(x), IndirectI]) => 0xF065 | (x << 8),
+        ("LD", [V(x), b]) => 0x6000 | (x << 8) | value(*b, 0xFF)?,
+        ("LD", [I, a]) => 0xA000 | value(*a, 0xFFF)?,
+        ("LD", [DelayTimer, V(x)]) => 0xF015 | (x << 8),
+        ("LD", [SoundTimer, V(x)]) => 0xF018 | (x << 8),
+        ("LD", [Font, V(x)]) => 0xF029 | (x << 8),
+        ("LD", [BigFont, V(x)]) => 0xF030 | (x << 8),
+        ("LD", [Bcd, V(x)]) => 0xF033 | (x << 8),
+        ("LD", [IndirectI, V(x)]) => 0xF055 | (x << 8),
+        ("ADD", [I, V(x)]) => 0xF01E | (x << 8),
+        ("ADD", [V(x), V(y)]) => 0x8004 | xy(*x, *y),
+        ("ADD", [V(x), b]) => 0x7000 | (x << 8) | value(*b, 0xFF)?,
+        ("OR", [V(x), V(y)]) => 0x8001 | xy(*x, *y),
+        ("AND", [V(x), V(y)]) => 0x8002 | xy(*x, *y),
+        ("XOR", [V(x), V(y)]) => 0x8003 | xy(*x, *y),
+        ("SUB", [V(x), V(y)]) => 0x8005 | xy(*x, *y),
+        ("SHR", [V(x)]) => 0x8006 | xy(*x, 0),
+        ("SHR", [V(x), V(y)]) => 0x8006 | xy(*x, *y),
+        ("SUBN", [V(x), V(y)]) => 0x8007 | xy(*x, *y),
+        ("SHL", [V(x)]) => 0x800E | xy(*x, 0),
+        ("SHL", [V(x), V(y)]) => 0x800E | xy(*x, *y),
+        ("RND", [V(x), b]) => 0xC000 | (x << 8) | value(*b, 0xFF)?,
+        ("DRW", [V(x), V(y), n]) => 0xD000 | xy(*x, *y) | value(*n, 0xF)?,
+        ("SKP", [V(x)]) => 0xE09E | (x << 8),
+        ("SKNP", [V(x)]) => 0xE0A1 | (x << 8),
+        _ => {
+            return Err(format!(
+                "'{}' doesn't take the operands {:?}",
+                mnemonic, operands
+            ))
+        }
+    };
+    Ok(opcode)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        assemble,
+        parse_hex,
+        parse_number,
+        AsmError,
+    };
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn instructions() {
+        let program =
+            assemble("CLS\nLD V3, 0x2A\nDRW V0, V1, 5\nLD [I], VF\nSHR V4", 0x200).unwrap();
+
+        assert_eq!(
+            program,
+            vec![0x00, 0xE0, 0x63, 0x2A, 0xD0, 0x15, 0xFF, 0x55, 0x84, 0x06]
+        );
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+            start:  LD I, sprite    ; labels can be used before they are defined
+                    JP start
+            sprite: DB 0b1111_0000, 144
+                    DW 0xBEEF
+        ";
+
+        let program = assemble(source, 0x200).unwrap();
+
+        assert_eq!(
+            program,
+            vec![0xA2, 0x04, 0x12, 0x00, 0xF0, 0x90, 0xBE, 0xEF]
+        );
+    }
+
+    #[test]
+    fn round_trips_through_the_disassembler() {
+        for opcode in (0x0000..=0xFFFF).step_by(7) {
+            let text = disassemble(opcode);
+
+            assert_eq!(
+                assemble(&text, 0x200),
+                Ok(opcode.to_be_bytes().to_vec()),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("0x2A"), Some(0x2A));
+        assert_eq!(parse_number("0X2A"), Some(0x2A));
+        assert_eq!(parse_number("0b1010"), Some(0b1010));
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_hex("300"), Some(0x300));
+        assert_eq!(parse_hex("0X300"), Some(0x300));
+        assert_eq!(parse_hex("0x0x300"), None);
+        assert_eq!(parse_hex("+300"), None);
+        assert_eq!(parse_hex("0x"), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            assemble("CLS\nJP nowhere", 0x200),
+            Err(AsmError {
+                line: 2,
+                message: String::from("undefined label 'nowhere'")
+            })
+        );
+        assert_eq!(assemble("LD V0, 0x100", 0x200).unwrap_err().line, 1);
+        assert_eq!(assemble("DRW V0, V1", 0x200).unwrap_err().line, 1);
+        assert_eq!(assemble("a:\na:", 0x200).unwrap_err().line, 2);
+    }
+}
@@ -0,0 +1,418 @@
+/// What the CPU is connected to: memory and the I/O lines of the machine around it.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Receives the byte written by `OUT port`, for ports 1 to 7.
+    fn output(&mut self, _port: u8, _value: u8) {}
+
+    /// Provides the byte read by `INP port`, for ports 1 to 7.
+    fn input(&mut self, _port: u8) -> u8 {
+        0
+    }
+
+    /// The state of external flag line EF1 to EF4, tested by `B1` to `B4` and `BN1` to `BN4`.
+    fn flag(&mut self, _line: u8) -> bool {
+        false
+    }
+}
+
+/// An RCA CDP1802 COSMAC CPU: sixteen 16-bit registers, any of which can be the program counter
+/// or the index register, an 8-bit accumulator and a carry flag.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cdp1802 {
+    /// The scratchpad registers R0 to RF.
+    pub r: [u16; 16],
+    /// Which register is the program counter.
+    pub p: u8,
+    /// Which register is the index register.
+    pub x: u8,
+    /// The accumulator.
+    pub d: u8,
+    /// The carry flag, which is set when there's no borrow after a subtraction.
+    pub df: bool,
+    /// Where `MARK` and interrupts save X and P.
+    pub t: u8,
+    /// Whether interrupts are enabled.
+    pub ie: bool,
+    /// The Q output line.
+    pub q: bool,
+    /// Whether the CPU is idling after `IDL`, until an interrupt or DMA request.
+    pub idle: bool,
+}
+
+impl Cdp1802 {
+    /// A CPU as it is after a reset: everything cleared, with R0 as the program counter and
+    /// interrupts enabled.
+    pub fn new() -> Self {
+        Cdp1802 {
+            ie: true,
+            ..Cdp1802::default()
+        }
+    }
+
+    /// Executes a single instruction, returning how many machine cycles it took: 2, or 3 for the
+    /// long branches and skips.
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> u64 {
+        let opcode = self.fetch(bus);
+        let n = (opcode & 0xF) as usize;
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            // LDN
+            0x0 => self.d = bus.read(self.r[n]),
+            // INC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            // DEC
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let taken = self.condition(bus, n);
+                self.short_branch(bus, taken);
+            }
+            // LDA
+            0x4 => {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            // STR
+            0x5 => bus.write(self.r[n], self.d),
+            0x6 => self.input_output(bus, n as u8),
+            0x7 => self.control(bus, opcode),
+            // GLO
+            0x8 => self.d = self.r[n] as u8,
+            // GHI
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            // PLO
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,
+            // PHI
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,
+            0xC => {
+                self.long_branch(bus, opcode);
+                return 3;
+            }
+            // SEP
+            0xD => self.p = n as u8,
+            // SEX
+            0xE => self.x = n as u8,
+            _ => self.alu(bus, opcode),
+        }
+        2
+    }
+
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let p = self.p as usize;
+        let byte = bus.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        byte
+    }
+
+    /// Whether the condition of short branch `3n` holds. Its complement is `3n | 8`.
+    fn condition<B: Bus>(&mut self, bus: &mut B, n: usize) -> bool {
+        let holds = match n & 0x7 {
+            0 => true,
+            1 => self.q,
+            2 => self.d == 0,
+            3 => self.df,
+            line => bus.flag(line as u8 - 3),
+        };
+        holds != (n & 0x8 != 0)
+    }
+
+    /// Replaces the low byte of the program counter with the byte after the branch if `taken`, and
+    /// otherwise skips over it.
+    fn short_branch<B: Bus>(&mut self, bus: &mut B, taken: bool) {
+        let p = self.p as usize;
+        if taken {
+            let low = bus.read(self.r[p]);
+            self.r[p] = self.r[p] & 0xFF00 | low as u16;
+        } else {
+            self.r[p] = self.r[p].wrapping_add(1);
+        }
+    }
+
+    /// The long branches and skips, `C0` to `CF`.
+    fn long_branch<B: Bus>(&mut self, bus: &mut B, opcode: u8) {
+        let p = self.p as usize;
+        let (is_skip, holds) = match opcode {
+            // NOP
+            0xC4 => return,
+            0xC5 => (true, !self.q),
+            0xC6 => (true, self.d != 0),
+            0xC7 => (true, !self.df),
+            0xCC => (true, self.ie),
+            0xCD => (true, self.q),
+            0xCE => (true, self.d == 0),
+            0xCF => (true, self.df),
+            _ => (false, self.condition(bus, (opcode & 0xF) as usize)),
+        };
+        if is_skip {
+            if holds {
+                self.r[p] = self.r[p].wrapping_add(2);
+            }
+        } else if holds {
+            let high = bus.read(self.r[p]);
+            let low = bus.read(self.r[p].wrapping_add(1));
+            self.r[p] = u16::from_be_bytes([high, low]);
+        } else {
+            self.r[p] = self.r[p].wrapping_add(2);
+        }
+    }
+
+    /// `IRX`, `OUT 1` to `OUT 7` and `INP 1` to `INP 7`.
+    fn input_output<B: Bus>(&mut self, bus: &mut B, n: u8) {
+        let x = self.x as usize;
+        match n {
+            0x0 => self.r[x] = self.r[x].wrapping_add(1),
+            0x1..=0x7 => {
+                let value = bus.read(self.r[x]);
+                bus.output(n, value);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            // 68 isn't an instruction on the 1802.
+            0x8 => {}
+            _ => {
+                let value = bus.input(n - 8);
+                bus.write(self.r[x], value);
+                self.d = value;
+            }
+        }
+    }
+
+    /// The miscellaneous instructions, `70` to `7F`.
+    fn control<B: Bus>(&mut self, bus: &mut B, opcode: u8) {
+        let x = self.x as usize;
+        match opcode {
+            // RET, DIS
+            0x70 | 0x71 => {
+                let value = bus.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                self.x = value >> 4;
+                self.p = value & 0xF;
+                self.ie = opcode == 0x70;
+            }
+            // LDXA
+            0x72 => {
+                self.d = bus.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+            }
+            // STXD
+            0x73 => {
+                bus.write(self.r[x], self.d);
+                self.r[x] = self.r[x].wrapping_sub(1);
+            }
+            // SHRC
+            0x76 => {
+                let carry = self.d & 1 != 0;
+                self.d = self.d >> 1 | (self.df as u8) << 7;
+                self.df = carry;
+            }
+            // SAV
+            0x78 => bus.write(self.r[x], self.t),
+            // MARK
+            0x79 => {
+                self.t = self.x << 4 | self.p;
+                bus.write(self.r[2], self.t);
+                self.x = self.p;
+                self.r[2] = self.r[2].wrapping_sub(1);
+            }
+            // REQ, SEQ
+            0x7A => self.q = false,
+            0x7B => self.q = true,
+            // SHLC
+            0x7E => {
+                let carry = self.d & 0x80 != 0;
+                self.d = self.d << 1 | self.df as u8;
+                self.df = carry;
+            }
+            // ADC, SDB, SMB and their immediate forms
+            _ => {
+                let operand = if opcode & 0x8 != 0 {
+                    self.fetch(bus)
+                } else {
+                    bus.read(self.r[x])
+                };
+                self.arithmetic(opcode & 0x3, operand, self.df);
+            }
+        }
+    }
+
+    /// The logic and arithmetic instructions, `F0` to `FF`.
+    fn alu<B: Bus>(&mut self, bus: &mut B, opcode: u8) {
+        match opcode {
+            // SHR
+            0xF6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            // SHL
+            0xFE => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            _ => {
+                let operand = if opcode & 0x8 != 0 {
+                    self.fetch(bus)
+                } else {
+                    bus.read(self.r[self.x as usize])
+                };
+                match opcode & 0x7 {
+                    // LDX, LDI
+                    0x0 => self.d = operand,
+                    // OR, ORI
+                    0x1 => self.d |= operand,
+                    // AND, ANI
+                    0x2 => self.d &= operand,
+                    // XOR, XRI
+                    0x3 => self.d ^= operand,
+                    // ADD, SD, SM and their immediate forms
+                    kind => self.arithmetic(kind & 0x3, operand, kind != 0x4),
+                }
+            }
+        }
+    }
+
+    /// Adds `operand` to D if `kind` is 0, subtracts D from it if 1, and subtracts it from D if 3,
+    /// with `carry` as the incoming carry, or as the inverted borrow for subtractions.
+    fn arithmetic(&mut self, kind: u8, operand: u8, carry: bool) {
+        let (a, b) = match kind {
+            0x0 => (self.d as u16, operand as u16),
+            0x1 => (operand as u16, !self.d as u16),
+            _ => (self.d as u16, !operand as u16),
+        };
+        let sum = a + (b & 0xFF) + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        Bus,
+        Cdp1802,
+    };
+
+    struct Memory {
+        bytes: Vec<u8>,
+        outputs: Vec<(u8, u8)>,
+    }
+
+    impl Bus for Memory {
+        fn read(&mut self, address: u16) -> u8 {
+            self.bytes[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.bytes[address as usize] = value;
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs.push((port, value));
+        }
+
+        fn flag(&mut self, line: u8) -> bool {
+            line == 3
+        }
+    }
+
+    /// Runs `program` from address 0 until it branches to itself, with R2 pointing at 0xFF.
+    fn run(program: &[u8]) -> (Cdp1802, Memory) {
+        let mut memory = Memory {
+            bytes: vec![0; 0x100],
+            outputs: Vec::new(),
+        };
+        memory.bytes[..program.len()].copy_from_slice(program);
+        let mut cpu = Cdp1802::new();
+        cpu.r[2] = 0xFF;
+        for _ in 0..1000 {
+            let pc = cpu.r[cpu.p as usize];
+            cpu.step(&mut memory);
+            if cpu.r[cpu.p as usize] == pc {
+                break;
+            }
+        }
+        (cpu, memory)
+    }
+
+    #[test]
+    fn registers() {
+        // LDI 0x12; PHI R5; LDI 0x34; PLO R5; INC R5; GLO R5; DEC R6; BR 0x0B
+        let (cpu, _) = run(&[
+            0xF8, 0x12, 0xB5, 0xF8, 0x34, 0xA5, 0x15, 0x85, 0x26, 0x30, 0x09,
+        ]);
+
+        assert_eq!(cpu.r[5], 0x1235);
+        assert_eq!(cpu.d, 0x35);
+        assert_eq!(cpu.r[6], 0xFFFF);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let d_and_df = |program: &[u8]| {
+            let mut program = program.to_vec();
+            let end = program.len() as u8;
+            program.extend_from_slice(&[0x30, end]);
+            let (cpu, _) = run(&program);
+            (cpu.d, cpu.df)
+        };
+
+        // LDI 0xF0; ADI 0x20
+        assert_eq!(d_and_df(&[0xF8, 0xF0, 0xFC, 0x20]), (0x10, true));
+        // LDI 0x10; SMI 0x20
+        assert_eq!(d_and_df(&[0xF8, 0x10, 0xFF, 0x20]), (0xF0, false));
+        // LDI 0x30; SMI 0x20
+        assert_eq!(d_and_df(&[0xF8, 0x30, 0xFF, 0x20]), (0x10, true));
+        // LDI 0x10; SDI 0x30
+        assert_eq!(d_and_df(&[0xF8, 0x10, 0xFD, 0x30]), (0x20, true));
+        // LDI 0xFF; ADI 0x01; ADCI 0x00
+        assert_eq!(
+            d_and_df(&[0xF8, 0xFF, 0xFC, 0x01, 0x7C, 0x00]),
+            (0x01, false)
+        );
+        // LDI 0x81; SHR; SHLC
+        assert_eq!(d_and_df(&[0xF8, 0x81, 0xF6, 0x7E]), (0x81, false));
+        // LDI 0xCC; ANI 0x0F; XRI 0xFF
+        assert_eq!(
+            d_and_df(&[0xF8, 0xCC, 0xFA, 0x0F, 0xFB, 0xFF]),
+            (0xF3, false)
+        );
+    }
+
+    #[test]
+    fn branches() {
+        // LDI 0; BZ 0x05; LDI 1; LBNZ 0x0009; B3 0x0C; LDI 2; SEQ; LSQ; LDI 3; BR 0x10
+        let (cpu, _) = run(&[
+            0xF8, 0x00, 0x32, 0x06, 0xF8, 0x01, 0xCA, 0x00, 0x09, 0x36, 0x0C, 0xF8, 0x7B, 0xCD,
+            0xF8, 0x03, 0x30, 0x10,
+        ]);
+
+        assert_eq!(cpu.d, 0x00);
+        assert!(cpu.q);
+    }
+
+    #[test]
+    fn subroutines() {
+        // 0x00: LDI 0x08; PLO R3; SEX R2; MARK; SEP R3; ...
+        // 0x08: SEQ; SEX R2; INC R2; RET
+        let mut program = vec![0xF8, 0x08, 0xA3, 0xE2, 0x79, 0xD3, 0x30, 0x06];
+        program.extend_from_slice(&[0x7B, 0xE2, 0x12, 0x70]);
+        let (cpu, memory) = run(&program);
+
+        assert!(cpu.q);
+        assert_eq!(cpu.p, 0);
+        assert_eq!(cpu.x, 2);
+        assert_eq!(cpu.r[0], 0x06);
+        assert_eq!(cpu.r[2], 0x100);
+        assert_eq!(memory.bytes[0xFF], 0x20);
+    }
+
+    #[test]
+    fn input_output() {
+        // SEX R5; LDI 0xAB; STR R5; OUT 2; BR 0x06
+        let (cpu, memory) = run(&[0xE5, 0xF8, 0xAB, 0x55, 0x62, 0x30, 0x05]);
+
+        assert_eq!(memory.outputs, vec![(2, 0xAB)]);
+        assert_eq!(cpu.r[5], 1);
+    }
+}
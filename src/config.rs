@@ -0,0 +1,229 @@
+use serde::Deserialize;
+use sha1::Sha1;
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    env,
+    error::Error,
+    fmt,
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+/// The knobs that can be set in the configuration file, either for every ROM or for a single one.
+/// Anything left out falls back to the next level down: a ROM's section falls back to the global
+/// settings, which fall back to the ROM database's recommendations and then the built-in defaults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub platform: Option<String>,
+    /// The CHIP-8 variant the program was written for, like `chip8e` or `hires`.
+    pub variant: Option<String>,
+    /// A quirk preset name.
+    pub quirks: Option<String>,
+    /// Instructions executed per second.
+    pub speed: Option<u64>,
+    /// Window pixels per display pixel.
+    pub scale: Option<usize>,
+    /// A palette name, or a comma-separated list of hex colours.
+    pub palette: Option<String>,
+    /// A font name, or the path of a font file.
+    pub font: Option<String>,
+    /// Keypad keys and the names of the keyboard keys that press them, like `5 = "Space"`.
+    pub keymap: Option<BTreeMap<String, String>>,
+}
+
+impl Settings {
+    /// These settings, with anything they leave out taken from `fallback`.
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            platform: self.platform.or(fallback.platform),
+            variant: self.variant.or(fallback.variant),
+            quirks: self.quirks.or(fallback.quirks),
+            speed: self.speed.or(fallback.speed),
+            scale: self.scale.or(fallback.scale),
+            palette: self.palette.or(fallback.palette),
+            font: self.font.or(fallback.font),
+            keymap: self.keymap.or(fallback.keymap),
+        }
+    }
+}
+
+/// The configuration file: global settings at the top level, followed by a `[rom.<sha1>]` section
+/// for every ROM that needs something different, keyed by the SHA-1 hash of the ROM's contents.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub global: Settings,
+    pub rom: HashMap<String, Settings>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "unable to read '{}': {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Self, toml::de::Error> {
+        // The ROM sections are split off by hand because serde can't reject misspelt keys in the
+        // global settings if they share a table with them.
+        let mut table: toml::value::Table = toml::from_str(source)?;
+        let rom: HashMap<String, Settings> = match table.remove("rom") {
+            Some(rom) => rom.try_into()?,
+            None => HashMap::new(),
+        };
+        Ok(Config {
+            global: toml::Value::Table(table).try_into()?,
+            // Hashes are looked up in lowercase, whatever case they were written in.
+            rom: rom
+                .into_iter()
+                .map(|(hash, settings)| (hash.to_ascii_lowercase(), settings))
+                .collect(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let source =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        Config::parse(&source).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    /// Loads the configuration file from its default location, if there is one there.
+    pub fn load_default() -> Result<Self, ConfigError> {
+        match default_path() {
+            Some(path) if path.exists() => Config::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// The settings for `rom`: its own section, if it has one, on top of the global settings, on
+    /// top of the `recommended` settings from the ROM database. Anything the user has set wins over
+    /// the database.
+    pub fn settings_for(&self, rom: &[u8], recommended: Option<&Settings>) -> Settings {
+        let mut settings = self.global.clone();
+        if let Some(recommended) = recommended {
+            settings = settings.or(recommended.clone());
+        }
+        if let Some(section) = self.rom.get(&rom_hash(rom)) {
+            settings = section.clone().or(settings);
+        }
+        settings
+    }
+}
+
+/// `$XDG_CONFIG_HOME/chip8/config.toml`, or `~/.config/chip8/config.toml` when that isn't set.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("chip8").join("config.toml"))
+}
+
+/// The lowercase hex SHA-1 hash of `rom`, which identifies it in the configuration file.
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::from(rom).digest().to_string()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        rom_hash,
+        Config,
+        Settings,
+    };
+
+    #[test]
+    fn hash() {
+        assert_eq!(rom_hash(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn per_rom_settings() {
+        let config = Config::parse(
+            r#"
+            palette = "amber"
+            speed = 600
+            scale = 10
+
+            [keymap]
+            5 = "Space"
+
+            [rom.A9993E364706816ABA3E25717850C26C9CD0D89D]
+            quirks = "vip"
+            speed = 1000
+            "#,
+        )
+        .unwrap();
+
+        let settings = config.settings_for(b"abc", None);
+        assert_eq!(settings.quirks.as_deref(), Some("vip"));
+        assert_eq!(settings.speed, Some(1000));
+        assert_eq!(settings.palette.as_deref(), Some("amber"));
+        assert_eq!(settings.keymap.unwrap()["5"], "Space");
+
+        let settings = config.settings_for(b"another rom", None);
+        assert_eq!(settings.quirks, None);
+        assert_eq!(settings.speed, Some(600));
+        assert_eq!(settings.scale, Some(10));
+
+        let recommended = Settings {
+            quirks: Some(String::from("schip")),
+            speed: Some(2000),
+            palette: Some(String::from("lcd")),
+            ..Settings::default()
+        };
+        let settings = config.settings_for(b"abc", Some(&recommended));
+        assert_eq!(settings.quirks.as_deref(), Some("vip"));
+        assert_eq!(settings.speed, Some(1000));
+        assert_eq!(settings.palette.as_deref(), Some("amber"));
+
+        // The database only fills in what the user hasn't set.
+        let settings = config.settings_for(b"another rom", Some(&recommended));
+        assert_eq!(settings.quirks.as_deref(), Some("schip"));
+        assert_eq!(settings.speed, Some(600));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Config::parse("speed = \"fast\"").is_err());
+        assert!(Config::parse("spede = 10").is_err());
+        assert!(Config::parse("[rom.abc]\nspede = 10").is_err());
+    }
+
+    #[test]
+    fn or() {
+        let settings = Settings {
+            speed: Some(1000),
+            ..Settings::default()
+        };
+        let fallback = Settings {
+            speed: Some(600),
+            scale: Some(10),
+            ..Settings::default()
+        };
+
+        let merged = settings.or(fallback);
+
+        assert_eq!(merged.speed, Some(1000));
+        assert_eq!(merged.scale, Some(10));
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt::Write;
+
+use crate::{
+    disasm::disassemble,
+    emulator::{
+        AccessKind,
+        Emulator,
+        Step,
+    },
+};
+
+// Flags for how a byte of memory was used
+
+const READ: u8 = 0b01;
+const WRITTEN: u8 = 0b10;
+
+/// Records which bytes of memory were executed as code, read as data or written during a run.
+pub struct Coverage {
+    /// How many times the instruction starting at every address was executed.
+    executions: Vec<u64>,
+    /// Whether every byte was fetched as part of an instruction, even if it didn't start one.
+    code: Vec<bool>,
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    /// Starts recording the coverage of `emulator`'s memory, turning on its memory tracking so
+    /// that `record` can see what every instruction accessed.
+    pub fn new(emulator: &mut Emulator) -> Self {
+        emulator.set_memory_tracking(true);
+        let size = emulator.memory().len();
+        Coverage {
+            executions: vec![0; size],
+            code: vec![false; size],
+            flags: vec![0; size],
+        }
+    }
+
+    /// Records `step`, which has just been executed by `emulator`.
+    pub fn record(&mut self, step: Step, emulator: &Emulator) {
+        if let Some(executions) = self.executions.get_mut(step.address as usize) {
+            *executions += 1;
+        }
+        for access in emulator.memory_accesses() {
+            let start = access.address as usize;
+            let end = (start + access.length as usize).min(self.flags.len());
+            for address in start.min(end)..end {
+                match access.kind {
+                    AccessKind::Fetch => self.code[address] = true,
+                    AccessKind::Read => self.flags[address] |= READ,
+                    AccessKind::Write => self.flags[address] |= WRITTEN,
+                }
+            }
+        }
+    }
+
+    pub fn executions_at(&self, address: u16) -> u64 {
+        self.executions.get(address as usize).copied().unwrap_or(0)
+    }
+
+    pub fn is_code(&self, address: u16) -> bool {
+        self.code.get(address as usize).copied().unwrap_or(false)
+    }
+
+    pub fn is_read(&self, address: u16) -> bool {
+        self.flag(address, READ)
+    }
+
+    pub fn is_written(&self, address: u16) -> bool {
+        self.flag(address, WRITTEN)
+    }
+
+    fn flag(&self, address: u16, flag: u8) -> bool {
+        matches!(self.flags.get(address as usize), Some(flags) if flags & flag != 0)
+    }
+
+    /// How `address` was used, as `X` for executed, `R` for read and `W` for written, with `-` in
+    /// place of anything it wasn't used for.
+    fn marks(&self, address: u16) -> String {
+        let mark = |used: bool, mark: char| if used { mark } else { '-' };
+        [
+            mark(self.is_code(address), 'X'),
+            mark(self.is_read(address), 'R'),
+            mark(self.is_written(address), 'W'),
+        ]
+        .iter()
+        .collect()
+    }
+
+    /// Disassembles `memory` from `start` to `end`, marking how every address was used. The
+    /// instructions that were executed are disassembled as such, and everything else one byte at
+    /// a time, with the bytes that were read as data drawn like sprites:
+    ///
+    /// ```text
+    /// X--       12  0x200: 6A02  LD VA, 0x02
+    /// -R-        0  0x2EA: 80    DB 0x80  #.......
+    /// ```
+    pub fn annotated_disassembly(&self, memory: &[u8], start: u16, end: u16) -> String {
+        let mut text = String::new();
+        let mut address = start as usize;
+        let end = (end as usize).min(memory.len());
+        while address < end {
+            let executions = self.executions[address];
+            if executions > 0 && address + 1 < end {
+                let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+                writeln!(
+                    text,
+                    "{} {:>10}  {:#05X}: {:04X}  {}",
+                    self.marks(address as u16),
+                    executions,
+                    address,
+                    opcode,
+                    disassemble(opcode)
+                )
+                .unwrap();
+                address += 2;
+                continue;
+            }
+
+            let byte = memory[address];
+            let sprite: String = if self.is_read(address as u16) {
+                let pixels: String = (0..8)
+                    .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                    .collect();
+                format!("  {}", pixels)
+            } else {
+                String::new()
+            };
+            writeln!(
+                text,
+                "{} {:>10}  {:#05X}: {:02X}    DB {:#04X}{}",
+                self.marks(address as u16),
+                0,
+                address,
+                byte,
+                byte,
+                sprite
+            )
+            .unwrap();
+            address += 1;
+        }
+        text
+    }
+
+    /// An LCOV tracefile for the program in `source`, loaded from `start` to `end`. Its lines are
+    /// the lines of `chip8 disasm`, which disassembles two bytes to a line, and only lines that
+    /// weren't just read as data count as code.
+    pub fn lcov(&self, source: &str, start: u16, end: u16) -> String {
+        let mut text = format!("TN:\nSF:{}\n", source);
+        let (mut found, mut hit) = (0, 0);
+        let end = (end as usize).min(self.executions.len());
+        for (line, address) in (start as usize..end).step_by(2).enumerate() {
+            let executions = self.executions[address..(address + 2).min(end)]
+                .iter()
+                .max()
+                .copied()
+                .unwrap_or(0);
+            let is_data = self.flags[address] & READ != 0 && executions == 0;
+            if is_data {
+                continue;
+            }
+            found += 1;
+            if executions > 0 {
+                hit += 1;
+            }
+            writeln!(text, "DA:{},{}", line + 1, executions).unwrap();
+        }
+        write!(text, "LF:{}\nLH:{}\nend_of_record\n", found, hit).unwrap();
+        text
+    }
+
+    /// A one line summary of the coverage from `start` to `end`.
+    pub fn summary(&self, start: u16, end: u16) -> String {
+        let end = (end as usize).min(self.executions.len());
+        let range = start as usize..end;
+        let count = |used: &dyn Fn(u16) -> bool| {
+            range
+                .clone()
+                .filter(|&address| used(address as u16))
+                .count()
+        };
+        format!(
+            "{} of {} bytes executed, {} read as data, {} written",
+            count(&|address| self.is_code(address)),
+            range.len(),
+            count(&|address| self.is_read(address)),
+            count(&|address| self.is_written(address))
+        )
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+    use crate::{
+        emulator::Emulator,
+        platform::Platform,
+    };
+
+    // 0x200: LD I, 0x20E
+    // 0x202: DRW V0, V0, 2
+    // 0x204: LD I, 0x300
+    // 0x206: LD B, V0
+    // 0x208: JP 0x208
+    // 0x20A: DW 0x1234
+    // 0x20C: DW 0x5678
+    // 0x20E: DB 0xF0, 0x90
+    const PROGRAM: [u8; 16] = [
+        0xA2, 0x0E, 0xD0, 0x02, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x08, 0x12, 0x34, 0x56, 0x78, 0xF0,
+        0x90,
+    ];
+
+    fn run() -> (Emulator, Coverage) {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&PROGRAM).unwrap();
+        let mut coverage = Coverage::new(&mut emulator);
+        for _ in 0..6 {
+            let step = emulator.step().unwrap();
+            coverage.record(step, &emulator);
+        }
+        (emulator, coverage)
+    }
+
+    #[test]
+    fn record() {
+        let (_, coverage) = run();
+
+        assert_eq!(coverage.executions_at(0x208), 2);
+        assert!(coverage.is_code(0x209));
+        assert!(!coverage.is_code(0x20A));
+        assert!(coverage.is_read(0x20F));
+        assert!(!coverage.is_read(0x210));
+        assert!(coverage.is_written(0x302));
+        assert_eq!(coverage.marks(0x300), "--W");
+        assert_eq!(
+            coverage.summary(0x200, 0x210),
+            "10 of 16 bytes executed, 2 read as data, 0 written"
+        );
+    }
+
+    #[test]
+    fn summary_with_64k_of_memory() {
+        let mut emulator = Emulator::with_config(Platform::XoChip.config());
+        emulator.load_rom(&PROGRAM).unwrap();
+        let mut coverage = Coverage::new(&mut emulator);
+        for _ in 0..6 {
+            let step = emulator.step().unwrap();
+            coverage.record(step, &emulator);
+        }
+
+        assert_eq!(
+            coverage.summary(0x200, 0xFFFF),
+            "10 of 65023 bytes executed, 2 read as data, 3 written"
+        );
+    }
+
+    #[test]
+    fn annotated_disassembly() {
+        let (emulator, coverage) = run();
+
+        let text = coverage.annotated_disassembly(emulator.memory(), 0x206, 0x210);
+
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            vec![
+                "X--          1  0x206: F033  LD B, V0",
+                "X--          2  0x208: 1208  JP 0x208",
+                "---          0  0x20A: 12    DB 0x12",
+                "---          0  0x20B: 34    DB 0x34",
+                "---          0  0x20C: 56    DB 0x56",
+                "---          0  0x20D: 78    DB 0x78",
+                "-R-          0  0x20E: F0    DB 0xF0  ####....",
+                "-R-          0  0x20F: 90    DB 0x90  #..#....",
+            ]
+        );
+    }
+
+    #[test]
+    fn lcov() {
+        let (_, coverage) = run();
+
+        assert_eq!(
+            coverage.lcov("test.ch8", 0x200, 0x210),
+            "TN:\nSF:test.ch8\nDA:1,1\nDA:2,1\nDA:3,1\nDA:4,1\nDA:5,2\nDA:6,0\nDA:7,0\nLF:7\n\
+             LH:5\nend_of_record\n"
+        );
+    }
+}
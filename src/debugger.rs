@@ -0,0 +1,1175 @@
+use std::{
+    error::Error,
+    fmt,
+    fmt::Write as _,
+    io::{
+        self,
+        BufRead,
+        Write,
+    },
+    sync::mpsc::{
+        self,
+        Receiver,
+    },
+    thread,
+};
+
+use crate::{
+    asm::parse_number,
+    disasm::{
+        disassemble,
+        disassemble_program,
+    },
+    emulator::{
+        AccessKind,
+        Emulator,
+        Step,
+    },
+    expr::Expr,
+};
+
+pub const PROMPT: &str = "(chip8) ";
+
+const HELP: &str = "\
+step [N]          (s)   execute N instructions, 1 by default
+next              (n)   execute the next instruction, running subroutine calls to completion
+finish            (f)   run until the current subroutine returns
+continue          (c)   run until a breakpoint
+break ADDR        (b)   stop when the program counter reaches ADDR
+break if EXPR           stop before any instruction when EXPR holds
+watch ADDR [LEN]        stop after memory is written
+rwatch ADDR [LEN]       stop after memory is read
+awatch ADDR [LEN]       stop after memory is read or written
+watch EXPR              stop after the value of EXPR changes, like 'watch V3'
+catch PATTERN           stop before an opcode matching PATTERN, like 'catch Dxyn'
+delete [N]        (d)   remove breakpoint N, or all of them
+breakpoints             list the breakpoints
+Any breakpoint can be made conditional by ending it with 'if EXPR', like
+'break 0x204 if V3 == 0x10 && I > 0x300'. Expressions use C's operators on numbers,
+V0-VF, I, PC, SP, DT, ST and memory bytes written as [ADDR].
+regs              (r)   show the registers and timers
+mem ADDR [LEN]    (x)   show LEN bytes of memory, 64 by default
+disasm [ADDR] [N] (l)   disassemble N instructions, around the program counter by default
+stack             (bt)  show the call stack
+set REG VALUE           change V0-VF, I, PC, DT or ST
+write ADDR BYTE...      change memory
+reset [hard]            restart the program, reloading memory too if hard
+quit              (q)   exit the emulator
+An empty line repeats the last command. Numbers are decimal, or hexadecimal with a 0x prefix.";
+
+/// How many bytes `mem` shows when it isn't told.
+const DEFAULT_DUMP_LENGTH: usize = 64;
+/// How many instructions `disasm` shows when it isn't told.
+const DEFAULT_DISASM_COUNT: usize = 9;
+/// How many instructions the headless debugger runs for a single command before interrupting the
+/// program, which is about half an hour of a program's time at the usual speed.
+pub const REPL_STEP_LIMIT: u64 = 1_000_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandError(String);
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for CommandError {}
+
+fn error<T>(message: String) -> Result<T, CommandError> {
+    Err(CommandError(message))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Stopped,
+    Running(Until),
+    Quit,
+}
+
+/// When a running program should stop, besides at breakpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Until {
+    Breakpoint,
+    /// The call stack is shallower than `depth`.
+    Return {
+        depth: usize,
+    },
+    /// The program counter reaches `address` with `depth` subroutines on the call stack.
+    Address {
+        address: u16,
+        depth: usize,
+    },
+    /// `remaining` more instructions have been executed.
+    Steps {
+        remaining: usize,
+    },
+}
+
+/// What makes a breakpoint stop the program.
+enum Trigger {
+    /// The program counter reaches an address.
+    Address(u16),
+    /// Any instruction, so that only the breakpoint's condition matters.
+    Always,
+    /// Memory from `start` to `end` inclusive is read or written.
+    Memory {
+        start: u16,
+        end: u16,
+        reads: bool,
+        writes: bool,
+    },
+    /// The value of an expression, written as `source`, changes from `value` when it was last
+    /// evaluated.
+    Change {
+        source: String,
+        expr: Expr,
+        value: Option<i64>,
+    },
+    /// An opcode whose bits under `mask` are `bits` is about to be executed.
+    Opcode { mask: u16, bits: u16 },
+}
+
+struct Breakpoint {
+    id: usize,
+    trigger: Trigger,
+    condition: Option<Expr>,
+    /// The command that set the breakpoint, for listing it.
+    command: String,
+}
+
+impl Breakpoint {
+    fn kind(&self) -> &'static str {
+        match self.trigger {
+            Trigger::Address(_) | Trigger::Always => "Breakpoint",
+            Trigger::Memory { .. } | Trigger::Change { .. } => "Watchpoint",
+            Trigger::Opcode { .. } => "Catchpoint",
+        }
+    }
+
+    /// Whether the breakpoint's condition holds, or why it couldn't be evaluated.
+    fn condition_holds(&self, emulator: &Emulator) -> Result<bool, String> {
+        match &self.condition {
+            Some(condition) => condition
+                .holds(emulator)
+                .map_err(|err| format!("{} {}: {}", self.kind(), self.id, err)),
+            None => Ok(true),
+        }
+    }
+}
+
+/// The result of `Debugger::advance`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// The instruction that was executed, if one was.
+    pub step: Option<Step>,
+    /// Why the debugger stopped, if it did.
+    pub report: Option<String>,
+}
+
+/// A command-line debugger, driving an `Emulator` through its stepping API. Commands are given to
+/// `execute` and run to completion immediately, except for the ones that resume the program: those
+/// leave the debugger running, and the caller then executes the program with `advance` for as long
+/// as `is_running` says, alongside its own display and timers.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    state: State,
+    last_command: String,
+    /// Don't stop before the first instruction after resuming, which would otherwise be the
+    /// breakpoint the program is stopped at.
+    resuming: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    /// Creates a debugger with the program stopped before its first instruction.
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            state: State::Stopped,
+            last_command: String::new(),
+            resuming: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Running(_))
+    }
+
+    pub fn has_quit(&self) -> bool {
+        self.state == State::Quit
+    }
+
+    /// Executes the next instruction while the debugger is running, unless there's a reason to
+    /// stop before it.
+    pub fn advance(&mut self, emulator: &mut Emulator) -> Progress {
+        let until = match self.state {
+            State::Running(until) => until,
+            _ => {
+                return Progress {
+                    step: None,
+                    report: None,
+                }
+            }
+        };
+
+        if !self.resuming {
+            if let Some(reason) = self.stop_reason(emulator, until) {
+                return self.stop(emulator, None, reason);
+            }
+        }
+        self.resuming = false;
+
+        match emulator.step() {
+            Ok(step) => {
+                if let Some(reason) = self.watch_reason(emulator, step.address) {
+                    return self.stop(emulator, Some(step), reason);
+                }
+                if let State::Running(Until::Steps { remaining }) = &mut self.state {
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        return self.stop(emulator, Some(step), String::new());
+                    }
+                }
+                Progress {
+                    step: Some(step),
+                    report: None,
+                }
+            }
+            Err(err) => {
+                self.state = State::Stopped;
+                Progress {
+                    step: None,
+                    report: Some(format!("error: {}", err)),
+                }
+            }
+        }
+    }
+
+    /// Stops the running program where it is, as if it had reached a breakpoint, returning what to
+    /// report. Callers use it to let the user take back control of a program that doesn't stop.
+    pub fn interrupt(&mut self, emulator: &Emulator, reason: String) -> Option<String> {
+        if !self.is_running() {
+            return None;
+        }
+        self.stop(emulator, None, reason).report
+    }
+
+    fn stop(&mut self, emulator: &Emulator, step: Option<Step>, reason: String) -> Progress {
+        self.state = State::Stopped;
+        let report = if reason.is_empty() {
+            current_instruction(emulator)
+        } else {
+            format!("{}\n{}", reason, current_instruction(emulator))
+        };
+        Progress {
+            step,
+            report: Some(report),
+        }
+    }
+
+    /// Why the program should stop before the next instruction, if it should. Stopping where
+    /// `next` was headed needs no explanation, so its reason is empty.
+    fn stop_reason(&self, emulator: &Emulator, until: Until) -> Option<String> {
+        let address = emulator.program_counter();
+        let opcode = emulator.next_opcode();
+        for breakpoint in &self.breakpoints {
+            let reason = match breakpoint.trigger {
+                Trigger::Address(target) if target == address => {
+                    format!("Breakpoint {} at {:#05X}", breakpoint.id, address)
+                }
+                Trigger::Always => format!("Breakpoint {}", breakpoint.id),
+                Trigger::Opcode { mask, bits } if opcode & mask == bits => {
+                    format!("Catchpoint {}: {}", breakpoint.id, disassemble(opcode))
+                }
+                _ => continue,
+            };
+            match breakpoint.condition_holds(emulator) {
+                Ok(true) => return Some(reason),
+                Ok(false) => {}
+                Err(err) => return Some(err),
+            }
+        }
+
+        let depth = emulator.call_stack().len();
+        match until {
+            Until::Return { depth: target } if depth < target => {
+                Some(String::from("Returned from the subroutine"))
+            }
+            Until::Address {
+                address: target,
+                depth: target_depth,
+            } if address == target && depth == target_depth => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    /// Why the program should stop after the instruction at `address` was executed, if it should.
+    /// Every watched expression is re-evaluated, so that the next change is measured from now.
+    fn watch_reason(&mut self, emulator: &Emulator, address: u16) -> Option<String> {
+        let mut reasons = Vec::new();
+        for breakpoint in &mut self.breakpoints {
+            let reason = match &mut breakpoint.trigger {
+                Trigger::Memory {
+                    start,
+                    end,
+                    reads,
+                    writes,
+                } => emulator
+                    .memory_accesses()
+                    .iter()
+                    .find(|access| {
+                        let watched = match access.kind {
+                            AccessKind::Fetch => false,
+                            AccessKind::Read => *reads,
+                            AccessKind::Write => *writes,
+                        };
+                        watched && access.overlaps(*start, *end)
+                    })
+                    .map(|access| {
+                        format!(
+                            "Watchpoint {}: {} {:#05X}-{:#05X} by {:#05X}",
+                            breakpoint.id,
+                            match access.kind {
+                                AccessKind::Write => "write to",
+                                _ => "read of",
+                            },
+                            access.address,
+                            access.address as u32 + (access.length as u32).max(1) - 1,
+                            address
+                        )
+                    }),
+                Trigger::Change {
+                    source,
+                    expr,
+                    value,
+                } => {
+                    let new_value = expr.evaluate(emulator).ok();
+                    let old_value = std::mem::replace(value, new_value);
+                    if new_value != old_value {
+                        Some(format!(
+                            "Watchpoint {}: {} changed from {} to {}",
+                            breakpoint.id,
+                            source,
+                            show_value(old_value),
+                            show_value(new_value)
+                        ))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            if let Some(reason) = reason {
+                match breakpoint.condition_holds(emulator) {
+                    Ok(true) => reasons.push(reason),
+                    Ok(false) => {}
+                    Err(err) => reasons.push(err),
+                }
+            }
+        }
+        if reasons.is_empty() {
+            None
+        } else {
+            Some(reasons.join("\n"))
+        }
+    }
+
+    /// Runs a command, returning what it printed. An empty line repeats the previous command.
+    pub fn execute(&mut self, emulator: &mut Emulator, line: &str) -> Result<String, CommandError> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            self.last_command = line.trim().to_string();
+            self.last_command.clone()
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(String::new()),
+        };
+
+        match command {
+            "step" | "s" => {
+                let count = optional_number(args.first(), 1)?;
+                self.step(emulator, count)
+            }
+            "next" | "n" => {
+                let address = emulator.program_counter();
+                if emulator.next_opcode() & 0xF000 == 0x2000 {
+                    self.resume(
+                        emulator,
+                        Until::Address {
+                            address: address.wrapping_add(2),
+                            depth: emulator.call_stack().len(),
+                        },
+                    );
+                    Ok(String::new())
+                } else {
+                    self.step(emulator, 1)
+                }
+            }
+            "finish" | "f" => {
+                let depth = emulator.call_stack().len();
+                if depth == 0 {
+                    return error(String::from("not in a subroutine"));
+                }
+                self.resume(emulator, Until::Return { depth });
+                Ok(String::new())
+            }
+            "continue" | "c" => {
+                self.resume(emulator, Until::Breakpoint);
+                Ok(String::new())
+            }
+            "break" | "b" | "watch" | "rwatch" | "awatch" | "catch" => {
+                let id = self.add_breakpoint(emulator, command, args)?;
+                let breakpoint = self.breakpoints.last().unwrap();
+                Ok(format!(
+                    "{} {}: {}",
+                    breakpoint.kind(),
+                    id,
+                    breakpoint.command
+                ))
+            }
+            "delete" | "d" => match args.first() {
+                Some(id) => {
+                    let id = match id.parse::<usize>() {
+                        Ok(id) => id,
+                        Err(_) => return error(format!("'{}' is not a breakpoint number", id)),
+                    };
+                    let count = self.breakpoints.len();
+                    self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+                    if self.breakpoints.len() == count {
+                        return error(format!("there's no breakpoint {}", id));
+                    }
+                    Ok(String::new())
+                }
+                None => {
+                    self.breakpoints.clear();
+                    Ok(String::new())
+                }
+            },
+            "breakpoints" => {
+                if self.breakpoints.is_empty() {
+                    return Ok(String::from("No breakpoints"));
+                }
+                let lines: Vec<String> = self
+                    .breakpoints
+                    .iter()
+                    .map(|breakpoint| format!("{:<3}{}", breakpoint.id, breakpoint.command))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            "regs" | "r" => Ok(registers(emulator)),
+            "mem" | "x" => {
+                let address = required_number(args.first(), "an address")? as usize;
+                let length = optional_number(args.get(1), DEFAULT_DUMP_LENGTH)?;
+                memory_dump(emulator, address, length)
+            }
+            "disasm" | "l" => {
+                let address = match args.first() {
+                    Some(_) => required_number(args.first(), "an address")?,
+                    None => emulator.program_counter().saturating_sub(8),
+                };
+                let count = optional_number(args.get(1), DEFAULT_DISASM_COUNT)?;
+                disassembly(emulator, address, count)
+            }
+            "stack" | "bt" => Ok(call_stack(emulator)),
+            "set" => {
+                let (register, value) = match args {
+                    [register, value] => (*register, *value),
+                    _ => return error(String::from("expected a register and a value")),
+                };
+                let value = required_number(Some(&value), "a value")?;
+                set_register(emulator, register, value)?;
+                Ok(String::new())
+            }
+            "write" => {
+                let address = required_number(args.first(), "an address")? as usize;
+                let bytes = args[1..]
+                    .iter()
+                    .map(|byte| match parse_number(byte) {
+                        Some(value) if value <= 0xFF => Ok(value as u8),
+                        _ => error(format!("'{}' is not a byte", byte)),
+                    })
+                    .collect::<Result<Vec<u8>, _>>()?;
+                let memory = emulator.memory_mut();
+                if bytes.is_empty() || address + bytes.len() > memory.len() {
+                    return error(String::from("expected bytes that fit in memory"));
+                }
+                memory[address..address + bytes.len()].copy_from_slice(&bytes);
+                Ok(String::new())
+            }
+            "reset" => {
+                match args {
+                    [] => emulator.reset(),
+                    ["hard"] => emulator.hard_reset(),
+                    _ => return error(String::from("expected nothing or 'hard'")),
+                }
+                self.state = State::Stopped;
+                Ok(current_instruction(emulator))
+            }
+            "quit" | "q" => {
+                self.state = State::Quit;
+                Ok(String::new())
+            }
+            "help" | "h" => Ok(String::from(HELP)),
+            _ => error(format!("unknown command '{}', try 'help'", command)),
+        }
+    }
+
+    fn add_breakpoint(
+        &mut self,
+        emulator: &mut Emulator,
+        command: &str,
+        words: &[&str],
+    ) -> Result<usize, CommandError> {
+        let (args, condition) = match words.iter().position(|&word| word == "if") {
+            Some(index) => match Expr::parse(&words[index + 1..].join(" ")) {
+                Ok(condition) => (&words[..index], Some(condition)),
+                Err(err) => return error(format!("invalid condition: {}", err)),
+            },
+            None => (words, None),
+        };
+
+        let trigger = match command {
+            "break" | "b" if args.is_empty() => {
+                if condition.is_none() {
+                    return error(String::from("expected an address or a condition"));
+                }
+                Trigger::Always
+            }
+            "break" | "b" => match args {
+                [address] => Trigger::Address(required_number(Some(address), "an address")?),
+                _ => return error(String::from("expected an address")),
+            },
+            "watch" | "rwatch" | "awatch" => match args {
+                [address] | [address, _] if parse_number(address).is_some() => {
+                    let start = required_number(Some(address), "an address")?;
+                    let length = optional_number(args.get(1), 1)?.max(1);
+                    Trigger::Memory {
+                        start,
+                        end: start.saturating_add(length as u16 - 1),
+                        reads: command != "watch",
+                        writes: command != "rwatch",
+                    }
+                }
+                [] => return error(String::from("expected an address or an expression")),
+                _ if command == "watch" => {
+                    let source = args.join(" ");
+                    let expr = match Expr::parse(&source) {
+                        Ok(expr) => expr,
+                        Err(err) => return error(format!("invalid expression: {}", err)),
+                    };
+                    let value = expr.evaluate(emulator).ok();
+                    Trigger::Change {
+                        source,
+                        expr,
+                        value,
+                    }
+                }
+                _ => return error(String::from("expected an address and a length")),
+            },
+            "catch" => match args {
+                [pattern] => opcode_pattern(pattern)?,
+                _ => return error(String::from("expected an opcode pattern like Dxyn")),
+            },
+            _ => unreachable!(),
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let command = match command {
+            "b" => "break",
+            command => command,
+        };
+        self.breakpoints.push(Breakpoint {
+            id,
+            trigger,
+            condition,
+            command: format!("{} {}", command, words.join(" ")),
+        });
+        self.track_memory(emulator);
+        Ok(id)
+    }
+
+    /// Turns on the emulator's memory tracking when there are memory watchpoints. It's left on
+    /// once they're deleted, in case something else, like a coverage map, relies on it too.
+    fn track_memory(&self, emulator: &mut Emulator) {
+        let watches_memory = self
+            .breakpoints
+            .iter()
+            .any(|breakpoint| matches!(breakpoint.trigger, Trigger::Memory { .. }));
+        if watches_memory {
+            emulator.set_memory_tracking(true);
+        }
+    }
+
+    fn resume(&mut self, emulator: &mut Emulator, until: Until) {
+        self.state = State::Running(until);
+        self.resuming = true;
+        // Changes made while stopped, by stepping or by hand, shouldn't trigger watchpoints.
+        for breakpoint in &mut self.breakpoints {
+            if let Trigger::Change { expr, value, .. } = &mut breakpoint.trigger {
+                *value = expr.evaluate(emulator).ok();
+            }
+        }
+        self.track_memory(emulator);
+    }
+
+    /// Runs `count` instructions through `advance`, like any other way of resuming, so that the
+    /// caller ticks the timers and records them as usual. Breakpoints along the way still stop it.
+    fn step(&mut self, emulator: &mut Emulator, count: usize) -> Result<String, CommandError> {
+        if count == 0 {
+            return Ok(current_instruction(emulator));
+        }
+        self.resume(emulator, Until::Steps { remaining: count });
+        Ok(String::new())
+    }
+}
+
+fn required_number(text: Option<&&str>, what: &str) -> Result<u16, CommandError> {
+    match text {
+        Some(text) => {
+            parse_number(text).map_or_else(|| error(format!("'{}' is not a number", text)), Ok)
+        }
+        None => error(format!("expected {}", what)),
+    }
+}
+
+fn optional_number(text: Option<&&str>, default: usize) -> Result<usize, CommandError> {
+    match text {
+        Some(_) => required_number(text, "a number").map(|number| number as usize),
+        None => Ok(default),
+    }
+}
+
+/// Parses an opcode pattern like `Dxyn` or `8xy6`, where hex digits have to match and any other
+/// character matches anything.
+fn opcode_pattern(pattern: &str) -> Result<Trigger, CommandError> {
+    if pattern.chars().count() != 4 {
+        return error(format!(
+            "'{}' isn't a four character opcode pattern",
+            pattern
+        ));
+    }
+    let (mut mask, mut bits) = (0, 0);
+    for c in pattern.chars() {
+        mask <<= 4;
+        bits <<= 4;
+        if let Some(digit) = c.to_digit(16) {
+            mask |= 0xF;
+            bits |= digit as u16;
+        }
+    }
+    Ok(Trigger::Opcode { mask, bits })
+}
+
+fn show_value(value: Option<i64>) -> String {
+    match value {
+        Some(value) => format!("{:#X}", value),
+        None => String::from("an error"),
+    }
+}
+
+fn current_instruction(emulator: &Emulator) -> String {
+    let address = emulator.program_counter();
+    let opcode = emulator.next_opcode();
+    format!(
+        "=> {:#05X}: {:04X}  {}",
+        address,
+        opcode,
+        disassemble(opcode)
+    )
+}
+
+fn registers(emulator: &Emulator) -> String {
+    let mut text = format!(
+        "PC: {:#05X}  I: {:#05X}  DT: {}  ST: {}",
+        emulator.program_counter(),
+        emulator.i(),
+        emulator.delay_timer(),
+        emulator.sound_timer()
+    );
+    for (x, value) in emulator.registers().iter().enumerate() {
+        let separator = if x % 8 == 0 { "\n" } else { "  " };
+        write!(text, "{}V{:X}: {:02X}", separator, x, value).unwrap();
+    }
+    text
+}
+
+fn memory_dump(emulator: &Emulator, address: usize, length: usize) -> Result<String, CommandError> {
+    let memory = emulator.memory();
+    if address >= memory.len() {
+        return error(format!("{:#05X} is outside of memory", address));
+    }
+    let end = (address + length).min(memory.len());
+    let lines: Vec<String> = memory[address..end]
+        .chunks(16)
+        .enumerate()
+        .map(|(index, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("{:#05X}: {}", address + 16 * index, hex.join(" "))
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+fn disassembly(emulator: &Emulator, address: u16, count: usize) -> Result<String, CommandError> {
+    let memory = emulator.memory();
+    let start = address as usize;
+    if start >= memory.len() {
+        return error(format!("{:#05X} is outside of memory", address));
+    }
+    let end = (start + 2 * count).min(memory.len());
+    let lines: Vec<String> = disassemble_program(&memory[start..end], address)
+        .iter()
+        .map(|line| {
+            let marker = if line.address == emulator.program_counter() {
+                "=>"
+            } else {
+                "  "
+            };
+            format!("{} {}", marker, line)
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+fn call_stack(emulator: &Emulator) -> String {
+    let mut text = format!("#0  {:#05X}", emulator.program_counter());
+    for (frame, address) in emulator.call_stack().iter().rev().enumerate() {
+        write!(text, "\n#{}  {:#05X}", frame + 1, address).unwrap();
+    }
+    text
+}
+
+fn set_register(emulator: &mut Emulator, register: &str, value: u16) -> Result<(), CommandError> {
+    let byte = || {
+        if value <= 0xFF {
+            Ok(value as u8)
+        } else {
+            error(format!("{:#X} doesn't fit in a byte", value))
+        }
+    };
+    match register.to_ascii_uppercase().as_str() {
+        "I" => emulator.set_i(value),
+        "PC" => emulator.set_program_counter(value),
+        "DT" => emulator.set_delay_timer(byte()?),
+        "ST" => emulator.set_sound_timer(byte()?),
+        name if name.len() == 2 && name.starts_with('V') => {
+            match usize::from_str_radix(&name[1..], 16) {
+                Ok(x) => emulator.set_register(x, byte()?),
+                Err(_) => return error(format!("'{}' is not a register", register)),
+            }
+        }
+        _ => return error(format!("'{}' is not a register", register)),
+    }
+    Ok(())
+}
+
+/// Reads debugger commands from standard input on a separate thread, so that a window can keep
+/// running while waiting for them.
+pub fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let sent = match line {
+                Ok(line) => sender.send(line).is_ok(),
+                Err(_) => false,
+            };
+            if !sent {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Runs the debugger without a window, reading commands from `input` until it's closed or the
+/// user quits. The timers are ticked every `cycles_per_frame` instructions while the program runs,
+/// and a program that runs for `REPL_STEP_LIMIT` instructions without stopping is interrupted, as
+/// there's no other way to get control back.
+pub fn repl<R, W>(
+    emulator: &mut Emulator,
+    debugger: &mut Debugger,
+    input: R,
+    mut output: W,
+    cycles_per_frame: u64,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut cycles = 0;
+    write!(output, "{}", PROMPT)?;
+    output.flush()?;
+    for line in input.lines() {
+        match debugger.execute(emulator, &line?) {
+            Ok(text) if text.is_empty() => {}
+            Ok(text) => writeln!(output, "{}", text)?,
+            Err(err) => writeln!(output, "error: {}", err)?,
+        }
+        let mut steps = 0;
+        while debugger.is_running() {
+            if steps == REPL_STEP_LIMIT {
+                let reason = format!("Interrupted after {} instructions", steps);
+                if let Some(report) = debugger.interrupt(emulator, reason) {
+                    writeln!(output, "{}", report)?;
+                }
+                break;
+            }
+            let progress = debugger.advance(emulator);
+            if progress.step.is_some() {
+                steps += 1;
+                cycles += 1;
+                if cycles % cycles_per_frame == 0 {
+                    emulator.tick();
+                }
+            }
+            if let Some(report) = progress.report {
+                writeln!(output, "{}", report)?;
+            }
+        }
+        if debugger.has_quit() {
+            return Ok(());
+        }
+        write!(output, "{}", PROMPT)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        repl,
+        Debugger,
+        REPL_STEP_LIMIT,
+    };
+    use crate::emulator::Emulator;
+
+    // 0x200: LD V0, 0x05
+    // 0x202: CALL 0x20A
+    // 0x204: ADD V0, 0x01
+    // 0x206: JP 0x206
+    // 0x208: DW 0x0000
+    // 0x20A: LD V1, 0x07
+    // 0x20C: RET
+    const PROGRAM: [u8; 14] = [
+        0x60, 0x05, 0x22, 0x0A, 0x70, 0x01, 0x12, 0x06, 0x00, 0x00, 0x61, 0x07, 0x00, 0xEE,
+    ];
+
+    fn setup() -> (Emulator, Debugger) {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&PROGRAM).unwrap();
+        (emulator, Debugger::new())
+    }
+
+    fn run(emulator: &mut Emulator, debugger: &mut Debugger) -> Option<String> {
+        let mut last_report = None;
+        for _ in 0..1000 {
+            if !debugger.is_running() {
+                break;
+            }
+            let progress = debugger.advance(emulator);
+            last_report = progress.report.or(last_report);
+        }
+        last_report
+    }
+
+    #[test]
+    fn step() {
+        let (mut emulator, mut debugger) = setup();
+
+        debugger.execute(&mut emulator, "step 2").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+
+        assert_eq!(report.unwrap(), "=> 0x20A: 6107  LD V1, 0x07");
+        assert_eq!(emulator.registers()[0x0], 0x05);
+        // An empty line repeats the last command.
+        debugger.execute(&mut emulator, "").unwrap();
+        run(&mut emulator, &mut debugger);
+        assert_eq!(emulator.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let (mut emulator, mut debugger) = setup();
+        debugger.execute(&mut emulator, "s").unwrap();
+        run(&mut emulator, &mut debugger);
+
+        debugger.execute(&mut emulator, "next").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+
+        assert_eq!(report.unwrap(), "=> 0x204: 7001  ADD V0, 0x01");
+        assert_eq!(emulator.registers()[0x1], 0x07);
+        assert!(emulator.call_stack().is_empty());
+    }
+
+    #[test]
+    fn finish() {
+        let (mut emulator, mut debugger) = setup();
+        assert!(debugger.execute(&mut emulator, "finish").is_err());
+        debugger.execute(&mut emulator, "step 2").unwrap();
+        run(&mut emulator, &mut debugger);
+
+        debugger.execute(&mut emulator, "finish").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+
+        assert!(report.unwrap().starts_with("Returned from the subroutine"));
+        assert_eq!(emulator.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (mut emulator, mut debugger) = setup();
+        debugger.execute(&mut emulator, "break 0x20C").unwrap();
+
+        debugger.execute(&mut emulator, "continue").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+        assert_eq!(
+            report.unwrap(),
+            "Breakpoint 1 at 0x20C\n=> 0x20C: 00EE  RET"
+        );
+
+        // Continuing doesn't stop at the same breakpoint again straight away.
+        debugger.execute(&mut emulator, "b 0x206").unwrap();
+        debugger.execute(&mut emulator, "c").unwrap();
+        run(&mut emulator, &mut debugger);
+        assert_eq!(emulator.program_counter(), 0x206);
+        assert_eq!(emulator.registers()[0x0], 0x06);
+
+        assert_eq!(
+            debugger.execute(&mut emulator, "breakpoints").unwrap(),
+            "1  break 0x20C\n2  break 0x206"
+        );
+        debugger.execute(&mut emulator, "delete 1").unwrap();
+        assert!(debugger.execute(&mut emulator, "delete 1").is_err());
+        debugger.execute(&mut emulator, "delete").unwrap();
+        assert_eq!(
+            debugger.execute(&mut emulator, "breakpoints").unwrap(),
+            "No breakpoints"
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let (mut emulator, mut debugger) = setup();
+
+        let output = debugger
+            .execute(&mut emulator, "break 0x20A if V0 != 5")
+            .unwrap();
+        assert_eq!(output, "Breakpoint 1: break 0x20A if V0 != 5");
+        debugger
+            .execute(&mut emulator, "break 0x20C if V1 == 7")
+            .unwrap();
+        debugger.execute(&mut emulator, "continue").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+        assert!(report.unwrap().starts_with("Breakpoint 2 at 0x20C"));
+
+        debugger.execute(&mut emulator, "delete").unwrap();
+        debugger.execute(&mut emulator, "break if V0 > 5").unwrap();
+        debugger.execute(&mut emulator, "continue").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+        assert_eq!(report.unwrap(), "Breakpoint 3\n=> 0x206: 1206  JP 0x206");
+        assert_eq!(emulator.registers()[0x0], 0x06);
+
+        assert!(debugger.execute(&mut emulator, "break").is_err());
+        assert!(debugger.execute(&mut emulator, "break 0x206 if").is_err());
+        assert!(debugger
+            .execute(&mut emulator, "break 0x206 if V0 ==")
+            .is_err());
+    }
+
+    #[test]
+    fn watchpoints() {
+        // 0x200: LD I, 0x300
+        // 0x202: LD V3, 0x05
+        // 0x204: LD B, V3
+        // 0x206: LD V2, [I]
+        // 0x208: ADD V3, 0x01
+        // 0x20A: JP 0x204
+        let mut emulator = Emulator::new();
+        emulator
+            .load_rom(&[
+                0xA3, 0x00, 0x63, 0x05, 0xF3, 0x33, 0xF2, 0x65, 0x73, 0x01, 0x12, 0x04,
+            ])
+            .unwrap();
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut emulator, "watch 0x302").unwrap();
+        debugger.execute(&mut emulator, "continue").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+        assert_eq!(
+            report.unwrap(),
+            "Watchpoint 1: write to 0x300-0x302 by 0x204\n=> 0x206: F265  LD V2, [I]"
+        );
+
+        // Reads are only caught by rwatch and awatch.
+        debugger.execute(&mut emulator, "delete").unwrap();
+        debugger.execute(&mut emulator, "rwatch 0x300 3").unwrap();
+        debugger.execute(&mut emulator, "continue").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+        assert!(report
+            .unwrap()
+            .starts_with("Watchpoint 2: read of 0x300-0x302 by 0x206"));
+
+        debugger.execute(&mut emulator, "delete").unwrap();
+        debugger
+            .execute(&mut emulator, "watch V3 if V3 > 6")
+            .unwrap();
+        debugger.execute(&mut emulator, "continue").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+        assert!(report
+            .unwrap()
+            .starts_with("Watchpoint 3: V3 changed from 0x6 to 0x7"));
+
+        assert!(debugger.execute(&mut emulator, "watch").is_err());
+        assert!(debugger.execute(&mut emulator, "rwatch V3").is_err());
+    }
+
+    #[test]
+    fn catchpoints() {
+        let (mut emulator, mut debugger) = setup();
+
+        debugger.execute(&mut emulator, "catch 00EE").unwrap();
+        debugger.execute(&mut emulator, "continue").unwrap();
+        let report = run(&mut emulator, &mut debugger);
+        assert_eq!(report.unwrap(), "Catchpoint 1: RET\n=> 0x20C: 00EE  RET");
+
+        debugger.execute(&mut emulator, "delete").unwrap();
+        debugger.execute(&mut emulator, "catch 7xnn").unwrap();
+        debugger.execute(&mut emulator, "continue").unwrap();
+        run(&mut emulator, &mut debugger);
+        assert_eq!(emulator.program_counter(), 0x204);
+
+        assert!(debugger.execute(&mut emulator, "catch 7x").is_err());
+    }
+
+    #[test]
+    fn inspection() {
+        let (mut emulator, mut debugger) = setup();
+        debugger.execute(&mut emulator, "step 2").unwrap();
+        run(&mut emulator, &mut debugger);
+
+        let registers = debugger.execute(&mut emulator, "regs").unwrap();
+        assert_eq!(
+            registers.lines().collect::<Vec<_>>(),
+            vec![
+                "PC: 0x20A  I: 0x000  DT: 0  ST: 0",
+                "V0: 05  V1: 00  V2: 00  V3: 00  V4: 00  V5: 00  V6: 00  V7: 00",
+                "V8: 00  V9: 00  VA: 00  VB: 00  VC: 00  VD: 00  VE: 00  VF: 00",
+            ]
+        );
+        assert_eq!(
+            debugger.execute(&mut emulator, "mem 0x200 4").unwrap(),
+            "0x200: 60 05 22 0A"
+        );
+        assert_eq!(
+            debugger.execute(&mut emulator, "disasm 0x208 2").unwrap(),
+            "   0x208: 0000  SYS 0x000\n=> 0x20A: 6107  LD V1, 0x07"
+        );
+        assert_eq!(
+            debugger.execute(&mut emulator, "bt").unwrap(),
+            "#0  0x20A\n#1  0x204"
+        );
+        assert!(debugger.execute(&mut emulator, "mem 0x1000").is_err());
+    }
+
+    #[test]
+    fn modification() {
+        let (mut emulator, mut debugger) = setup();
+
+        debugger.execute(&mut emulator, "set V3 0x10").unwrap();
+        debugger.execute(&mut emulator, "set i 0x300").unwrap();
+        debugger.execute(&mut emulator, "set PC 0x20A").unwrap();
+        debugger
+            .execute(&mut emulator, "write 0x300 1 2 0xFF")
+            .unwrap();
+
+        assert_eq!(emulator.registers()[0x3], 0x10);
+        assert_eq!(emulator.i(), 0x300);
+        assert_eq!(emulator.program_counter(), 0x20A);
+        assert_eq!(&emulator.memory()[0x300..0x303], &[1, 2, 0xFF]);
+        assert!(debugger.execute(&mut emulator, "set V3 0x100").is_err());
+        assert!(debugger.execute(&mut emulator, "set VG 1").is_err());
+        assert!(debugger.execute(&mut emulator, "frobnicate").is_err());
+    }
+
+    #[test]
+    fn reset() {
+        let (mut emulator, mut debugger) = setup();
+        debugger.execute(&mut emulator, "step 4").unwrap();
+        run(&mut emulator, &mut debugger);
+        debugger.execute(&mut emulator, "write 0x200 0x61").unwrap();
+
+        assert_eq!(
+            debugger.execute(&mut emulator, "reset").unwrap(),
+            "=> 0x200: 6105  LD V1, 0x05"
+        );
+        assert!(emulator.call_stack().is_empty());
+        assert_eq!(emulator.registers()[0x0], 0);
+        assert_eq!(
+            debugger.execute(&mut emulator, "reset hard").unwrap(),
+            "=> 0x200: 6005  LD V0, 0x05"
+        );
+        assert!(debugger.execute(&mut emulator, "reset soft").is_err());
+    }
+
+    #[test]
+    fn headless_repl() {
+        let (mut emulator, mut debugger) = setup();
+        let mut output = Vec::new();
+
+        repl(
+            &mut emulator,
+            &mut debugger,
+            "b 0x204\nc\nregs\nq\nregs\n".as_bytes(),
+            &mut output,
+            10,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Breakpoint 1 at 0x204\n=> 0x204: 7001  ADD V0, 0x01"));
+        assert_eq!(output.matches("PC: 0x204").count(), 1);
+        assert!(debugger.has_quit());
+    }
+
+    #[test]
+    fn headless_repl_runs_the_timers_and_interrupts() {
+        let mut emulator = Emulator::new();
+        // JP 0x200
+        emulator.load_rom(&[0x12, 0x00]).unwrap();
+        emulator.set_delay_timer(5);
+        let mut output = Vec::new();
+
+        repl(
+            &mut emulator,
+            &mut Debugger::new(),
+            "step 20
+c
+"
+            .as_bytes(),
+            &mut output,
+            10,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!(
+            "Interrupted after {} instructions\n=> 0x200",
+            REPL_STEP_LIMIT
+        )));
+        assert_eq!(emulator.delay_timer(), 0);
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+
+/// Renders `opcode` in the mnemonic syntax of Cowgod's Chip-8 Technical Reference, which is also
+/// what the assembler reads. Opcodes that aren't instructions are rendered as a `DW` data word.
+pub fn disassemble(opcode: u16) -> String {
+    let nnn = opcode & 0x0FFF;
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let kk = opcode & 0x00FF;
+    let n = opcode & 0x000F;
+
+    match opcode & 0xF000 {
+        0x0000 => match nnn {
+            0x0E0 => String::from("CLS"),
+            0x0EE => String::from("RET"),
+            _ => format!("SYS {:#05X}", nnn),
+        },
+        0x1000 => format!("JP {:#05X}", nnn),
+        0x2000 => format!("CALL {:#05X}", nnn),
+        0x3000 => format!("SE V{:X}, {:#04X}", x, kk),
+        0x4000 => format!("SNE V{:X}, {:#04X}", x, kk),
+        0x5000 if n == 0x0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, {:#04X}", x, kk),
+        0x7000 => format!("ADD V{:X}, {:#04X}", x, kk),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => data_word(opcode),
+        },
+        0x9000 if n == 0x0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, {:#05X}", nnn),
+        0xB000 => format!("JP V0, {:#05X}", nnn),
+        0xC000 => format!("RND V{:X}, {:#04X}", x, kk),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 => match kk {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => data_word(opcode),
+        },
+        0xF000 => match kk {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => data_word(opcode),
+        },
+        _ => data_word(opcode),
+    }
+}
+
+fn data_word(opcode: u16) -> String {
+    format!("DW {:#06X}", opcode)
+}
+
+/// A single instruction, or a trailing data byte, of a disassembled program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex: String = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        write!(f, "{:#05X}: {:<4}  {}", self.address, hex, self.text)
+    }
+}
+
+/// Disassembles `program` two bytes at a time as if it were loaded at `start`.
+pub fn disassemble_program(program: &[u8], start: u16) -> Vec<Line> {
+    program
+        .chunks(2)
+        .enumerate()
+        .map(|(index, bytes)| {
+            let text = match *bytes {
+                [first, second] => disassemble(u16::from_be_bytes([first, second])),
+                [byte] => format!("DB {:#04X}", byte),
+                _ => unreachable!(),
+            };
+            Line {
+                address: start.wrapping_add(2 * index as u16),
+                bytes: bytes.to_vec(),
+                text,
+            }
+        })
+        .collect()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        disassemble,
+        disassemble_program,
+    };
+
+    #[test]
+    fn instructions() {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x00EE), "RET");
+        assert_eq!(disassemble(0x0123), "SYS 0x123");
+        assert_eq!(disassemble(0x2ABC), "CALL 0xABC");
+        assert_eq!(disassemble(0x3A0F), "SE VA, 0x0F");
+        assert_eq!(disassemble(0x8126), "SHR V1, V2");
+        assert_eq!(disassemble(0xB300), "JP V0, 0x300");
+        assert_eq!(disassemble(0xD01F), "DRW V0, V1, 15");
+        assert_eq!(disassemble(0xE5A1), "SKNP V5");
+        assert_eq!(disassemble(0xF730), "LD HF, V7");
+        assert_eq!(disassemble(0xF355), "LD [I], V3");
+        assert_eq!(disassemble(0xF265), "LD V2, [I]");
+    }
+
+    #[test]
+    fn data_words() {
+        assert_eq!(disassemble(0x5121), "DW 0x5121");
+        assert_eq!(disassemble(0x812F), "DW 0x812F");
+        assert_eq!(disassemble(0xFFFF), "DW 0xFFFF");
+    }
+
+    #[test]
+    fn program() {
+        let lines = disassemble_program(&[0x60, 0x05, 0x12, 0x00, 0xAA], 0x200);
+
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "0x200: 6005  LD V0, 0x05",
+                "0x202: 1200  JP 0x200",
+                "0x204: AA    DB 0xAA",
+            ]
+        );
+    }
+}
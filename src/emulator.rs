@@ -1,329 +1,595 @@
-use std::{
-    thread,
-    time::Duration,
-};
 use rand::{
-    rngs::ThreadRng,
+    rngs::StdRng,
     Rng,
+    SeedableRng,
 };
-use sdl2::{
-    event::Event,
-    keyboard::Keycode,
-    pixels::Color,
-    rect::Rect,
+use std::{
+    error::Error,
+    fmt,
+    ops::Range,
 };
 
-
-// Display constants
-
-const PIXEL_WIDTH: usize = 20;
-const PIXEL_HEIGHT: usize = 20;
-
-const WINDOW_WIDTH: i32 = (PIXEL_WIDTH * DISPLAY_WIDTH) as i32;
-const WINDOW_HEIGHT: i32 = (PIXEL_HEIGHT * DISPLAY_HEIGHT) as i32;
-
-const TICKS_PER_SECOND: u64 = 60;
-const MICROSECONDS_PER_FRAME: u64 = 1_000_000 / TICKS_PER_SECOND / FRAMES_PER_TICK;
-// Undocumented assumption that the emulator's FPS will be above than 60.
-const FRAMES_PER_TICK: u64 = 10;
+use crate::{
+    cdp1802::{
+        Bus,
+        Cdp1802,
+    },
+    font::Font,
+    framebuffer::Framebuffer,
+    palette::Rgb,
+    platform::{
+        MachineConfig,
+        Platform,
+    },
+    quirks::Quirks,
+    variant::{
+        Extension,
+        Variant,
+    },
+};
 
 // Emulator constants
 
 pub const DISPLAY_HEIGHT: usize = 32;
 pub const DISPLAY_WIDTH: usize = 64;
 
-const MEMORY_SIZE: usize = 4096;
 const V_SIZE: usize = 16;
-const STACK_SIZE: usize = 16;
-const SPRITES: [u8; 16 * 5] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
 
+/// How many keys a keypad has. CHIP-8X's second keypad follows the first, as keys 0x10 to 0x1F.
+pub const KEYPAD_SIZE: usize = 16;
+
+// Where the COSMAC VIP's interpreter keeps its state, as offsets below the top of memory. Machine
+// code routines called with `SYS` find it in the same places.
+
+const VIP_RESERVED_OFFSET: usize = 0x160;
+const VIP_DISPLAY_OFFSET: usize = 0x100;
+const VIP_V_OFFSET: usize = 0x110;
+const VIP_STACK_OFFSET: usize = 0x131;
+
+/// How many CDP1802 instructions a machine code routine can execute before it's assumed never to
+/// return.
+const MACHINE_CODE_STEP_LIMIT: usize = 1_000_000;
+
+/// A CHIP-8 machine. Besides running programs, everything in it can be inspected and changed
+/// through its accessors (registers, timers, stack, memory, display and keypad), so that tools,
+/// bots and tests can work with it directly.
 pub struct Emulator {
     program_counter: u16,
-    memory: [u8; MEMORY_SIZE],
+    memory: Vec<u8>,
+    /// The program last loaded and where it was loaded, for reloading it on a hard reset.
+    rom: Vec<u8>,
+    rom_address: u16,
+    load_address: u16,
+    font: Font,
+    font_address: u16,
     v: [u8; V_SIZE],
     i: u16,
-    stack_pointer: u8,
-    stack: [u16; STACK_SIZE],
-    keyboard: [bool; 16],
-    bitmap: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+    stack_pointer: usize,
+    /// The stack, when it isn't kept in memory. It holds every entry that has been used, or all of
+    /// them if the depth is limited.
+    stack: Vec<u16>,
+    stack_depth: Option<usize>,
+    stack_address: Option<u16>,
+    vip_layout: bool,
+    /// The display as it was in memory when it was last brought in step with the framebuffer, to
+    /// tell which of them has changed since.
+    display_in_memory: Vec<u8>,
+    /// How many times each keypad key is being held down, as several keyboard keys can be bound to
+    /// the same one.
+    keyboard: [u8; 2 * KEYPAD_SIZE],
+    bitmap: Framebuffer,
     sound_timer: u8,
     delay_timer: u8,
-    rng: ThreadRng,
+    rng: StdRng,
+    quirks: Quirks,
+    variant: Variant,
+    extension: Option<Box<dyn Extension>>,
     is_paused: bool,
+    tracks_memory: bool,
+    memory_accesses: Vec<MemoryAccess>,
+    /// The CDP1802 machine cycles taken by the machine code routine called by the last step.
+    machine_code_cycles: u64,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
+    }
 }
 
 impl Emulator {
     pub fn new() -> Self {
-        let mut memory = [0; MEMORY_SIZE];
-        memory[..0x50].copy_from_slice(&SPRITES);
+        Emulator::with_platform(Platform::Chip8)
+    }
+
+    /// Creates an emulator with the memory layout of `platform`.
+    pub fn with_platform(platform: Platform) -> Self {
+        Emulator::with_config(platform.config())
+    }
+
+    /// Creates an emulator for the machine described by `config`.
+    pub fn with_config(config: MachineConfig) -> Self {
+        let font = Font::default();
+        let mut memory = vec![0; config.memory_size];
+        let bytes = font.bytes();
+        memory[..bytes.len()].copy_from_slice(&bytes);
 
         Emulator {
-            program_counter: 0x200,
+            program_counter: config.load_address,
             memory,
+            rom: Vec::new(),
+            rom_address: config.load_address,
+            load_address: config.load_address,
+            font,
+            font_address: 0x000,
             i: 0,
             v: [0; V_SIZE],
-            stack_pointer: 0x0,
-            stack: [0; STACK_SIZE],
-            keyboard: [false; 16],
-            bitmap: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            stack_pointer: 0,
+            stack: Emulator::empty_stack(&config),
+            stack_depth: config.stack_depth,
+            stack_address: config.stack_address,
+            vip_layout: config.vip_layout,
+            display_in_memory: Vec::new(),
+            keyboard: [0; 2 * KEYPAD_SIZE],
+            bitmap: Framebuffer::new(DISPLAY_WIDTH, DISPLAY_HEIGHT),
             sound_timer: 0,
             delay_timer: 0,
-            rng: rand::thread_rng(),
+            rng: StdRng::from_entropy(),
+            quirks: Quirks::default(),
+            variant: Variant::default(),
+            extension: None,
             is_paused: false,
+            tracks_memory: false,
+            memory_accesses: Vec::new(),
+            machine_code_cycles: 0,
         }
     }
 
-    pub fn run(&mut self) {
-        // Set up the SDL2 window
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+    fn empty_stack(config: &MachineConfig) -> Vec<u16> {
+        match (config.stack_address, config.stack_depth) {
+            (None, Some(depth)) if !config.vip_layout => vec![0; depth],
+            _ => Vec::new(),
+        }
+    }
 
-        eprintln!("Window dimensions: {}, {}", WINDOW_WIDTH, WINDOW_HEIGHT);
+    /// The machine this emulator emulates.
+    pub fn config(&self) -> MachineConfig {
+        MachineConfig {
+            memory_size: self.memory.len(),
+            stack_depth: self.stack_depth,
+            load_address: self.load_address,
+            stack_address: self.stack_address,
+            vip_layout: self.vip_layout,
+        }
+    }
 
-        let window = video_subsystem
-            .window("CHIP-8", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
-            .position_centered()
-            .build()
-            .unwrap();
+    /// Whether the display pixel at column `x` of row `y` is lit.
+    pub fn pixel_at(&self, x: usize, y: usize) -> bool {
+        self.bitmap.pixel_at(x, y)
+    }
 
-        let mut canvas = window.into_canvas().build().unwrap();
-        let mut event_pump = sdl_context.event_pump().unwrap();
-        let mut remaining_frames = FRAMES_PER_TICK;
-        canvas.set_draw_color(Color::BLACK);
-        canvas.clear();
-        canvas.present();
-        'running: loop {
-            canvas.set_draw_color(Color::BLACK);
-            canvas.clear();
-
-            // Handle input
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. } => {
-                        break 'running;
-                    }
-                    Event::AppDidEnterBackground { .. } => {
-                        self.pause();
-                    }
-                    Event::AppDidEnterForeground { .. } => {
-                        self.resume();
-                    }
-                    Event::KeyDown {
-                        repeat, keycode, ..
-                    } => {
-                        if repeat {
-                            continue;
-                        }
+    /// The colour the pixel at column `x` of row `y` is shown in, for variants with colour
+    /// hardware. The frontend's palette is used for the others.
+    pub fn pixel_color(&self, x: usize, y: usize) -> Option<Rgb> {
+        let lit = self.pixel_at(x, y);
+        self.extension.as_ref()?.pixel_color(x, y, lit)
+    }
 
-                        let key = keycode.expect("No key in keycode on KeyDown event");
-                        eprintln!("Key pressed: {}", key);
-
-                        match key {
-                            Keycode::Num1 => {
-                                self.key_press(0x1);
-                            }
-                            Keycode::Num2 => {
-                                self.key_press(0x2);
-                            }
-                            Keycode::Num3 => {
-                                self.key_press(0x3);
-                            }
-                            Keycode::Num4 => {
-                                self.key_press(0xC);
-                            }
-
-                            Keycode::Q => {
-                                self.key_press(0x4);
-                            }
-                            Keycode::W => {
-                                self.key_press(0x5);
-                            }
-                            Keycode::E => {
-                                self.key_press(0x6);
-                            }
-                            Keycode::R => {
-                                self.key_press(0xD);
-                            }
-
-                            Keycode::A => {
-                                self.key_press(0x7);
-                            }
-                            Keycode::S => {
-                                self.key_press(0x8);
-                            }
-                            Keycode::D => {
-                                self.key_press(0x9);
-                            }
-                            Keycode::F => {
-                                self.key_press(0xE);
-                            }
-
-                            Keycode::Z => {
-                                self.key_press(0xA);
-                            }
-                            Keycode::X => {
-                                self.key_press(0x0);
-                            }
-                            Keycode::C => {
-                                self.key_press(0xB);
-                            }
-                            Keycode::V => {
-                                self.key_press(0xF);
-                            }
-
-                            _ => {}
-                        };
-                    }
-                    Event::KeyUp {
-                        repeat, keycode, ..
-                    } => {
-                        if repeat {
-                            continue;
-                        }
+    /// The display, whose `rows` are a slice of its pixels packed one bit to a pixel. A display
+    /// kept in memory reflects changes to memory once the next instruction has executed.
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.bitmap
+    }
 
-                        let key = keycode.expect("No key in keycode on KeyUp event");
-                        eprintln!("Key released: {}", key);
-
-                        match key {
-                            Keycode::Num1 => {
-                                self.key_release(0x1);
-                            }
-                            Keycode::Num2 => {
-                                self.key_release(0x2);
-                            }
-                            Keycode::Num3 => {
-                                self.key_release(0x3);
-                            }
-                            Keycode::Num4 => {
-                                self.key_release(0xC);
-                            }
-
-                            Keycode::Q => {
-                                self.key_release(0x4);
-                            }
-                            Keycode::W => {
-                                self.key_release(0x5);
-                            }
-                            Keycode::E => {
-                                self.key_release(0x6);
-                            }
-                            Keycode::R => {
-                                self.key_release(0xD);
-                            }
-
-                            Keycode::A => {
-                                self.key_release(0x7);
-                            }
-                            Keycode::S => {
-                                self.key_release(0x8);
-                            }
-                            Keycode::D => {
-                                self.key_release(0x9);
-                            }
-                            Keycode::F => {
-                                self.key_release(0xE);
-                            }
-
-                            Keycode::Z => {
-                                self.key_release(0xA);
-                            }
-                            Keycode::X => {
-                                self.key_release(0x0);
-                            }
-                            Keycode::C => {
-                                self.key_release(0xB);
-                            }
-                            Keycode::V => {
-                                self.key_release(0xF);
-                            }
-
-                            _ => {}
-                        }
-                    }
-                    _ => {}
-                }
-            }
+    /// The display, for drawing on it directly.
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        &mut self.bitmap
+    }
 
-            if self.is_paused {
-                continue;
-            }
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
 
-            self.instruction_cycle();
-            canvas.set_draw_color(Color::WHITE);
-
-            for x in 0..DISPLAY_WIDTH {
-                for y in 0..DISPLAY_HEIGHT {
-                    if self.pixel_at(x, y) {
-                        canvas
-                            .fill_rect(Rect::new(
-                                (x * PIXEL_WIDTH) as i32,
-                                (y * PIXEL_HEIGHT) as i32,
-                                PIXEL_WIDTH as u32,
-                                PIXEL_HEIGHT as u32,
-                            ))
-                            .expect("Failed to draw the rectangles");
-                    }
-                }
-            }
+    /// Turns this emulator into `variant`, with its display and the instructions it adds. The
+    /// display is cleared.
+    pub fn set_variant(&mut self, variant: Variant) {
+        let (width, height) = variant.display_size();
+        self.variant = variant;
+        self.bitmap = Framebuffer::new(width, height);
+        self.extension = variant.extension();
+        self.display_in_memory.clear();
+        self.sync_display();
+    }
 
-            canvas.present();
-            // 60 FPS
-            thread::sleep(Duration::from_micros(MICROSECONDS_PER_FRAME));
-            // Tick
-            remaining_frames -= 1;
-            if remaining_frames == 0 {
-                self.tick();
-                remaining_frames = FRAMES_PER_TICK;
+    /// Gives every instruction to `extension` before executing it, replacing the current variant's
+    /// extension if it has one.
+    pub fn set_extension(&mut self, extension: Option<Box<dyn Extension>>) {
+        self.extension = extension;
+    }
+
+    /// The address programs are loaded at and start executing from.
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /// Changes the address programs are loaded at and start executing from.
+    pub fn set_load_address(&mut self, address: u16) {
+        self.load_address = address;
+        self.program_counter = address;
+    }
+
+    /// Copies `rom` into memory at the load address and points the program counter at it.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        let start = self.load_address as usize;
+        let top = self.memory.len();
+        // Programs can't run into a stack kept in memory after them, or the VIP interpreter's
+        // stack, variables and display.
+        let end = match self.stack_address {
+            _ if self.vip_layout => top.saturating_sub(VIP_RESERVED_OFFSET),
+            Some(address) if address as usize >= start => (address as usize).min(top),
+            _ => top,
+        };
+        let capacity = end.saturating_sub(start);
+        if rom.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if rom.len() > capacity {
+            return Err(LoadError::TooLarge {
+                size: rom.len(),
+                capacity,
+            });
+        }
+
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.rom = rom.to_vec();
+        self.rom_address = self.load_address;
+        self.program_counter = self.load_address;
+        Ok(())
+    }
+
+    /// Restarts the program: clears the registers, timers, stack and display and jumps back to the
+    /// load address. Memory is left as it is, including anything the program changed.
+    pub fn reset(&mut self) {
+        self.program_counter = self.load_address;
+        self.v = [0; V_SIZE];
+        self.i = 0;
+        self.stack_pointer = 0;
+        self.stack = Emulator::empty_stack(&self.config());
+        self.bitmap.clear();
+        self.sound_timer = 0;
+        self.delay_timer = 0;
+        self.memory_accesses.clear();
+        if let Some(extension) = self.extension.as_mut() {
+            extension.reset();
+        }
+        self.sync_display();
+    }
+
+    /// Restarts the program like `reset`, but also clears memory and loads the font and the
+    /// program into it again, as if the machine had just been turned on.
+    pub fn hard_reset(&mut self) {
+        self.reset();
+        for byte in self.memory.iter_mut() {
+            *byte = 0;
+        }
+        self.write_font();
+        let start = self.rom_address as usize;
+        self.memory[start..start + self.rom.len()].copy_from_slice(&self.rom);
+        self.sync_display();
+    }
+
+    /// Where the font starts in memory.
+    pub fn font_address(&self) -> u16 {
+        self.font_address
+    }
+
+    /// Replaces the font with `font`, stored in memory from `address`. The old font is cleared
+    /// from memory.
+    pub fn set_font(&mut self, font: Font, address: u16) -> Result<(), OutOfBounds> {
+        self.memory_range(address, font.bytes().len())?;
+        let old = self.memory_range(self.font_address, self.font.bytes().len());
+        if let Ok(old) = old {
+            for byte in &mut self.memory[old] {
+                *byte = 0;
             }
         }
+        self.font = font;
+        self.font_address = address;
+        self.write_font();
+        Ok(())
     }
 
-    pub fn pixel_at(&self, x: usize, y: usize) -> bool {
-        assert!(y < DISPLAY_HEIGHT);
-        assert!(x < DISPLAY_WIDTH);
+    fn write_font(&mut self) {
+        let bytes = self.font.bytes();
+        let start = self.font_address as usize;
+        self.memory[start..start + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Makes `RND` produce the same sequence of numbers on every run with the same seed.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// The address of the next instruction to execute.
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// Makes execution continue from `address`.
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.program_counter = address;
+    }
+
+    /// The opcode the next instruction cycle will execute.
+    pub fn next_opcode(&self) -> u16 {
+        self.fetch(self.program_counter)
+    }
 
-        self.bitmap[y][x]
+    /// The general purpose registers V0 to VF.
+    pub fn registers(&self) -> &[u8; V_SIZE] {
+        &self.v
     }
 
-    pub fn load(&mut self, rom: &[u8; 0xE00]) {
-        self.memory[0x200..].copy_from_slice(rom);
+    /// Sets register V`x`, where `x` is from 0x0 to 0xF.
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+
+    /// The address register I.
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.i = value;
+    }
+
+    /// The delay timer, which counts down at 60 Hz until it reaches 0.
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    /// The sound timer, which counts down at 60 Hz, sounding the buzzer until it reaches 0.
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// How many subroutines are currently being executed.
+    pub fn stack_pointer(&self) -> usize {
+        self.stack_pointer
+    }
+
+    /// Moves the top of the call stack, which can't be deeper than the stack depth.
+    pub fn set_stack_pointer(&mut self, value: usize) {
+        if let Some(depth) = self.stack_depth {
+            assert!(value <= depth, "the stack only has {} entries", depth);
+        }
+        if !self.keeps_stack_in_memory() && value > self.stack.len() {
+            self.stack.resize(value, 0);
+        }
+        self.stack_pointer = value;
+    }
+
+    /// How many subroutine calls can be nested, if there's a limit.
+    pub fn stack_depth(&self) -> Option<usize> {
+        self.stack_depth
+    }
+
+    /// The return addresses of the subroutines currently being executed, outermost first.
+    pub fn call_stack(&self) -> Vec<u16> {
+        (0..self.stack_pointer)
+            .map(|index| self.stack_entry(index))
+            .collect()
+    }
+
+    /// Every entry of the stack, including the ones above the stack pointer, which are left over
+    /// from subroutines that have returned. A stack without a limit only has the entries that have
+    /// been used.
+    pub fn stack(&self) -> Vec<u16> {
+        let size = match self.stack_depth {
+            Some(depth) => depth,
+            None => self.stack.len().max(self.stack_pointer),
+        };
+        (0..size).map(|index| self.stack_entry(index)).collect()
+    }
+
+    fn keeps_stack_in_memory(&self) -> bool {
+        self.vip_layout || self.stack_address.is_some()
+    }
+
+    /// Where stack entry `index` is kept in memory, if the stack is kept there and has room for
+    /// it. The VIP's stack grows down from just below its interpreter's variables, two bytes to an
+    /// entry, and any other grows up from its address.
+    fn stack_entry_address(&self, index: usize) -> Option<usize> {
+        let address = if self.vip_layout {
+            let top = self.memory.len();
+            (top - VIP_STACK_OFFSET - 1)
+                .checked_sub(2 * index)
+                .filter(|&address| address >= top - VIP_RESERVED_OFFSET)?
+        } else {
+            self.stack_address? as usize + 2 * index
+        };
+        if address + 2 <= self.memory.len() {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
+    fn stack_entry(&self, index: usize) -> u16 {
+        if !self.keeps_stack_in_memory() {
+            return self.stack[index];
+        }
+        match self.stack_entry_address(index) {
+            Some(address) => u16::from_be_bytes([self.memory[address], self.memory[address + 1]]),
+            None => 0,
+        }
+    }
+
+    /// Pushes `address` onto the stack, returning false if there isn't room for it.
+    fn push(&mut self, address: u16) -> bool {
+        if Some(self.stack_pointer) == self.stack_depth {
+            return false;
+        }
+        if self.keeps_stack_in_memory() {
+            let entry = match self.stack_entry_address(self.stack_pointer) {
+                Some(entry) => entry,
+                None => return false,
+            };
+            self.record(AccessKind::Write, entry as u16, 2);
+            self.memory[entry..entry + 2].copy_from_slice(&address.to_be_bytes());
+        } else if self.stack_pointer == self.stack.len() {
+            self.stack.push(address);
+        } else {
+            self.stack[self.stack_pointer] = address;
+        }
+        self.stack_pointer += 1;
+        true
+    }
+
+    /// Pops the top address off the stack, if there is one.
+    fn pop(&mut self) -> Option<u16> {
+        if self.stack_pointer == 0 {
+            return None;
+        }
+        self.stack_pointer -= 1;
+        if let Some(entry) = self.stack_entry_address(self.stack_pointer) {
+            self.record(AccessKind::Read, entry as u16, 2);
+        }
+        Some(self.stack_entry(self.stack_pointer))
+    }
+
+    /// All of memory, from address 0.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// All of memory, for changing it directly.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// The `length` bytes of memory starting at `address`.
+    pub fn read_memory(&self, address: u16, length: usize) -> Result<&[u8], OutOfBounds> {
+        let range = self.memory_range(address, length)?;
+        Ok(&self.memory[range])
+    }
+
+    /// Copies `bytes` into memory starting at `address`.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) -> Result<(), OutOfBounds> {
+        let range = self.memory_range(address, bytes.len())?;
+        self.memory[range].copy_from_slice(bytes);
+        self.sync_display();
+        Ok(())
+    }
+
+    fn memory_range(&self, address: u16, length: usize) -> Result<Range<usize>, OutOfBounds> {
+        let start = address as usize;
+        match start.checked_add(length) {
+            Some(end) if end <= self.memory.len() => Ok(start..end),
+            _ => Err(OutOfBounds {
+                address,
+                length,
+                size: self.memory.len(),
+            }),
+        }
+    }
+
+    /// Makes every step record the memory it accesses, for `memory_accesses`. This is off by
+    /// default as it slows the emulator down.
+    pub fn set_memory_tracking(&mut self, enabled: bool) {
+        self.tracks_memory = enabled;
+        self.memory_accesses.clear();
+    }
+
+    /// The memory accessed by the last step, in the order it was accessed, when memory tracking is
+    /// on.
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.memory_accesses
+    }
+
+    fn record(&mut self, kind: AccessKind, address: u16, length: usize) {
+        if self.tracks_memory {
+            self.memory_accesses.push(MemoryAccess {
+                kind,
+                address,
+                length: length as u16,
+            });
+        }
+    }
+
+    /// Executes a single instruction, reporting which one it was.
+    pub fn step(&mut self) -> Result<Step, ExecError> {
+        self.memory_accesses.clear();
+        self.machine_code_cycles = 0;
+        let address = self.program_counter;
+        let opcode = self.fetch(address);
+        self.record(AccessKind::Fetch, address, 2);
+        self.program_counter = self.program_counter.wrapping_add(2);
+        let result = self.decode_and_execute(opcode);
+        self.sync_display();
+        result.map(|_| Step { address, opcode })
+    }
+
+    /// Brings a display kept in memory and the framebuffer back in step, after an instruction or
+    /// anything else changed one of them. If both changed, memory wins.
+    fn sync_display(&mut self) {
+        if !self.vip_layout || !self.maps_display() {
+            return;
+        }
+        let row_size = DISPLAY_WIDTH / 8;
+        let end = self.memory.len();
+        let start = end - VIP_DISPLAY_OFFSET;
+
+        if self.memory[start..end] != self.display_in_memory[..] {
+            for (y, bytes) in self.memory[start..end].chunks(row_size).enumerate() {
+                let row = bytes.iter().fold(0, |row, &byte| row << 8 | byte as u128);
+                self.bitmap.set_row(y, row);
+            }
+        } else {
+            for (y, &row) in self.bitmap.rows().iter().enumerate() {
+                let row_start = start + y * row_size;
+                self.memory[row_start..row_start + row_size]
+                    .copy_from_slice(&row.to_be_bytes()[16 - row_size..]);
+            }
+        }
+        self.display_in_memory.clear();
+        self.display_in_memory
+            .extend_from_slice(&self.memory[start..end]);
     }
 
     /// The CHIP-8's fetch, decode, and execute instruction cycle.
-    pub fn instruction_cycle(&mut self) {
-        let opcode: u16 = self.fetch(self.program_counter);
-        self.program_counter += 2;
-        self.decode_and_execute(opcode);
-        // self.dump_debug_info();
+    pub fn instruction_cycle(&mut self) -> Result<(), ExecError> {
+        self.step().map(|_| ())
     }
 
+    /// Whether keypad key `key` is being held down: 0x0 to 0xF on the first keypad, and 0x10 to
+    /// 0x1F on the second.
+    pub fn is_key_pressed(&self, key: usize) -> bool {
+        self.keyboard[key] > 0
+    }
+
+    /// Holds keypad key `key` down. A key pressed more than once stays down until it has been
+    /// released as many times.
     pub fn key_press(&mut self, key: usize) {
-        self.keyboard[key] = true;
+        self.keyboard[key] = self.keyboard[key].saturating_add(1);
     }
 
+    /// Lets go of keypad key `key`. Releasing a key that isn't down does nothing.
     pub fn key_release(&mut self, key: usize) {
-        assert!(
-            self.keyboard[key],
-            "Attempting to release a key that wasn't registered as pressed"
-        );
-        self.keyboard[key] = false;
+        self.keyboard[key] = self.keyboard[key].saturating_sub(1);
     }
 
     pub fn pause(&mut self) {
@@ -334,6 +600,16 @@ impl Emulator {
         self.is_paused = false;
     }
 
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Whether the buzzer should be sounding, which it does for as long as the sound timer is
+    /// counting down.
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound_timer > 0
+    }
+
     pub fn tick(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -343,16 +619,30 @@ impl Emulator {
         }
     }
 
+    /// Where `address` is in memory. Machines with less than 64K mirror their memory across the
+    /// address space, and addresses past the top of 64K wrap around to the bottom.
+    fn wrap(&self, address: usize) -> usize {
+        address % self.memory.len()
+    }
+
     fn fetch(&self, program_counter: u16) -> u16 {
         let pc = program_counter as usize;
         // The instructions are stored big endian and are 16 bits large
-        let first_byte: u8 = self.memory[pc];
-        let second_byte: u8 = self.memory[pc + 1];
+        let first_byte: u8 = self.memory[self.wrap(pc)];
+        let second_byte: u8 = self.memory[self.wrap(pc + 1)];
 
         ((first_byte as u16) << 8) | (second_byte as u16)
     }
 
-    fn decode_and_execute(&mut self, opcode: u16) {
+    fn decode_and_execute(&mut self, opcode: u16) -> Result<(), ExecError> {
+        if let Some(mut extension) = self.extension.take() {
+            let result = extension.execute(self, opcode);
+            self.extension = Some(extension);
+            if result? {
+                return Ok(());
+            }
+        }
+
         match opcode & 0xF000 {
             0x000 => match opcode & 0x0FFF {
                 0x00E0 => {
@@ -361,12 +651,12 @@ impl Emulator {
                 }
                 0x00EE => {
                     // 00EE - RET
-                    self.ret();
+                    self.ret()?;
                 }
                 _ => {
                     // 0nnn - SYS addr
                     let nnn = opcode & 0x0FFF;
-                    self.sys_addr(nnn);
+                    self.sys_addr(nnn)?;
                 }
             },
             0x1000 => {
@@ -377,7 +667,7 @@ impl Emulator {
             0x2000 => {
                 // 2nnn - CALL addr
                 let nnn = opcode & 0x0FFF;
-                self.call_addr(nnn);
+                self.call_addr(nnn)?;
             }
             0x3000 => {
                 // 3xkk - SE Vx, byte
@@ -400,7 +690,7 @@ impl Emulator {
                         self.se_vx_vy(x, y);
                     }
                     _ => {
-                        panic!("Unrecognised opcode: {}", opcode);
+                        return Err(self.unrecognised(opcode));
                     }
                 }
             }
@@ -446,6 +736,9 @@ impl Emulator {
                     }
                     0x0006 => {
                         // 8xy6 - SHR Vx {, Vy}
+                        if self.quirks.shift_uses_vy {
+                            self.ld_vx_vy(x, y);
+                        }
                         self.shr_vx(x);
                     }
                     0x0007 => {
@@ -454,10 +747,13 @@ impl Emulator {
                     }
                     0x000E => {
                         // 8xyE - SHL Vx {, Vy}
+                        if self.quirks.shift_uses_vy {
+                            self.ld_vx_vy(x, y);
+                        }
                         self.shl_vx(x);
                     }
                     _ => {
-                        panic!("Unrecognised opcode: {}", opcode);
+                        return Err(self.unrecognised(opcode));
                     }
                 }
             }
@@ -470,7 +766,7 @@ impl Emulator {
                         self.sne_vx_vy(x, y);
                     }
                     _ => {
-                        panic!("Unrecognised opcode: {}", opcode);
+                        return Err(self.unrecognised(opcode));
                     }
                 }
             }
@@ -509,7 +805,7 @@ impl Emulator {
                         self.sknp_vx(x);
                     }
                     _ => {
-                        panic!("Unrecognised opcode: {}", opcode);
+                        return Err(self.unrecognised(opcode));
                     }
                 }
             }
@@ -540,6 +836,12 @@ impl Emulator {
                         // Fx29 - LD F, Vx}
                         self.ld_f_vx(x);
                     }
+                    0x0030 => {
+                        // Fx30 - LD HF, Vx
+                        if !self.ld_hf_vx(x) {
+                            return Err(self.unrecognised(opcode));
+                        }
+                    }
                     0x0033 => {
                         // Fx33 - LD B, Vx}
                         self.ld_b_vx(x);
@@ -553,72 +855,148 @@ impl Emulator {
                         self.ld_vx_i(x);
                     }
                     _ => {
-                        panic!("Unrecognised opcode: {}", opcode);
+                        return Err(self.unrecognised(opcode));
                     }
                 }
             }
             _ => {
-                panic!("Unrecognised opcode: {}", opcode);
+                return Err(self.unrecognised(opcode));
             }
         };
+
+        Ok(())
     }
 
-    fn dump_debug_info(&self) {
-        println!(
-            "Program counter: {} -> {:#06X}",
-            self.program_counter - 2,
-            self.fetch(self.program_counter - 2)
-        );
-        for i in 0..V_SIZE {
-            println!("V[{:#X}]: {}", i, self.v[i]);
+    fn unrecognised(&self, opcode: u16) -> ExecError {
+        ExecError::UnrecognisedOpcode {
+            address: self.program_counter.wrapping_sub(2),
+            opcode,
+        }
+    }
+
+    /// Runs the CDP1802 machine code routine at `nnn` the way the COSMAC VIP's interpreter did,
+    /// until it returns to the interpreter with `SEP R4`. The routine finds the interpreter's
+    /// registers where it left them: V0 to VF in memory 0x110 bytes below the top, the display in
+    /// the top 0x100 bytes, and in the CPU's registers
+    ///
+    /// - R2, the interpreter's stack, growing down from 0x131 bytes below the top,
+    /// - R5, the program counter, R6 and R7, the addresses of Vx and Vy,
+    /// - R8.1 and R8.0, the delay and sound timers, R9, a random number,
+    /// - RA, I, and RB.1, the page of the display.
+    ///
+    /// Whatever the routine changes is copied back afterwards. The keypad can be read by writing a
+    /// key to port 2 with `OUT 2` and testing whether it's held down with `B3`.
+    ///
+    /// Routines can only be run with the VIP's memory layout, so they can't overwrite the program.
+    fn sys_addr(&mut self, nnn: u16) -> Result<(), ExecError> {
+        let address = self.program_counter.wrapping_sub(2);
+        if !self.vip_layout {
+            return Err(ExecError::MachineCodeWithoutVipLayout { address });
+        }
+        let top = self.memory.len();
+        let v_address = top - VIP_V_OFFSET;
+        self.memory[v_address..v_address + V_SIZE].copy_from_slice(&self.v);
+
+        let mut cpu = Cdp1802::new();
+        // The routine's stack carries on from the program's.
+        cpu.r[2] = (top - VIP_STACK_OFFSET - 2 * self.stack_pointer) as u16;
+        cpu.r[3] = nnn;
+        cpu.r[5] = self.program_counter;
+        cpu.r[6] = (v_address + (nnn as usize >> 8 & 0xF)) as u16;
+        cpu.r[7] = (v_address + (nnn as usize >> 4 & 0xF)) as u16;
+        cpu.r[8] = u16::from_be_bytes([self.delay_timer, self.sound_timer]);
+        cpu.r[9] = self.rng.gen();
+        cpu.r[0xA] = self.i;
+        cpu.r[0xB] = ((top - VIP_DISPLAY_OFFSET) as u16) & 0xFF00;
+        cpu.p = 3;
+        cpu.x = 2;
+
+        let mut bus = MachineCodeBus {
+            memory: &mut self.memory,
+            keyboard: &self.keyboard[..KEYPAD_SIZE],
+            key: 0,
+            accesses: if self.tracks_memory {
+                Some(&mut self.memory_accesses)
+            } else {
+                None
+            },
+        };
+        let mut steps = 0;
+        while cpu.p != 4 {
+            if steps == MACHINE_CODE_STEP_LIMIT {
+                return Err(ExecError::MachineCodeTimeout { address });
+            }
+            self.machine_code_cycles += cpu.step(&mut bus);
+            steps += 1;
         }
 
-        println!();
-        println!();
+        self.v
+            .copy_from_slice(&self.memory[v_address..v_address + V_SIZE]);
+        self.program_counter = cpu.r[5];
+        self.i = cpu.r[0xA];
+        let [delay_timer, sound_timer] = cpu.r[8].to_be_bytes();
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        Ok(())
+    }
+
+    /// Whether the display can be kept in memory, which it only can in the COSMAC VIP's 64x32
+    /// mode.
+    fn maps_display(&self) -> bool {
+        self.bitmap.width() == DISPLAY_WIDTH && self.bitmap.height() == DISPLAY_HEIGHT
     }
 
-    fn sys_addr(&mut self, _nnn: u16) {
-        unimplemented!("The instruction [0nnn - SYS addr] has not been implemented.");
+    /// The CDP1802 machine cycles taken by the machine code routine called by the last step, if it
+    /// called one.
+    pub fn machine_code_cycles(&self) -> u64 {
+        self.machine_code_cycles
     }
 
     fn cls(&mut self) {
-        for row in self.bitmap.iter_mut() {
-            for pixel in row.iter_mut() {
-                *pixel = false;
-            }
-        }
+        self.bitmap.clear();
     }
 
-    fn ret(&mut self) {
-        self.stack_pointer -= 1;
-        self.program_counter = self.stack[self.stack_pointer as usize];
+    fn ret(&mut self) -> Result<(), ExecError> {
+        match self.pop() {
+            Some(address) => {
+                self.program_counter = address;
+                Ok(())
+            }
+            None => Err(ExecError::StackUnderflow {
+                address: self.program_counter.wrapping_sub(2),
+            }),
+        }
     }
 
     fn jp_addr(&mut self, nnn: u16) {
         self.program_counter = nnn;
     }
 
-    fn call_addr(&mut self, nnn: u16) {
-        self.stack[self.stack_pointer as usize] = self.program_counter;
-        self.stack_pointer += 1;
+    fn call_addr(&mut self, nnn: u16) -> Result<(), ExecError> {
+        if !self.push(self.program_counter) {
+            return Err(ExecError::StackOverflow {
+                address: self.program_counter.wrapping_sub(2),
+            });
+        }
         self.program_counter = nnn;
+        Ok(())
     }
 
     fn se_vx_byte(&mut self, x: usize, kk: u8) {
         if self.v[x] == kk {
-            self.program_counter += 2;
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
     fn sne_vx_byte(&mut self, x: usize, kk: u8) {
         if self.v[x] != kk {
-            self.program_counter += 2;
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
     fn se_vx_vy(&mut self, x: usize, y: usize) {
         if self.v[x] == self.v[y] {
-            self.program_counter += 2;
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
@@ -636,14 +1014,23 @@ impl Emulator {
 
     fn or_vx_vy(&mut self, x: usize, y: usize) {
         self.v[x] |= self.v[y];
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
     }
 
     fn and_vx_vy(&mut self, x: usize, y: usize) {
         self.v[x] &= self.v[y];
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
     }
 
     fn xor_vx_vy(&mut self, x: usize, y: usize) {
         self.v[x] ^= self.v[y];
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
     }
 
     fn add_vx_vy(&mut self, x: usize, y: usize) {
@@ -676,7 +1063,7 @@ impl Emulator {
 
     fn sne_vx_vy(&mut self, x: usize, y: usize) {
         if self.v[x] != self.v[y] {
-            self.program_counter += 2;
+            self.program_counter = self.program_counter.wrapping_add(2);
         }
     }
 
@@ -685,7 +1072,12 @@ impl Emulator {
     }
 
     fn jp_v0_addr(&mut self, nnn: u16) {
-        self.program_counter = nnn + (self.v[0x0] as u16);
+        let register = if self.quirks.jump_uses_vx {
+            (nnn >> 8) as usize
+        } else {
+            0x0
+        };
+        self.program_counter = nnn + (self.v[register] as u16);
     }
 
     fn rnd_vx_byte(&mut self, x: usize, kk: u8) {
@@ -694,26 +1086,31 @@ impl Emulator {
     }
 
     fn drw_vx_vy_nibble(&mut self, x: usize, y: usize, nibble: u8) {
-        self.v[0xF] = 0;
-
-        for byte_index in 0..nibble {
-            let byte = self.memory[(self.i + byte_index as u16) as usize];
-            for pixel_index in 0..8 {
-                let row = (self.v[y] + byte_index) as usize % 32;
-                let col = (self.v[x] + pixel_index) as usize % 64;
-                let bit = byte & (1 << (7 - pixel_index)) != 0;
-                self.v[0xF] |= (self.bitmap[row][col] && bit) as u8;
-                self.bitmap[row][col] ^= bit;
-            }
+        self.record(AccessKind::Read, self.i, nibble as usize);
+        let mut sprite = [0; 0xF];
+        for (row, byte) in sprite.iter_mut().enumerate().take(nibble as usize) {
+            *byte = self.memory[self.wrap(self.i as usize + row)];
         }
+        let sprite = &sprite[..nibble as usize];
+        let (x, y) = (self.v[x] as usize, self.v[y] as usize);
+        let collision = if self.quirks.clip_sprites {
+            self.bitmap.draw_sprite_clipped(x, y, sprite)
+        } else {
+            self.bitmap.draw_sprite(x, y, sprite)
+        };
+        self.v[0xF] = collision as u8;
     }
 
     fn skp_vx(&mut self, x: usize) {
-        self.program_counter += if self.keyboard[x] { 2 } else { 0 }
+        if self.is_key_pressed((self.v[x] & 0xF) as usize) {
+            self.program_counter = self.program_counter.wrapping_add(2);
+        }
     }
 
     fn sknp_vx(&mut self, x: usize) {
-        self.program_counter += if self.keyboard[x] { 0 } else { 2 }
+        if !self.is_key_pressed((self.v[x] & 0xF) as usize) {
+            self.program_counter = self.program_counter.wrapping_add(2);
+        }
     }
 
     fn ld_vx_dt(&mut self, x: usize) {
@@ -722,16 +1119,16 @@ impl Emulator {
 
     fn ld_vx_k(&mut self, x: usize) {
         // If no key is pressed at the moment, decrement the PC by two to stay at the same instruction.
-        self.program_counter -= if self.keyboard.iter().any(|key| *key) {
-            for (key, &key_is_pressed) in self.keyboard.iter().enumerate() {
-                if key_is_pressed {
+        let keypad = &self.keyboard[..KEYPAD_SIZE];
+        if keypad.iter().any(|&presses| presses > 0) {
+            for (key, &presses) in keypad.iter().enumerate() {
+                if presses > 0 {
                     self.v[x] = key as u8;
                 }
             }
-            0
         } else {
-            2
-        };
+            self.program_counter = self.program_counter.wrapping_sub(2);
+        }
     }
 
     fn ld_dt_vx(&mut self, x: usize) {
@@ -743,37 +1140,248 @@ impl Emulator {
     }
 
     fn add_i_vx(&mut self, x: usize) {
-        self.i += self.v[x] as u16;
+        self.i = self.i.wrapping_add(self.v[x] as u16);
     }
 
     fn ld_f_vx(&mut self, x: usize) {
-        self.i = (5 * self.v[x]) as u16;
+        self.i = self.font_address + self.font.small_glyph_offset(self.v[x]);
+    }
+
+    /// Points I at the big glyph for Vx, returning false if the font doesn't have one.
+    fn ld_hf_vx(&mut self, x: usize) -> bool {
+        match self.font.big_glyph_offset(self.v[x]) {
+            Some(offset) => {
+                self.i = self.font_address + offset;
+                true
+            }
+            None => false,
+        }
     }
 
     fn ld_b_vx(&mut self, x: usize) {
-        let i = self.i as usize;
-        self.memory[i] = self.v[x] / 100;
-        self.memory[i + 1] = (self.v[x] / 10) % 10;
-        self.memory[i + 2] = self.v[x] % 10;
+        self.record(AccessKind::Write, self.i, 3);
+        let digits = [self.v[x] / 100, (self.v[x] / 10) % 10, self.v[x] % 10];
+        for (offset, digit) in digits.iter().enumerate() {
+            let address = self.wrap(self.i as usize + offset);
+            self.memory[address] = *digit;
+        }
     }
 
     fn ld_i_vx(&mut self, x: usize) {
+        self.record(AccessKind::Write, self.i, x + 1);
         for index in 0x0..x + 1 {
-            self.memory[self.i as usize + index] = self.v[index];
+            let address = self.wrap(self.i as usize + index);
+            self.memory[address] = self.v[index];
+        }
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
         }
     }
 
     fn ld_vx_i(&mut self, x: usize) {
+        self.record(AccessKind::Read, self.i, x + 1);
         for index in 0..x + 1 {
-            self.v[index] = self.memory[self.i as usize + index];
+            self.v[index] = self.memory[self.wrap(self.i as usize + index)];
+        }
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
         }
     }
 }
 
+/// An attempt to access memory past its end.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub address: u16,
+    pub length: usize,
+    /// The size of memory.
+    pub size: usize,
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes at {:#05X} go past the end of memory at {:#05X}",
+            self.length, self.address, self.size
+        )
+    }
+}
+
+impl Error for OutOfBounds {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoadError {
+    Empty,
+    TooLarge { size: usize, capacity: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Empty => write!(f, "the ROM is empty"),
+            LoadError::TooLarge { size, capacity } => write!(
+                f,
+                "the ROM is {} bytes but only {} bytes of program space are available",
+                size, capacity
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+/// The instruction executed by `Emulator::step`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub address: u16,
+    pub opcode: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    /// An instruction being fetched for execution.
+    Fetch,
+    Read,
+    Write,
+}
+
+/// What a machine code routine's CDP1802 is connected to: the emulator's memory, mirrored across
+/// the whole address space, and the VIP's keypad.
+struct MachineCodeBus<'a> {
+    memory: &'a mut [u8],
+    keyboard: &'a [u8],
+    /// The key last written to the keypad's latch.
+    key: u8,
+    accesses: Option<&'a mut Vec<MemoryAccess>>,
+}
+
+impl MachineCodeBus<'_> {
+    fn access(&mut self, kind: AccessKind, address: u16) -> usize {
+        let address = address as usize % self.memory.len();
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(MemoryAccess {
+                kind,
+                address: address as u16,
+                length: 1,
+            });
+        }
+        address
+    }
+}
+
+impl Bus for MachineCodeBus<'_> {
+    fn read(&mut self, address: u16) -> u8 {
+        let address = self.access(AccessKind::Read, address);
+        self.memory[address]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let address = self.access(AccessKind::Write, address);
+        self.memory[address] = value;
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        if port == 2 {
+            self.key = value & 0xF;
+        }
+    }
+
+    fn flag(&mut self, line: u8) -> bool {
+        line == 3 && self.keyboard[self.key as usize] > 0
+    }
+}
+
+/// A block of memory accessed by an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub length: u16,
+}
+
+impl MemoryAccess {
+    /// Whether any of the addresses from `start` to `end` inclusive were accessed.
+    pub fn overlaps(&self, start: u16, end: u16) -> bool {
+        let last = self.address as u32 + self.length as u32;
+        (self.address as u32) <= end as u32 && (start as u32) < last
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExecError {
+    UnrecognisedOpcode {
+        address: u16,
+        opcode: u16,
+    },
+    /// A subroutine call with the stack already full.
+    StackOverflow {
+        address: u16,
+    },
+    /// A return from a subroutine with the stack empty.
+    StackUnderflow {
+        address: u16,
+    },
+    /// A machine code routine that didn't return to the interpreter.
+    MachineCodeTimeout {
+        address: u16,
+    },
+    /// A machine code routine called without the COSMAC VIP's memory layout.
+    MachineCodeWithoutVipLayout {
+        address: u16,
+    },
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::UnrecognisedOpcode { address, opcode } => {
+                write!(f, "unrecognised opcode {:#06X} at {:#05X}", opcode, address)
+            }
+            ExecError::StackOverflow { address } => {
+                write!(f, "stack overflow calling a subroutine at {:#05X}", address)
+            }
+            ExecError::StackUnderflow { address } => write!(
+                f,
+                "stack underflow returning from a subroutine at {:#05X}",
+                address
+            ),
+            ExecError::MachineCodeTimeout { address } => write!(
+                f,
+                "the machine code routine called at {:#05X} didn't return",
+                address
+            ),
+            ExecError::MachineCodeWithoutVipLayout { address } => write!(
+                f,
+                "the machine code routine called at {:#05X} needs the COSMAC VIP's memory layout",
+                address
+            ),
+        }
+    }
+}
+
+impl Error for ExecError {}
+
 // Unit tests
 #[cfg(test)]
 mod tests {
-    use super::Emulator;
+    use super::{
+        AccessKind,
+        Emulator,
+        ExecError,
+        LoadError,
+        MemoryAccess,
+        OutOfBounds,
+        Step,
+    };
+    use crate::{
+        font::Font,
+        platform::{
+            MachineConfig,
+            Platform,
+        },
+        quirks::Quirks,
+    };
 
     #[test]
     fn new() {
@@ -787,8 +1395,8 @@ mod tests {
         );
         // Memory
         assert_eq!(chip8.memory.len(), 4096);
-        assert_eq!(chip8.bitmap.len(), 32);
-        assert_eq!(chip8.bitmap[0].len(), 64);
+        assert_eq!(chip8.bitmap.height(), 32);
+        assert_eq!(chip8.bitmap.width(), 64);
         // I
         assert_eq!(chip8.i, 0);
         // V
@@ -805,47 +1413,150 @@ mod tests {
         // Display
         for x in 0..64 {
             for y in 0..32 {
-                assert_eq!(chip8.pixel_at(x, y), false);
+                assert!(!chip8.pixel_at(x, y));
             }
         }
     }
 
     #[test]
-    #[should_panic]
+    fn load_rom() {
+        let mut chip8 = Emulator::new();
+
+        chip8.load_rom(&[0x12, 0x34, 0x56]).unwrap();
+
+        assert_eq!(chip8.memory[0x200..0x204], [0x12, 0x34, 0x56, 0x00]);
+        assert_eq!(chip8.program_counter, 0x200);
+    }
+
+    #[test]
+    fn load_rom_fills_program_space() {
+        let mut chip8 = Emulator::new();
+
+        assert_eq!(chip8.load_rom(&[0xFF; 0xE00]), Ok(()));
+        assert_eq!(
+            chip8.load_rom(&[0xFF; 0xE01]),
+            Err(LoadError::TooLarge {
+                size: 0xE01,
+                capacity: 0xE00
+            })
+        );
+    }
+
+    #[test]
+    fn load_rom_empty() {
+        let mut chip8 = Emulator::new();
+
+        assert_eq!(chip8.load_rom(&[]), Err(LoadError::Empty));
+    }
+
+    #[test]
+    fn load_rom_at_load_address() {
+        let mut chip8 = Emulator::with_platform(Platform::Eti660);
+        assert_eq!(chip8.program_counter, 0x600);
+        chip8.set_load_address(0x300);
+
+        chip8.load_rom(&[0xAB; 0xD00]).unwrap();
+
+        assert_eq!(chip8.memory[0x300], 0xAB);
+        assert_eq!(chip8.memory[0xFFF], 0xAB);
+        assert_eq!(chip8.program_counter, 0x300);
+    }
+
+    #[test]
+    fn hard_reset_after_moving_the_load_address() {
+        let mut chip8 = Emulator::new();
+        chip8.set_load_address(0x300);
+        chip8.load_rom(&[0xAB; 0xD00]).unwrap();
+        chip8.set_load_address(0x600);
+
+        chip8.hard_reset();
+
+        assert_eq!(chip8.memory[0x2FF], 0x00);
+        assert_eq!(chip8.memory[0x300], 0xAB);
+        assert_eq!(chip8.memory[0xFFF], 0xAB);
+    }
+
+    #[test]
+    fn load_rom_xo_chip() {
+        let mut chip8 = Emulator::with_platform(Platform::XoChip);
+
+        assert_eq!(chip8.load_rom(&vec![0xFF; 0x10000 - 0x200]), Ok(()));
+    }
+
+    #[test]
     fn sys_addr() {
+        let mut chip8 = Emulator::with_config(MachineConfig::default().with_vip_layout());
+        chip8.load_rom(&[0x03, 0x00]).unwrap();
+        chip8.v[3] = 0x41;
+        // LDN R6; ADI 0x01; STR R6
+        // LDI 0x01; PHI RA; LDI 0x23; PLO RA
+        // LDI 0x00; PLO RB; LDI 0xF0; STR RB
+        // SEP R4
+        let routine = [
+            0x06, 0xFC, 0x01, 0x56, 0xF8, 0x01, 0xBA, 0xF8, 0x23, 0xAA, 0xF8, 0x00, 0xAB, 0xF8,
+            0xF0, 0x5B, 0xD4,
+        ];
+        chip8.write_memory(0x300, &routine).unwrap();
+
+        chip8.step().unwrap();
+
+        assert_eq!(chip8.v[3], 0x42);
+        assert_eq!(chip8.i, 0x123);
+        assert_eq!(chip8.program_counter, 0x202);
+        assert_eq!(chip8.bitmap.rows()[0], 0xF0 << 56);
+        assert_eq!(chip8.machine_code_cycles(), 12 * 2);
+    }
+
+    #[test]
+    fn sys_addr_without_return() {
+        let mut chip8 = Emulator::with_config(MachineConfig::default().with_vip_layout());
+        chip8.load_rom(&[0x03, 0x00]).unwrap();
+        // BR 0x00
+        chip8.write_memory(0x300, &[0x30, 0x00]).unwrap();
+
+        assert_eq!(
+            chip8.step(),
+            Err(ExecError::MachineCodeTimeout { address: 0x200 })
+        );
+    }
+
+    #[test]
+    fn sys_addr_without_vip_layout() {
         let mut chip8 = Emulator::new();
+        chip8.load_rom(&[0x03, 0x00]).unwrap();
 
-        chip8.sys_addr(0);
+        assert_eq!(
+            chip8.step(),
+            Err(ExecError::MachineCodeWithoutVipLayout { address: 0x200 })
+        );
+        assert!(chip8.memory[0xEF0..].iter().all(|&byte| byte == 0));
     }
 
     #[test]
     fn cls() {
         let mut chip8 = Emulator::new();
-        for row in chip8.bitmap.iter_mut() {
-            for pixel in row.iter_mut() {
-                *pixel = true;
+        for y in 0..32 {
+            for x in (0..64).step_by(8) {
+                chip8.bitmap.draw_sprite_row(x, y, 0xFF);
             }
         }
 
         chip8.cls();
 
-        assert!(chip8
-            .bitmap
-            .iter()
-            .all(|&row| row.iter().all(|&pixel| !pixel)));
+        assert!(chip8.bitmap.rows().iter().all(|&row| row == 0));
     }
 
     #[test]
     fn ret() {
         let mut chip8 = Emulator::new();
         // Simulate entering a subroutine
-        chip8.stack[chip8.stack_pointer as usize] = chip8.program_counter;
-        let old_address: u16 = chip8.stack[chip8.stack_pointer as usize];
+        chip8.stack[chip8.stack_pointer] = chip8.program_counter;
+        let old_address: u16 = chip8.stack[chip8.stack_pointer];
         chip8.stack_pointer += 1;
         // Set program counter to a different address
         chip8.program_counter = 0xF03D;
 
-        chip8.ret();
+        chip8.ret().unwrap();
 
         assert_eq!(chip8.stack_pointer, 0);
         assert_eq!(chip8.program_counter, old_address);
@@ -866,11 +1577,181 @@ mod tests {
         let mut chip8 = Emulator::new();
         let nnn: u16 = 0x2E6;
 
-        chip8.call_addr(nnn);
+        chip8.call_addr(nnn).unwrap();
 
         assert_eq!(chip8.program_counter, nnn);
         assert_eq!(chip8.stack_pointer, 1);
-        assert_eq!(chip8.stack[(chip8.stack_pointer - 1) as usize], 0x200);
+        assert_eq!(chip8.stack[chip8.stack_pointer - 1], 0x200);
+    }
+
+    #[test]
+    fn stack_overflow_and_underflow() {
+        let mut chip8 = Emulator::with_config(MachineConfig {
+            stack_depth: Some(12),
+            ..MachineConfig::default()
+        });
+        // CALL 0x200
+        chip8.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..12 {
+            chip8.step().unwrap();
+        }
+
+        assert_eq!(
+            chip8.step(),
+            Err(ExecError::StackOverflow { address: 0x200 })
+        );
+        assert_eq!(chip8.stack_pointer, 12);
+
+        let mut chip8 = Emulator::new();
+        // RET
+        chip8.load_rom(&[0x00, 0xEE]).unwrap();
+        assert_eq!(
+            chip8.step(),
+            Err(ExecError::StackUnderflow { address: 0x200 })
+        );
+    }
+
+    #[test]
+    fn unlimited_stack() {
+        let mut chip8 = Emulator::with_config(MachineConfig {
+            stack_depth: None,
+            ..MachineConfig::default()
+        });
+        chip8.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..100 {
+            chip8.step().unwrap();
+        }
+
+        assert_eq!(chip8.stack_pointer(), 100);
+        assert_eq!(chip8.stack().len(), 100);
+        assert_eq!(chip8.call_stack()[99], 0x202);
+    }
+
+    #[test]
+    fn memory_wraps_around() {
+        let mut chip8 = Emulator::with_config(MachineConfig {
+            memory_size: MachineConfig::MEMORY_2K,
+            ..MachineConfig::default()
+        });
+        // LD V0, 0xAB; LD V1, 0xCD; LD I, 0xFFF; LD [I], V1; LD V0, 0x00; LD V1, 0x00
+        // LD V1, [I]; DRW V2, V2, 2; JP 0x900
+        chip8
+            .load_rom(&[
+                0x60, 0xAB, 0x61, 0xCD, 0xAF, 0xFF, 0xF1, 0x55, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x65,
+                0xD2, 0x22, 0x19, 0x00,
+            ])
+            .unwrap();
+        // JP 0x200, where JP 0x900 ends up.
+        chip8.memory[0x100..0x102].copy_from_slice(&[0x12, 0x00]);
+
+        for _ in 0..9 {
+            chip8.step().unwrap();
+        }
+
+        assert_eq!(chip8.memory[0x7FF], 0xAB);
+        assert_eq!(chip8.memory[0x000], 0xCD);
+        assert_eq!(chip8.v[..2], [0xAB, 0xCD]);
+        assert_eq!(chip8.framebuffer().rows()[0] >> 56, 0xAB);
+        assert_eq!(chip8.framebuffer().rows()[1] >> 56, 0xCD);
+        assert_eq!(chip8.program_counter, 0x900);
+        assert_eq!(chip8.next_opcode(), 0x1200);
+        chip8.step().unwrap();
+        assert_eq!(chip8.program_counter, 0x200);
+    }
+
+    #[test]
+    fn stack_in_memory() {
+        let mut chip8 = Emulator::with_config(MachineConfig {
+            stack_depth: Some(12),
+            stack_address: Some(0xEA0),
+            ..MachineConfig::default()
+        });
+        // 0x200: CALL 0x204
+        // 0x202: JP 0x202
+        // 0x204: RET
+        chip8
+            .load_rom(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE])
+            .unwrap();
+
+        chip8.step().unwrap();
+        assert_eq!(&chip8.memory[0xEA0..0xEA2], &[0x02, 0x02]);
+        assert_eq!(chip8.call_stack(), &[0x202]);
+
+        // Programs can change their return addresses.
+        chip8.memory[0xEA1] = 0x00;
+        chip8.step().unwrap();
+        assert_eq!(chip8.program_counter, 0x200);
+        assert_eq!(chip8.stack().len(), 12);
+
+        assert!(chip8.load_rom(&[0; 0xCA0]).is_ok());
+        assert_eq!(
+            chip8.load_rom(&[0; 0xCA1]),
+            Err(LoadError::TooLarge {
+                size: 0xCA1,
+                capacity: 0xCA0
+            })
+        );
+    }
+
+    #[test]
+    fn display_in_memory() {
+        let mut chip8 = Emulator::with_config(MachineConfig::default().with_vip_layout());
+        // LD V0, 0x08; LD F, V0; DRW V0, V0, 5; LD I, 0xF01; LD V0, 0xFF; LD [I], V0; CLS
+        chip8
+            .load_rom(&[
+                0x60, 0x08, 0xF0, 0x29, 0xD0, 0x05, 0xAF, 0x01, 0x60, 0xFF, 0xF0, 0x55, 0x00, 0xE0,
+            ])
+            .unwrap();
+
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        // The top of the 8 is drawn at (8, 8), which is the ninth byte of the ninth row.
+        assert_eq!(chip8.memory[0xF00 + 8 * 8 + 1], 0xF0);
+
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert!((8..16).all(|x| chip8.pixel_at(x, 0)));
+
+        chip8.step().unwrap();
+        assert!(chip8.memory[0xF00..0x1000].iter().all(|&byte| byte == 0));
+
+        assert!(chip8.load_rom(&[0; 0xCA0]).is_ok());
+        assert!(chip8.load_rom(&[0; 0xCA1]).is_err());
+    }
+
+    #[test]
+    fn vip_layout_at_the_top_of_memory() {
+        let config = MachineConfig {
+            memory_size: MachineConfig::MEMORY_2K,
+            ..MachineConfig::default()
+        };
+        let mut chip8 = Emulator::with_config(config.with_vip_layout());
+        assert!(chip8.load_rom(&[0; 0x4A0]).is_ok());
+        assert!(chip8.load_rom(&[0; 0x4A1]).is_err());
+
+        // CALL 0x204; CALL 0x206; LD V0, 0xFF; LD I, 0x700; LD [I], V0
+        chip8
+            .load_rom(&[
+                0x22, 0x04, 0x00, 0x00, 0x22, 0x06, 0x60, 0xFF, 0xA7, 0x00, 0xF0, 0x55,
+            ])
+            .unwrap();
+        for _ in 0..5 {
+            chip8.step().unwrap();
+        }
+        // The stack grows down from 0x6CF, and the display starts at 0x700.
+        assert_eq!(chip8.memory[0x6CE..0x6D0], [0x02, 0x02]);
+        assert_eq!(chip8.memory[0x6CC..0x6CE], [0x02, 0x06]);
+        assert!((0..8).all(|x| chip8.pixel_at(x, 0)));
+
+        // The stack is 12 entries deep.
+        chip8.reset();
+        chip8.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..12 {
+            chip8.step().unwrap();
+        }
+        assert!(chip8.step().is_err());
     }
 
     #[test]
@@ -1210,19 +2091,310 @@ mod tests {
         assert_eq!(chip8.program_counter, 0x2F3);
     }
 
+    #[test]
+    fn drw_vx_vy_nibble() {
+        let mut chip8 = Emulator::new();
+        // The font sprite for 0
+        chip8.i = 0;
+        chip8.v[0x1] = 62;
+        chip8.v[0x2] = 30;
+
+        chip8.drw_vx_vy_nibble(0x1, 0x2, 5);
+
+        assert_eq!(chip8.v[0xF], 0);
+        // The sprite wraps around both edges of the display
+        assert!(chip8.pixel_at(62, 30));
+        assert!(chip8.pixel_at(1, 30));
+        assert!(!chip8.pixel_at(2, 30));
+        assert!(chip8.pixel_at(62, 31));
+        assert!(!chip8.pixel_at(63, 31));
+        assert!(chip8.pixel_at(1, 2));
+    }
+
+    #[test]
+    fn drw_vx_vy_nibble_collision() {
+        let mut chip8 = Emulator::new();
+        chip8.i = 0;
+
+        chip8.drw_vx_vy_nibble(0x0, 0x0, 5);
+        chip8.drw_vx_vy_nibble(0x0, 0x0, 5);
+
+        assert_eq!(chip8.v[0xF], 1);
+        assert!(chip8.bitmap.rows().iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn step() {
+        let mut chip8 = Emulator::new();
+        chip8.load_rom(&[0x63, 0x2A, 0x22, 0x08]).unwrap();
+
+        assert_eq!(
+            chip8.step(),
+            Ok(Step {
+                address: 0x200,
+                opcode: 0x632A
+            })
+        );
+        assert_eq!(chip8.registers()[0x3], 0x2A);
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.program_counter(), 0x208);
+        assert_eq!(chip8.call_stack(), &[0x204]);
+    }
+
+    #[test]
+    fn memory_tracking() {
+        let mut chip8 = Emulator::new();
+        // LD I, 0x300; LD [I], V2; DRW V0, V0, 4
+        chip8
+            .load_rom(&[0xA3, 0x00, 0xF2, 0x55, 0xD0, 0x04])
+            .unwrap();
+
+        chip8.step().unwrap();
+        assert!(chip8.memory_accesses().is_empty());
+
+        chip8.set_memory_tracking(true);
+        chip8.step().unwrap();
+        assert_eq!(
+            chip8.memory_accesses(),
+            &[
+                MemoryAccess {
+                    kind: AccessKind::Fetch,
+                    address: 0x202,
+                    length: 2
+                },
+                MemoryAccess {
+                    kind: AccessKind::Write,
+                    address: 0x300,
+                    length: 3
+                },
+            ]
+        );
+        assert!(chip8.memory_accesses()[1].overlaps(0x302, 0x310));
+        assert!(!chip8.memory_accesses()[1].overlaps(0x303, 0x310));
+
+        chip8.step().unwrap();
+        assert_eq!(
+            chip8.memory_accesses()[1],
+            MemoryAccess {
+                kind: AccessKind::Read,
+                address: 0x300,
+                length: 4
+            }
+        );
+    }
+
+    #[test]
+    fn unrecognised_opcode() {
+        let mut chip8 = Emulator::new();
+        chip8.load_rom(&[0x00, 0xE0, 0x81, 0x2F]).unwrap();
+
+        assert_eq!(chip8.instruction_cycle(), Ok(()));
+        assert_eq!(
+            chip8.instruction_cycle(),
+            Err(ExecError::UnrecognisedOpcode {
+                address: 0x202,
+                opcode: 0x812F
+            })
+        );
+    }
+
+    #[test]
+    fn quirk_shift_uses_vy() {
+        let mut chip8 = Emulator::new();
+        chip8.set_quirks(Quirks::VIP);
+        chip8.v[0x1] = 0b0000_0001;
+        chip8.v[0x2] = 0b1000_0010;
+        // 8126 - SHR V1, V2
+        chip8.load_rom(&[0x81, 0x26]).unwrap();
+
+        chip8.instruction_cycle().unwrap();
+
+        assert_eq!(chip8.v[0x1], 0b0100_0001);
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn quirk_load_store_increments_i() {
+        let mut chip8 = Emulator::new();
+        chip8.set_quirks(Quirks::VIP);
+        chip8.i = 0x300;
+
+        chip8.ld_i_vx(3);
+        assert_eq!(chip8.i, 0x304);
+
+        chip8.ld_vx_i(1);
+        assert_eq!(chip8.i, 0x306);
+    }
+
+    #[test]
+    fn quirk_jump_uses_vx() {
+        let mut chip8 = Emulator::new();
+        chip8.set_quirks(Quirks::SUPER_CHIP);
+        chip8.v[0x0] = 0x01;
+        chip8.v[0x3] = 0x10;
+
+        chip8.jp_v0_addr(0x345);
+
+        assert_eq!(chip8.program_counter, 0x355);
+    }
+
+    #[test]
+    fn quirk_logic_resets_vf() {
+        let mut chip8 = Emulator::new();
+        chip8.set_quirks(Quirks::VIP);
+        chip8.v[0xF] = 1;
+
+        chip8.or_vx_vy(0x0, 0x1);
+
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn quirk_clip_sprites() {
+        let mut chip8 = Emulator::new();
+        chip8.set_quirks(Quirks::VIP);
+        chip8.i = 0;
+        chip8.v[0x1] = 62;
+        chip8.v[0x2] = 30;
+
+        chip8.drw_vx_vy_nibble(0x1, 0x2, 5);
+
+        assert!(chip8.pixel_at(62, 30));
+        assert!(!chip8.pixel_at(0, 30));
+        assert!(!chip8.pixel_at(62, 0));
+    }
+
+    #[test]
+    fn seed() {
+        let mut first = Emulator::new();
+        let mut second = Emulator::new();
+        first.seed(42);
+        second.seed(42);
+
+        for _ in 0..16 {
+            first.rnd_vx_byte(0x0, 0xFF);
+            second.rnd_vx_byte(0x0, 0xFF);
+            assert_eq!(first.v[0x0], second.v[0x0]);
+        }
+    }
+
+    #[test]
+    fn introspection() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_rom(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE])
+            .unwrap();
+        emulator.step().unwrap();
+        emulator.step().unwrap();
+
+        assert_eq!(emulator.call_stack(), &[] as &[u16]);
+        assert_eq!(emulator.stack().len(), 16);
+        assert_eq!(emulator.stack()[0], 0x202);
+
+        assert_eq!(emulator.write_memory(0x300, &[1, 2, 3]), Ok(()));
+        assert_eq!(emulator.read_memory(0x301, 2), Ok(&[2, 3][..]));
+        assert_eq!(
+            emulator.read_memory(0xFFF, 2),
+            Err(OutOfBounds {
+                address: 0xFFF,
+                length: 2,
+                size: 0x1000
+            })
+        );
+        assert!(emulator.write_memory(0xFFE, &[0; 3]).is_err());
+
+        emulator.key_press(0xA);
+        assert!(emulator.is_key_pressed(0xA));
+        assert!(!emulator.is_key_pressed(0xB));
+
+        emulator.set_register(0x0, 0);
+        emulator.set_i(0x0);
+        emulator.drw_vx_vy_nibble(0x0, 0x0, 1);
+        assert_eq!(emulator.framebuffer().rows()[0] >> 60, 0xF);
+    }
+
+    #[test]
+    fn key_held_by_two_bindings() {
+        let mut emulator = Emulator::new();
+
+        // Two keyboard keys bound to keypad key 5, pressed one after the other.
+        emulator.key_press(0x5);
+        emulator.key_press(0x5);
+        emulator.key_release(0x5);
+        assert!(emulator.is_key_pressed(0x5));
+
+        emulator.key_release(0x5);
+        assert!(!emulator.is_key_pressed(0x5));
+        emulator.key_release(0x5);
+        assert!(!emulator.is_key_pressed(0x5));
+    }
+
+    #[test]
+    fn reset() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_rom(&[0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x22, 0x00])
+            .unwrap();
+        for _ in 0..4 {
+            emulator.step().unwrap();
+        }
+        emulator.set_delay_timer(10);
+        emulator.bitmap.draw_sprite_row(0, 0, 0xFF);
+        emulator.memory[0x000] = 0x00;
+        emulator.memory[0x201] = 0x06;
+
+        emulator.reset();
+
+        assert_eq!(emulator.program_counter, 0x200);
+        assert_eq!(emulator.v, [0; 16]);
+        assert_eq!(emulator.i, 0);
+        assert_eq!(emulator.stack_pointer, 0);
+        assert_eq!(emulator.stack, [0; 16]);
+        assert_eq!(emulator.delay_timer, 0);
+        assert!(!emulator.pixel_at(0, 0));
+        assert_eq!(emulator.memory[0x201], 0x06);
+        assert_eq!(emulator.memory[0x300], 0x05);
+
+        emulator.hard_reset();
+
+        assert_eq!(emulator.program_counter, 0x200);
+        assert_eq!(emulator.memory[0x000], 0xF0);
+        assert_eq!(emulator.memory[0x201], 0x05);
+        assert_eq!(emulator.memory[0x300], 0x00);
+    }
+
     #[test]
     fn ld_vx_dt() {
-        unimplemented!();
+        let mut chip8 = Emulator::new();
+        chip8.set_delay_timer(0x3C);
+
+        chip8.ld_vx_dt(0x5);
+
+        assert_eq!(chip8.v[0x5], 0x3C);
+        assert_eq!(chip8.delay_timer(), 0x3C);
     }
 
     #[test]
     fn ld_dt_vx() {
-        unimplemented!();
+        let mut chip8 = Emulator::new();
+        chip8.v[0x5] = 0x3C;
+
+        chip8.ld_dt_vx(0x5);
+
+        assert_eq!(chip8.delay_timer(), 0x3C);
+        assert_eq!(chip8.sound_timer(), 0x00);
     }
 
     #[test]
     fn ld_st_vx() {
-        unimplemented!();
+        let mut chip8 = Emulator::new();
+        chip8.v[0x5] = 0x3C;
+
+        chip8.ld_st_vx(0x5);
+
+        assert_eq!(chip8.sound_timer(), 0x3C);
+        assert_eq!(chip8.delay_timer(), 0x00);
     }
 
     #[test]
@@ -1230,7 +2402,7 @@ mod tests {
         let mut chip8 = Emulator::new();
         let x: usize = 3;
         chip8.v[x] = 10;
-        chip8.i = 1 as u16;
+        chip8.i = 1;
 
         chip8.add_i_vx(x);
 
@@ -1239,7 +2411,41 @@ mod tests {
 
     #[test]
     fn ld_f_vx() {
-        unimplemented!();
+        let mut chip8 = Emulator::new();
+        chip8.v[0x2] = 0xA;
+        chip8.v[0x3] = 0xFF;
+
+        chip8.ld_f_vx(0x2);
+        assert_eq!(chip8.i, 50);
+        assert_eq!(&chip8.memory[50..55], &[0xF0, 0x90, 0xF0, 0x90, 0x90]);
+
+        // Only the low nibble selects a digit.
+        chip8.ld_f_vx(0x3);
+        assert_eq!(chip8.i, 75);
+    }
+
+    #[test]
+    fn set_font() {
+        let mut chip8 = Emulator::new();
+        chip8.set_font("schip".parse().unwrap(), 0x050).unwrap();
+        chip8.v[0x2] = 0x7;
+
+        assert_eq!(chip8.font_address(), 0x050);
+        assert_eq!(chip8.memory[0x000], 0x00);
+        chip8.ld_f_vx(0x2);
+        assert_eq!(chip8.i, 0x050 + 35);
+        assert!(chip8.ld_hf_vx(0x2));
+        assert_eq!(chip8.i, 0x050 + 80 + 70);
+        assert_eq!(chip8.memory[chip8.i as usize], 0xFF);
+
+        chip8.set_font(Font::default(), 0x000).unwrap();
+        assert!(!chip8.ld_hf_vx(0x2));
+        assert_eq!(chip8.memory[0x050 + 80], 0x00);
+        assert!(chip8.set_font(Font::default(), 0xFF0).is_err());
+
+        chip8.memory[0x000] = 0x00;
+        chip8.hard_reset();
+        assert_eq!(chip8.memory[0x000], 0xF0);
     }
 
     #[test]
@@ -1258,11 +2464,49 @@ mod tests {
 
     #[test]
     fn ld_i_vx() {
-        unimplemented!();
+        let mut chip8 = Emulator::new();
+        for x in 0..4 {
+            chip8.v[x] = 0x10 + x as u8;
+        }
+        chip8.i = 0x300;
+
+        chip8.ld_i_vx(0x2);
+
+        assert_eq!(chip8.memory[0x300..0x304], [0x10, 0x11, 0x12, 0x00]);
+        assert_eq!(chip8.i, 0x300);
+
+        chip8.set_quirks(Quirks::VIP);
+        chip8.ld_i_vx(0x2);
+        assert_eq!(chip8.i, 0x303);
+
+        // Stores past the top of memory wrap around to the bottom.
+        chip8.i = 0xFFE;
+        chip8.ld_i_vx(0x3);
+        assert_eq!(chip8.memory[0xFFE..0x1000], [0x10, 0x11]);
+        assert_eq!(chip8.memory[0x000..0x002], [0x12, 0x13]);
+        assert_eq!(chip8.i, 0x1002);
     }
 
     #[test]
     fn ld_vx_i() {
-        unimplemented!();
+        let mut chip8 = Emulator::new();
+        chip8.memory[0x300..0x304].copy_from_slice(&[0x10, 0x11, 0x12, 0x13]);
+        chip8.i = 0x300;
+
+        chip8.ld_vx_i(0x2);
+
+        assert_eq!(chip8.v[0x0..0x4], [0x10, 0x11, 0x12, 0x00]);
+        assert_eq!(chip8.i, 0x300);
+
+        chip8.set_quirks(Quirks::VIP);
+        chip8.ld_vx_i(0x2);
+        assert_eq!(chip8.i, 0x303);
+
+        // Loads past the top of memory wrap around to the bottom, where the font is.
+        chip8.memory[0xFFE..0x1000].copy_from_slice(&[0xAA, 0xBB]);
+        chip8.i = 0xFFE;
+        chip8.ld_vx_i(0x3);
+        assert_eq!(chip8.v[0x0..0x4], [0xAA, 0xBB, 0xF0, 0x90]);
+        assert_eq!(chip8.i, 0x1002);
     }
 }
@@ -0,0 +1,380 @@
+use std::{
+    error::Error,
+    fmt,
+};
+
+use crate::{
+    asm::parse_number,
+    emulator::Emulator,
+};
+
+/// An error in an expression, or in evaluating one.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExprError(String);
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ExprError {}
+
+fn error<T>(message: String) -> Result<T, ExprError> {
+    Err(ExprError(message))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Register {
+    V(usize),
+    I,
+    Pc,
+    /// The depth of the call stack.
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+/// The binary operators with their precedence, which is the same as in C: higher binds tighter.
+/// Longer operators come before their prefixes so that they're matched first.
+const BINARY_OPS: [(&str, BinaryOp, u8); 18] = [
+    ("||", BinaryOp::Or, 1),
+    ("&&", BinaryOp::And, 2),
+    ("==", BinaryOp::Equal, 6),
+    ("!=", BinaryOp::NotEqual, 6),
+    ("<=", BinaryOp::LessEqual, 7),
+    (">=", BinaryOp::GreaterEqual, 7),
+    ("<<", BinaryOp::ShiftLeft, 8),
+    (">>", BinaryOp::ShiftRight, 8),
+    ("|", BinaryOp::BitOr, 3),
+    ("^", BinaryOp::BitXor, 4),
+    ("&", BinaryOp::BitAnd, 5),
+    ("<", BinaryOp::Less, 7),
+    (">", BinaryOp::Greater, 7),
+    ("+", BinaryOp::Add, 9),
+    ("-", BinaryOp::Subtract, 9),
+    ("*", BinaryOp::Multiply, 10),
+    ("/", BinaryOp::Divide, 10),
+    ("%", BinaryOp::Remainder, 10),
+];
+
+/// An expression over the emulator's state, like `V3 == 0x10 && I > 0x300`. Expressions are made
+/// of numbers, the registers `V0`-`VF`, `I`, `PC`, `SP` (the depth of the call stack), `DT` and
+/// `ST`, memory bytes written as `[address]`, parentheses, and C's arithmetic, comparison and
+/// logical operators. Comparisons and logical operators produce 1 for true and 0 for false.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expr(Node);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            source,
+            position: 0,
+        };
+        let node = parser.binary(0)?;
+        parser.skip_whitespace();
+        if parser.position < source.len() {
+            return error(format!("unexpected '{}'", parser.rest()));
+        }
+        Ok(Expr(node))
+    }
+
+    pub fn evaluate(&self, emulator: &Emulator) -> Result<i64, ExprError> {
+        self.0.evaluate(emulator)
+    }
+
+    /// Evaluates the expression as a condition, which holds if it isn't zero.
+    pub fn holds(&self, emulator: &Emulator) -> Result<bool, ExprError> {
+        self.evaluate(emulator).map(|value| value != 0)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, emulator: &Emulator) -> Result<i64, ExprError> {
+        let value = match self {
+            Node::Number(number) => *number,
+            Node::Register(register) => match register {
+                Register::V(x) => emulator.registers()[*x] as i64,
+                Register::I => emulator.i() as i64,
+                Register::Pc => emulator.program_counter() as i64,
+                Register::Sp => emulator.stack_pointer() as i64,
+                Register::Dt => emulator.delay_timer() as i64,
+                Register::St => emulator.sound_timer() as i64,
+            },
+            Node::Memory(address) => {
+                let address = address.evaluate(emulator)?;
+                match emulator.memory().get(address as usize) {
+                    Some(&byte) if address >= 0 => byte as i64,
+                    _ => return error(format!("{:#X} is outside of memory", address)),
+                }
+            }
+            Node::Unary(op, operand) => {
+                let operand = operand.evaluate(emulator)?;
+                match op {
+                    UnaryOp::Not => (operand == 0) as i64,
+                    UnaryOp::Negate => operand.wrapping_neg(),
+                    UnaryOp::Complement => !operand,
+                }
+            }
+            Node::Binary(BinaryOp::And, left, right) => {
+                (left.evaluate(emulator)? != 0 && right.evaluate(emulator)? != 0) as i64
+            }
+            Node::Binary(BinaryOp::Or, left, right) => {
+                (left.evaluate(emulator)? != 0 || right.evaluate(emulator)? != 0) as i64
+            }
+            Node::Binary(op, left, right) => {
+                let (left, right) = (left.evaluate(emulator)?, right.evaluate(emulator)?);
+                match op {
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::BitXor => left ^ right,
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::Equal => (left == right) as i64,
+                    BinaryOp::NotEqual => (left != right) as i64,
+                    BinaryOp::Less => (left < right) as i64,
+                    BinaryOp::LessEqual => (left <= right) as i64,
+                    BinaryOp::Greater => (left > right) as i64,
+                    BinaryOp::GreaterEqual => (left >= right) as i64,
+                    BinaryOp::ShiftLeft => left.wrapping_shl(right as u32),
+                    BinaryOp::ShiftRight => left.wrapping_shr(right as u32),
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Subtract => left.wrapping_sub(right),
+                    BinaryOp::Multiply => left.wrapping_mul(right),
+                    BinaryOp::Divide | BinaryOp::Remainder if right == 0 => {
+                        return error(String::from("division by zero"))
+                    }
+                    BinaryOp::Divide => left.wrapping_div(right),
+                    BinaryOp::Remainder => left.wrapping_rem(right),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        if self.eat(token) {
+            Ok(())
+        } else if self.rest().is_empty() {
+            error(format!("expected '{}' at the end", token))
+        } else {
+            error(format!("expected '{}' but found '{}'", token, self.rest()))
+        }
+    }
+
+    /// Parses a chain of binary operators that bind tighter than `min_precedence`.
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, ExprError> {
+        let mut left = self.unary()?;
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            let op = BINARY_OPS
+                .iter()
+                .find(|(token, _, precedence)| {
+                    *precedence > min_precedence && rest.starts_with(token)
+                })
+                // `|` and `&` mustn't be mistaken for the start of `||` and `&&`.
+                .filter(|(token, _, _)| {
+                    !(token.len() == 1 && rest[1..].starts_with(*token) && "|&".contains(*token))
+                });
+            let (token, op, precedence) = match op {
+                Some(&op) => op,
+                None => return Ok(left),
+            };
+            self.position += token.len();
+            let right = self.binary(precedence)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        let op = if self.eat("!") {
+            UnaryOp::Not
+        } else if self.eat("-") {
+            UnaryOp::Negate
+        } else if self.eat("~") {
+            UnaryOp::Complement
+        } else {
+            return self.primary();
+        };
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat("[") {
+            let address = self.binary(0)?;
+            self.expect("]")?;
+            return Ok(Node::Memory(Box::new(address)));
+        }
+
+        self.skip_whitespace();
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let word = &rest[..length];
+        if word.is_empty() {
+            if rest.is_empty() {
+                return error(String::from("expected a value at the end"));
+            }
+            return error(format!("expected a value but found '{}'", rest));
+        }
+        self.position += length;
+
+        if let Some(number) = parse_number(word) {
+            return Ok(Node::Number(number as i64));
+        }
+        let register = match word.to_ascii_uppercase().as_str() {
+            "I" => Register::I,
+            "PC" => Register::Pc,
+            "SP" => Register::Sp,
+            "DT" => Register::Dt,
+            "ST" => Register::St,
+            name if name.len() == 2 && name.starts_with('V') => {
+                match usize::from_str_radix(&name[1..], 16) {
+                    Ok(x) => Register::V(x),
+                    Err(_) => return error(format!("'{}' is not a register", word)),
+                }
+            }
+            _ => return error(format!("'{}' is not a number or a register", word)),
+        };
+        Ok(Node::Register(register))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::Expr;
+    use crate::emulator::Emulator;
+
+    fn evaluate(source: &str, emulator: &Emulator) -> i64 {
+        Expr::parse(source).unwrap().evaluate(emulator).unwrap()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let emulator = Emulator::new();
+
+        assert_eq!(evaluate("1 + 2 * 3", &emulator), 7);
+        assert_eq!(evaluate("(1 + 2) * 3", &emulator), 9);
+        assert_eq!(evaluate("10 - 4 - 3", &emulator), 3);
+        assert_eq!(evaluate("0x10 >> 2 | 1", &emulator), 5);
+        assert_eq!(evaluate("-3 % 2", &emulator), -1);
+        assert_eq!(evaluate("!0 + ~0", &emulator), 0);
+        assert_eq!(evaluate("6 & 3 ^ 1", &emulator), 3);
+    }
+
+    #[test]
+    fn conditions() {
+        let mut emulator = Emulator::new();
+        emulator.set_register(0x3, 0x10);
+        emulator.set_i(0x301);
+
+        let condition = Expr::parse("V3 == 0x10 && I > 0x300").unwrap();
+        assert!(condition.holds(&emulator).unwrap());
+
+        emulator.set_i(0x300);
+        assert!(!condition.holds(&emulator).unwrap());
+        assert!(Expr::parse("v3 != 16 || pc == 0x200")
+            .unwrap()
+            .holds(&emulator)
+            .unwrap());
+    }
+
+    #[test]
+    fn memory() {
+        let mut emulator = Emulator::new();
+        emulator.memory_mut()[0x300] = 42;
+        emulator.set_i(0x2FF);
+
+        assert_eq!(evaluate("[I + 1]", &emulator), 42);
+        assert_eq!(evaluate("[0]", &emulator), 0xF0);
+        assert!(Expr::parse("[0x1000]")
+            .unwrap()
+            .evaluate(&emulator)
+            .is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Expr::parse("").is_err());
+        assert!(Expr::parse("V3 ==").is_err());
+        assert!(Expr::parse("(1 + 2").is_err());
+        assert!(Expr::parse("VG").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("score > 3").is_err());
+        assert!(Expr::parse("1 / 0")
+            .unwrap()
+            .evaluate(&Emulator::new())
+            .is_err());
+    }
+}
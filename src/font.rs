@@ -0,0 +1,291 @@
+use std::{
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
+/// The size of a small glyph in bytes: 4 pixels wide, drawn in the high nibble, and 5 high.
+pub const SMALL_GLYPH_SIZE: usize = 5;
+/// The size of a big glyph in bytes: 8 pixels wide and 10 high.
+pub const BIG_GLYPH_SIZE: usize = 10;
+
+/// The hex digits interpreters keep in memory for `LD F, Vx`, and on SUPER-CHIP and later the big
+/// digits for `LD HF, Vx`. Interpreters drew their glyphs differently, and some programs depend on
+/// the exact shapes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Font {
+    /// The 16 small glyphs, 0 to F.
+    small: Vec<u8>,
+    /// The big glyphs, 0 to 9 on SUPER-CHIP and 0 to F on XO-CHIP, if there are any.
+    big: Vec<u8>,
+}
+
+const CHIP_48: [u8; 16 * SMALL_GLYPH_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const VIP: [u8; 16 * SMALL_GLYPH_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const DREAM_6800: [u8; 16 * SMALL_GLYPH_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+const ETI_660: [u8; 16 * SMALL_GLYPH_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0x80, 0x80, 0xE0, 0xA0, 0xE0, // b
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0x20, 0x20, 0xE0, 0xA0, 0xE0, // d
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+const SUPER_CHIP_BIG: [u8; 10 * BIG_GLYPH_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+const OCTO_BIG: [u8; 16 * BIG_GLYPH_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// Built-in fonts, as their small glyphs and big glyphs.
+const PRESETS: [(&str, &[u8], &[u8]); 6] = [
+    ("vip", &VIP, &[]),
+    ("dream6800", &DREAM_6800, &[]),
+    ("eti660", &ETI_660, &[]),
+    ("chip48", &CHIP_48, &[]),
+    ("schip", &CHIP_48, &SUPER_CHIP_BIG),
+    ("octo", &CHIP_48, &OCTO_BIG),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FontError {
+    UnknownFont(String),
+    /// A font file that isn't 16 small glyphs, optionally followed by 10 or 16 big ones.
+    InvalidSize(usize),
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::UnknownFont(name) => write!(
+                f,
+                "unknown font '{}', expected one of: {}",
+                name,
+                PRESETS
+                    .iter()
+                    .map(|(name, _, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            FontError::InvalidSize(size) => write!(
+                f,
+                "a font file should be {} bytes of small glyphs, optionally followed by {} or {} \
+                 bytes of big ones, not {} bytes",
+                16 * SMALL_GLYPH_SIZE,
+                10 * BIG_GLYPH_SIZE,
+                16 * BIG_GLYPH_SIZE,
+                size
+            ),
+        }
+    }
+}
+
+impl Error for FontError {}
+
+impl Font {
+    /// Reads a font from the contents of a font file: the 16 small glyphs, 5 bytes each, optionally
+    /// followed by 10 or 16 big glyphs of 10 bytes each.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FontError> {
+        let small_size = 16 * SMALL_GLYPH_SIZE;
+        match bytes.len().checked_sub(small_size) {
+            Some(big_size) if [0, 10 * BIG_GLYPH_SIZE, 16 * BIG_GLYPH_SIZE].contains(&big_size) => {
+                let (small, big) = bytes.split_at(small_size);
+                Ok(Font {
+                    small: small.to_vec(),
+                    big: big.to_vec(),
+                })
+            }
+            _ => Err(FontError::InvalidSize(bytes.len())),
+        }
+    }
+
+    /// The small glyphs followed by the big ones, as they're laid out in memory.
+    pub fn bytes(&self) -> Vec<u8> {
+        [&self.small[..], &self.big[..]].concat()
+    }
+
+    /// Where the small glyph for `digit` starts, relative to the start of the font.
+    pub fn small_glyph_offset(&self, digit: u8) -> u16 {
+        (digit & 0xF) as u16 * SMALL_GLYPH_SIZE as u16
+    }
+
+    /// Where the big glyph for `digit` starts, relative to the start of the font, if the font has
+    /// one for it.
+    pub fn big_glyph_offset(&self, digit: u8) -> Option<u16> {
+        let offset = (digit & 0xF) as usize * BIG_GLYPH_SIZE;
+        if offset < self.big.len() {
+            Some((self.small.len() + offset) as u16)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Font {
+    /// The font most emulators use, which came from CHIP-48.
+    fn default() -> Self {
+        "chip48".parse().unwrap()
+    }
+}
+
+impl FromStr for Font {
+    type Err = FontError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalised: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        PRESETS
+            .iter()
+            .find(|(preset, _, _)| *preset == normalised)
+            .map(|(_, small, big)| Font {
+                small: small.to_vec(),
+                big: big.to_vec(),
+            })
+            .ok_or_else(|| FontError::UnknownFont(name.to_string()))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        Font,
+        FontError,
+    };
+
+    #[test]
+    fn from_str() {
+        assert_eq!("CHIP-48".parse(), Ok(Font::default()));
+        assert_eq!("DREAM 6800".parse::<Font>().unwrap().bytes()[5], 0x40);
+        assert_eq!("schip".parse::<Font>().unwrap().bytes().len(), 180);
+        assert_eq!("octo".parse::<Font>().unwrap().bytes().len(), 240);
+        assert_eq!(
+            "telmac".parse::<Font>(),
+            Err(FontError::UnknownFont(String::from("telmac")))
+        );
+    }
+
+    #[test]
+    fn from_bytes() {
+        assert!(Font::from_bytes(&[0xF0; 80]).is_ok());
+        assert!(Font::from_bytes(&[0xF0; 180]).is_ok());
+        assert!(Font::from_bytes(&[0xF0; 240]).is_ok());
+        assert_eq!(
+            Font::from_bytes(&[0xF0; 79]),
+            Err(FontError::InvalidSize(79))
+        );
+        assert_eq!(
+            Font::from_bytes(&[0xF0; 90]),
+            Err(FontError::InvalidSize(90))
+        );
+        assert_eq!(
+            Font::from_bytes(&[0xF0; 185]),
+            Err(FontError::InvalidSize(185))
+        );
+    }
+
+    #[test]
+    fn glyphs() {
+        let font: Font = "schip".parse().unwrap();
+
+        assert_eq!(font.small_glyph_offset(0xA), 50);
+        assert_eq!(font.small_glyph_offset(0x1A), 50);
+        assert_eq!(font.big_glyph_offset(0x2), Some(100));
+        assert_eq!(font.big_glyph_offset(0xA), None);
+        assert_eq!(Font::default().big_glyph_offset(0x0), None);
+    }
+}
@@ -0,0 +1,215 @@
+/// The widest display any supported mode uses, in pixels. The only other width is 64.
+pub const MAX_WIDTH: usize = 128;
+/// The tallest display any supported mode uses, in pixels.
+pub const MAX_HEIGHT: usize = 64;
+
+/// A monochrome display stored as one bit per pixel, with each row packed into a single integer:
+/// the low 64 bits of a `u128` on 64 pixel wide displays, and all of it on 128 pixel wide ones.
+/// The leftmost pixel of a row is its most significant used bit, so a sprite byte can be drawn by
+/// shifting it into place and XORing it onto the row, and collisions found with a single AND.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    rows: [u128; MAX_HEIGHT],
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(
+            (width == 64 || width == MAX_WIDTH) && (1..=MAX_HEIGHT).contains(&height),
+            "Unsupported display size {}x{}",
+            width,
+            height
+        );
+
+        Framebuffer {
+            width,
+            height,
+            rows: [0; MAX_HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The packed rows of the display, top to bottom. Only the low `width` bits of each are used.
+    pub fn rows(&self) -> &[u128] {
+        &self.rows[..self.height]
+    }
+
+    /// Replaces row `y` with the packed pixels of `row`, ignoring any bits beyond the width.
+    pub fn set_row(&mut self, y: usize, row: u128) {
+        assert!(y < self.height);
+
+        self.rows[y] = row & (u128::MAX >> (MAX_WIDTH - self.width));
+    }
+
+    pub fn clear(&mut self) {
+        self.rows = [0; MAX_HEIGHT];
+    }
+
+    pub fn pixel_at(&self, x: usize, y: usize) -> bool {
+        assert!(y < self.height);
+        assert!(x < self.width);
+
+        self.rows[y] >> (self.width - 1 - x) & 1 == 1
+    }
+
+    /// XORs the eight pixels of `byte` onto row `y`, starting at column `x`. Pixels that go past
+    /// the right edge wrap around to the left. Returns whether a lit pixel was turned off.
+    #[inline]
+    pub fn draw_sprite_row(&mut self, x: usize, y: usize, byte: u8) -> bool {
+        self.draw_sprite(x, y, &[byte])
+    }
+
+    /// Draws `sprite` one byte per row starting at `(x, y)`, wrapping around both edges of the
+    /// display. Returns whether a lit pixel was turned off.
+    #[inline]
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.blit(x, y, sprite, true)
+    }
+
+    /// Draws `sprite` like `draw_sprite`, except that the parts of it beyond the right and bottom
+    /// edges are cut off. The starting position still wraps around.
+    #[inline]
+    pub fn draw_sprite_clipped(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.blit(x, y, sprite, false)
+    }
+
+    #[inline]
+    fn blit(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        let shift = (x % self.width) as u32;
+        let mut row_index = if y < self.height { y } else { y % self.height };
+        let mut collision = false;
+        for &byte in sprite {
+            // 128-bit shifts are several times slower than 64-bit ones, so only pay for them on
+            // the displays that need them.
+            let bits = match (self.width == 64, wrap) {
+                (true, true) => ((byte as u64) << 56).rotate_right(shift) as u128,
+                (true, false) => (((byte as u64) << 56) >> shift) as u128,
+                (false, true) => ((byte as u128) << 120).rotate_right(shift),
+                (false, false) => ((byte as u128) << 120) >> shift,
+            };
+            let row = &mut self.rows[row_index];
+            collision |= *row & bits != 0;
+            *row ^= bits;
+
+            row_index += 1;
+            if row_index == self.height {
+                if !wrap {
+                    break;
+                }
+                row_index = 0;
+            }
+        }
+
+        collision
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::Framebuffer;
+
+    fn lit_columns(framebuffer: &Framebuffer, y: usize) -> Vec<usize> {
+        (0..framebuffer.width())
+            .filter(|&x| framebuffer.pixel_at(x, y))
+            .collect()
+    }
+
+    #[test]
+    fn new() {
+        let framebuffer = Framebuffer::new(64, 32);
+
+        assert_eq!(framebuffer.rows().len(), 32);
+        assert!(framebuffer.rows().iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn draw_sprite_row() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+
+        let collision = framebuffer.draw_sprite_row(3, 5, 0b1010_0001);
+
+        assert!(!collision);
+        assert_eq!(lit_columns(&framebuffer, 5), vec![3, 5, 10]);
+        assert!(lit_columns(&framebuffer, 4).is_empty());
+    }
+
+    #[test]
+    fn draw_sprite_row_collision() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+        framebuffer.draw_sprite_row(0, 0, 0b1100_0000);
+
+        assert!(!framebuffer.draw_sprite_row(2, 0, 0b1100_0000));
+        assert!(framebuffer.draw_sprite_row(1, 0, 0b1000_0000));
+        assert_eq!(lit_columns(&framebuffer, 0), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn draw_sprite_row_wraps() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+
+        framebuffer.draw_sprite_row(60, 33, 0xFF);
+
+        assert_eq!(
+            lit_columns(&framebuffer, 1),
+            vec![0, 1, 2, 3, 60, 61, 62, 63]
+        );
+    }
+
+    #[test]
+    fn draw_sprite_row_full_width() {
+        let mut framebuffer = Framebuffer::new(128, 64);
+
+        framebuffer.draw_sprite_row(124, 63, 0b1000_0001);
+        framebuffer.draw_sprite_row(0, 0, 0b1000_0000);
+
+        assert_eq!(lit_columns(&framebuffer, 63), vec![3, 124]);
+        assert_eq!(framebuffer.rows()[0], 1 << 127);
+    }
+
+    #[test]
+    fn draw_sprite_wraps_vertically() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+
+        let collision = framebuffer.draw_sprite(0, 31, &[0x80, 0x40, 0x20]);
+
+        assert!(!collision);
+        assert_eq!(lit_columns(&framebuffer, 31), vec![0]);
+        assert_eq!(lit_columns(&framebuffer, 0), vec![1]);
+        assert_eq!(lit_columns(&framebuffer, 1), vec![2]);
+    }
+
+    #[test]
+    fn draw_sprite_clipped() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+
+        framebuffer.draw_sprite_clipped(62, 31, &[0xFF, 0xFF]);
+        framebuffer.draw_sprite_clipped(64 + 4, 32, &[0x80]);
+
+        assert_eq!(lit_columns(&framebuffer, 31), vec![62, 63]);
+        assert_eq!(lit_columns(&framebuffer, 0), vec![4]);
+    }
+
+    #[test]
+    fn clear() {
+        let mut framebuffer = Framebuffer::new(64, 32);
+        for y in 0..32 {
+            for x in (0..64).step_by(8) {
+                framebuffer.draw_sprite_row(x, y, 0xFF);
+            }
+        }
+
+        framebuffer.clear();
+
+        assert!(framebuffer.rows().iter().all(|&row| row == 0));
+    }
+}
@@ -0,0 +1,391 @@
+use sdl2::{
+    audio::{
+        AudioCallback,
+        AudioSpecDesired,
+    },
+    event::Event,
+    keyboard::{
+        Keycode,
+        Mod,
+    },
+    pixels::{
+        Color,
+        PixelFormatEnum,
+    },
+    render::Texture,
+};
+use std::{
+    error::Error,
+    io::{
+        self,
+        Write,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    debugger::{
+        self,
+        Debugger,
+        PROMPT,
+    },
+    emulator::{
+        Emulator,
+        DISPLAY_WIDTH,
+    },
+    headless::display_image,
+    instruments::Instruments,
+    keymap::Keymap,
+    machine::Machine,
+    palette::{
+        Palette,
+        Rgb,
+    },
+    phosphor::Phosphor,
+    timing::{
+        FrameClock,
+        Timing,
+    },
+    upscale::Filter,
+};
+
+// Display constants
+
+const TICKS_PER_SECOND: u64 = 60;
+/// The shortest sleep between instructions, as shorter ones overshoot by too much.
+const MIN_SLEEP_MICROSECONDS: u64 = 1000;
+
+// Sound constants
+
+const SAMPLE_RATE: i32 = 44_100;
+const TONE_FREQUENCY: f32 = 440.0;
+const VOLUME: f32 = 0.1;
+
+pub struct Options {
+    pub palette: Palette,
+    pub filter: Filter,
+    /// The fraction of its brightness a cleared pixel keeps every frame (1/60th of a second), or
+    /// `None` to show pixels exactly as the emulator draws them.
+    pub phosphor_decay: Option<f32>,
+    /// Only redraw the window once per frame rather than after every instruction, hiding the
+    /// intermediate states of sprites that are erased and redrawn within a frame.
+    pub vblank_only: bool,
+    /// How many window pixels wide and high every display pixel is.
+    pub scale: usize,
+    /// How long instructions take, which sets how many are executed every frame.
+    pub timing: Timing,
+    pub keymap: Keymap,
+    /// Don't play the tone while the sound timer is running.
+    pub mute: bool,
+    /// Start with the program stopped in the debugger, which reads commands from standard input.
+    pub debug: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            palette: Palette::default(),
+            filter: Filter::default(),
+            phosphor_decay: None,
+            vblank_only: false,
+            scale: 20,
+            timing: Timing::Instructions(10),
+            keymap: Keymap::default(),
+            mute: false,
+            debug: false,
+        }
+    }
+}
+
+/// A square wave, played while the sound timer is running.
+struct SquareWave {
+    phase_increment: f32,
+    phase: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = if self.phase < 0.5 { VOLUME } else { -VOLUME };
+            self.phase = (self.phase + self.phase_increment) % 1.0;
+        }
+    }
+}
+
+/// Runs the machine in a window until it's closed, passing every instruction executed to
+/// `instruments`. Only the CHIP-8 interpreter can be instrumented or debugged.
+pub fn run<M: Machine>(
+    machine: &mut M,
+    options: Options,
+    instruments: &mut Instruments,
+) -> Result<(), Box<dyn Error>> {
+    let Options {
+        mut palette,
+        mut filter,
+        phosphor_decay,
+        vblank_only,
+        scale,
+        timing,
+        keymap,
+        mute,
+        debug,
+    } = options;
+    if debug && machine.emulator().is_none() {
+        return Err("only the CHIP-8 interpreter can be debugged".into());
+    }
+    let microseconds_per_frame = 1_000_000 / TICKS_PER_SECOND;
+    // Displays wider than usual get smaller pixels, to keep the window the same size.
+    let (width, height) = machine.display_size();
+    let scale = (scale * DISPLAY_WIDTH / width).max(1);
+    // Without vblank-only drawing the screen is refreshed after every instruction, so spread the
+    // per-frame decay over all of the refreshes within a frame.
+    let mut phosphor = phosphor_decay.map(|decay| {
+        let decay = if vblank_only {
+            decay
+        } else {
+            decay.powf(1.0 / timing.instructions_per_frame() as f32)
+        };
+        Phosphor::with_size(decay, width, height)
+    });
+
+    // Set up the SDL2 window
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let window_width = (scale * width) as u32;
+    let window_height = (scale * height) as u32;
+    eprintln!("Window dimensions: {}, {}", window_width, window_height);
+
+    let window = video_subsystem
+        .window("CHIP-8", window_width, window_height)
+        .position_centered()
+        .build()?;
+
+    let audio_device = if mute {
+        None
+    } else {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        Some(
+            audio_subsystem.open_playback(None, &desired_spec, |spec| SquareWave {
+                phase_increment: TONE_FREQUENCY / spec.freq as f32,
+                phase: 0.0,
+            })?,
+        )
+    };
+
+    let mut canvas = window.into_canvas().build()?;
+    canvas.set_draw_color(sdl_color(palette.background()));
+    canvas.clear();
+    canvas.present();
+    let texture_creator = canvas.texture_creator();
+    let mut texture: Option<Texture> = None;
+    let mut last_image = None;
+    let mut last_filter = None;
+    let mut draw = |machine: &M,
+                    palette: &Palette,
+                    filter: Filter,
+                    phosphor: Option<&Phosphor>|
+     -> Result<(), Box<dyn Error>> {
+        // Filtering is by far the most expensive part of drawing, so only redo it when the
+        // picture has actually changed.
+        let image = display_image(machine, palette, phosphor);
+        if last_image.as_ref() != Some(&image) || last_filter != Some(filter) {
+            let scaled = filter.apply(&image, scale);
+            let size = (scaled.width() as u32, scaled.height() as u32);
+            if texture.as_ref().map(|texture| {
+                let query = texture.query();
+                (query.width, query.height)
+            }) != Some(size)
+            {
+                texture = Some(texture_creator.create_texture_streaming(
+                    PixelFormatEnum::RGBA32,
+                    size.0,
+                    size.1,
+                )?);
+            }
+            texture
+                .as_mut()
+                .unwrap()
+                .update(None, &scaled.to_rgba(), 4 * scaled.width())?;
+            last_image = Some(image);
+            last_filter = Some(filter);
+        }
+
+        canvas.clear();
+        canvas.copy(texture.as_ref().unwrap(), None, None)?;
+        canvas.present();
+        Ok(())
+    };
+    let mut debug = if debug {
+        prompt();
+        Some((Debugger::new(), debugger::spawn_stdin_reader()))
+    } else {
+        None
+    };
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut clock = FrameClock::new(timing);
+    let mut owed_sleep = 0;
+    'running: loop {
+        // Handle input
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => {
+                    break 'running;
+                }
+                Event::AppDidEnterBackground { .. } => {
+                    machine.pause();
+                }
+                Event::AppDidEnterForeground { .. } => {
+                    machine.resume();
+                }
+                Event::KeyDown {
+                    repeat: false,
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } => match keycode {
+                    Keycode::F2 => {
+                        palette = palette.next();
+                        eprintln!("Palette: {}", palette.name());
+                    }
+                    Keycode::F3 => {
+                        filter = filter.next();
+                        eprintln!("Filter: {}", filter);
+                    }
+                    Keycode::F5 => {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            machine.hard_reset();
+                            eprintln!("Hard reset");
+                        } else {
+                            machine.reset();
+                            eprintln!("Reset");
+                        }
+                        if let Some(phosphor) = phosphor.as_mut() {
+                            phosphor.update(|x, y| machine.pixel_at(x, y));
+                        }
+                        draw(machine, &palette, filter, phosphor.as_ref())?;
+                    }
+                    _ => {
+                        if let Some(key) = keymap.key(keycode) {
+                            machine.key_press(key);
+                        }
+                    }
+                },
+                Event::KeyUp {
+                    repeat: false,
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(key) = keymap.key(keycode) {
+                        machine.key_release(key);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut redraw = false;
+        if let Some((debugger, commands)) = debug.as_mut() {
+            for line in commands.try_iter() {
+                match debugger.execute(emulator(machine), &line) {
+                    Ok(text) if text.is_empty() => {}
+                    Ok(text) => println!("{}", text),
+                    Err(err) => println!("error: {}", err),
+                }
+                if debugger.has_quit() {
+                    break 'running;
+                }
+                if !debugger.is_running() {
+                    prompt();
+                }
+                redraw = true;
+            }
+        }
+
+        let mut step = None;
+        if !machine.is_paused() {
+            step = match debug.as_mut() {
+                Some((debugger, _)) => {
+                    let progress = debugger.advance(emulator(machine));
+                    if let Some(report) = progress.report {
+                        println!("{}", report);
+                        prompt();
+                        redraw = true;
+                    }
+                    progress.step
+                }
+                None => Some(machine.step()?),
+            };
+        }
+        // While the program is paused or stopped in the debugger, keep the window responsive and
+        // showing any changes made from the debugger, without running the timers.
+        let step = match step {
+            Some(step) => step,
+            None => {
+                if redraw {
+                    draw(machine, &palette, filter, phosphor.as_ref())?;
+                }
+                thread::sleep(Duration::from_micros(microseconds_per_frame));
+                continue;
+            }
+        };
+        if let Some(emulator) = machine.emulator() {
+            instruments.record(step, emulator)?;
+        }
+
+        let cost = machine.cost(step, timing);
+        let is_vblank = clock.advance(cost);
+
+        if is_vblank || !vblank_only {
+            if let Some(phosphor) = phosphor.as_mut() {
+                phosphor.update(|x, y| machine.pixel_at(x, y));
+            }
+            draw(machine, &palette, filter, phosphor.as_ref())?;
+        }
+
+        // 60 FPS, with every instruction taking its share of the frame. Sleeps too short to be
+        // accurate are saved up until they aren't.
+        owed_sleep += microseconds_per_frame * cost;
+        if owed_sleep >= MIN_SLEEP_MICROSECONDS * timing.budget() {
+            thread::sleep(Duration::from_micros(owed_sleep / timing.budget()));
+            owed_sleep %= timing.budget();
+        }
+        // Tick
+        if is_vblank {
+            machine.tick();
+            if let Some(audio_device) = audio_device.as_ref() {
+                if machine.is_sound_playing() {
+                    audio_device.resume();
+                } else {
+                    audio_device.pause();
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The CHIP-8 interpreter being debugged, which only exists if `machine` is one.
+fn emulator<M: Machine>(machine: &mut M) -> &mut Emulator {
+    machine
+        .emulator_mut()
+        .expect("only the CHIP-8 interpreter can be debugged")
+}
+
+fn prompt() {
+    print!("{}", PROMPT);
+    io::stdout().flush().unwrap();
+}
+
+fn sdl_color(rgb: Rgb) -> Color {
+    Color::RGB(rgb.r, rgb.g, rgb.b)
+}
@@ -0,0 +1,661 @@
+use std::{
+    collections::BTreeSet,
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        ErrorKind,
+        Read,
+        Write,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+};
+
+use crate::emulator::Emulator;
+
+// Signals reported to GDB when the program stops
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// The byte GDB sends, outside of any packet, to interrupt a running program.
+const INTERRUPT: u8 = 0x03;
+
+/// How many instructions are executed between checks for an interrupt while the program runs.
+const INTERRUPT_CHECK_INTERVAL: u64 = 1024;
+
+/// The registers in the order GDB numbers them, with their names and sizes in bits.
+const REGISTERS: [(&str, u32); 21] = [
+    ("v0", 8),
+    ("v1", 8),
+    ("v2", 8),
+    ("v3", 8),
+    ("v4", 8),
+    ("v5", 8),
+    ("v6", 8),
+    ("v7", 8),
+    ("v8", 8),
+    ("v9", 8),
+    ("va", 8),
+    ("vb", 8),
+    ("vc", 8),
+    ("vd", 8),
+    ("ve", 8),
+    ("vf", 8),
+    ("i", 16),
+    ("pc", 16),
+    ("sp", 16),
+    ("dt", 8),
+    ("st", 8),
+];
+const I: usize = 16;
+const PC: usize = 17;
+const SP: usize = 18;
+const DT: usize = 19;
+const ST: usize = 20;
+
+/// Waits for GDB to connect to `listener` and lets it control the emulator until it detaches or
+/// kills the program. While the program runs, the timers tick once every `cycles_per_frame`
+/// instructions.
+pub fn serve(
+    emulator: &mut Emulator,
+    listener: &TcpListener,
+    cycles_per_frame: u64,
+) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let mut stub = Stub {
+        emulator,
+        connection: Connection::new(stream)?,
+        breakpoints: BTreeSet::new(),
+        cycles_per_frame,
+        remaining_cycles: cycles_per_frame,
+    };
+    stub.run()
+}
+
+/// What GDB sent: a command packet, or an interrupt.
+enum Message {
+    Packet(String),
+    Interrupt,
+}
+
+/// Reads and writes the packets of GDB's remote serial protocol, like `$m200,4#2e`.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// Whether packets are still acknowledged, which GDB can turn off.
+    acknowledges: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        Ok(Connection {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            acknowledges: true,
+        })
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// The next message from GDB, or `None` once it has disconnected. Packets with the wrong
+    /// checksum are rejected, asking GDB to send them again.
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Message::Interrupt)),
+                Some(b'$') => {}
+                // Acknowledgements of our own packets, and noise between packets.
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            self.reader.read_until(b'#', &mut data)?;
+            if data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+            if expected == Some(checksum_of(&data)) {
+                if self.acknowledges {
+                    self.writer.write_all(b"+")?;
+                }
+                return Ok(Some(Message::Packet(
+                    String::from_utf8_lossy(&data).into_owned(),
+                )));
+            } else if self.acknowledges {
+                self.writer.write_all(b"-")?;
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(
+            self.writer,
+            "${}#{:02x}",
+            data,
+            checksum_of(data.as_bytes())
+        )?;
+        self.writer.flush()
+    }
+
+    /// Whether GDB has asked for the running program to be interrupted, without waiting for it.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.reader.get_ref().set_nonblocking(true)?;
+        let next = match self.reader.fill_buf() {
+            Ok(buffer) => Ok(buffer.first().copied()),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        };
+        self.reader.get_ref().set_nonblocking(false)?;
+        if next? == Some(INTERRUPT) {
+            self.reader.consume(1);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// What to do after handling a packet.
+enum Reply {
+    Send(String),
+    /// End the session without replying.
+    Close,
+}
+
+struct Stub<'a> {
+    emulator: &'a mut Emulator,
+    connection: Connection,
+    breakpoints: BTreeSet<u16>,
+    cycles_per_frame: u64,
+    /// How many more instructions will be executed before the timers next tick.
+    remaining_cycles: u64,
+}
+
+impl<'a> Stub<'a> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(message) = self.connection.receive()? {
+            let packet = match message {
+                Message::Packet(packet) => packet,
+                // The program is already stopped.
+                Message::Interrupt => continue,
+            };
+            match self.handle(&packet)? {
+                Reply::Send(reply) => {
+                    self.connection.send(&reply)?;
+                    if packet == "D" || packet.starts_with("D;") {
+                        return Ok(());
+                    }
+                    if packet == "QStartNoAckMode" {
+                        self.connection.acknowledges = false;
+                    }
+                }
+                Reply::Close => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> io::Result<Reply> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP),
+            "g" => self.read_registers(),
+            "G" => ok_or_error(self.write_registers(args)),
+            "p" => parse_hex(args)
+                .and_then(|n| self.read_register(n as usize))
+                .unwrap_or_else(|| error(1)),
+            "P" => ok_or_error(args.split_once('=').and_then(|(n, value)| {
+                let n = parse_hex(n)? as usize;
+                self.write_register(n, &decode_hex(value)?)
+            })),
+            "m" => self.read_memory(args).unwrap_or_else(|| error(1)),
+            "M" => ok_or_error(self.write_memory(args)),
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(address) => self.emulator.set_program_counter(address as u16),
+                        None => return Ok(Reply::Send(error(1))),
+                    }
+                }
+                let signal = if command == "c" {
+                    self.resume()?
+                } else {
+                    self.step()
+                };
+                stop_reply(signal)
+            }
+            "Z" | "z" => match breakpoint_address(args) {
+                Some(address) => {
+                    if command == "Z" {
+                        self.breakpoints.insert(address);
+                    } else {
+                        self.breakpoints.remove(&address);
+                    }
+                    String::from("OK")
+                }
+                // Only software breakpoints are supported.
+                None => String::new(),
+            },
+            "q" => self.query(args),
+            "Q" if args == "StartNoAckMode" => String::from("OK"),
+            // There's only one thread, so selecting it or asking whether it's alive always works.
+            "H" | "T" => String::from("OK"),
+            "D" => String::from("OK"),
+            "k" => return Ok(Reply::Close),
+            _ => String::new(),
+        };
+        Ok(Reply::Send(reply))
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+")
+        } else if let Some(annex) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, length) = match annex.split_once(',') {
+                Some((offset, length)) => (parse_hex(offset), parse_hex(length)),
+                None => (None, None),
+            };
+            match (offset, length) {
+                (Some(offset), Some(length)) => {
+                    let description = target_description();
+                    let start = (offset as usize).min(description.len());
+                    let end = (start + length as usize).min(description.len());
+                    let more = if end < description.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, &description[start..end])
+                }
+                _ => error(1),
+            }
+        } else {
+            match query {
+                "Attached" => String::from("1"),
+                "C" => String::from("QC1"),
+                "fThreadInfo" => String::from("m1"),
+                "sThreadInfo" => String::from("l"),
+                _ => String::new(),
+            }
+        }
+    }
+
+    fn register(&self, n: usize) -> Option<u16> {
+        Some(match n {
+            0..=15 => self.emulator.registers()[n] as u16,
+            I => self.emulator.i(),
+            PC => self.emulator.program_counter(),
+            // An unlimited stack can in principle go deeper than GDB's 16 bit register shows.
+            SP => self.emulator.stack_pointer().min(u16::MAX as usize) as u16,
+            DT => self.emulator.delay_timer() as u16,
+            ST => self.emulator.sound_timer() as u16,
+            _ => return None,
+        })
+    }
+
+    fn read_register(&self, n: usize) -> Option<String> {
+        let value = self.register(n)?;
+        let size = REGISTERS[n].1 as usize / 8;
+        Some(encode_hex(&value.to_le_bytes()[..size]))
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTERS.len())
+            .filter_map(|n| self.read_register(n))
+            .collect()
+    }
+
+    /// Sets register `n` from its little-endian bytes.
+    fn write_register(&mut self, n: usize, bytes: &[u8]) -> Option<()> {
+        let &(_, bits) = REGISTERS.get(n)?;
+        if bytes.len() != bits as usize / 8 {
+            return None;
+        }
+        let value = bytes[0] as u16 | bytes.get(1).map_or(0, |&high| (high as u16) << 8);
+        match n {
+            0..=15 => self.emulator.set_register(n, value as u8),
+            I => self.emulator.set_i(value),
+            PC => self.emulator.set_program_counter(value),
+            SP => match self.emulator.stack_depth() {
+                Some(depth) if value as usize > depth => return None,
+                _ => self.emulator.set_stack_pointer(value as usize),
+            },
+            DT => self.emulator.set_delay_timer(value as u8),
+            _ => self.emulator.set_sound_timer(value as u8),
+        }
+        Some(())
+    }
+
+    fn write_registers(&mut self, hex: &str) -> Option<()> {
+        let bytes = decode_hex(hex)?;
+        let total: u32 = REGISTERS.iter().map(|&(_, bits)| bits / 8).sum();
+        if bytes.len() != total as usize {
+            return None;
+        }
+        let mut offset = 0;
+        for (n, &(_, bits)) in REGISTERS.iter().enumerate() {
+            let size = bits as usize / 8;
+            self.write_register(n, &bytes[offset..offset + size])?;
+            offset += size;
+        }
+        Some(())
+    }
+
+    /// The memory range in `m` and `M` packets, like `200,4`.
+    fn memory_range(&self, range: &str) -> Option<(usize, usize)> {
+        let (address, length) = range.split_once(',')?;
+        let start = parse_hex(address)? as usize;
+        let end = start.checked_add(parse_hex(length)? as usize)?;
+        if end > self.emulator.memory().len() {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (start, end) = self.memory_range(args)?;
+        Some(encode_hex(&self.emulator.memory()[start..end]))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<()> {
+        let (range, data) = args.split_once(':')?;
+        let (start, end) = self.memory_range(range)?;
+        let bytes = decode_hex(data)?;
+        if bytes.len() != end - start {
+            return None;
+        }
+        self.emulator.memory_mut()[start..end].copy_from_slice(&bytes);
+        Some(())
+    }
+
+    /// Executes one instruction, returning the signal to stop with.
+    fn step(&mut self) -> u8 {
+        match self.emulator.step() {
+            Ok(_) => {
+                self.remaining_cycles -= 1;
+                if self.remaining_cycles == 0 {
+                    self.emulator.tick();
+                    self.remaining_cycles = self.cycles_per_frame;
+                }
+                SIGTRAP
+            }
+            Err(_) => SIGILL,
+        }
+    }
+
+    /// Runs the program until it reaches a breakpoint, fails or is interrupted, returning the
+    /// signal to stop with. The instruction at the program counter is always executed, so that
+    /// continuing from a breakpoint doesn't stop there again.
+    fn resume(&mut self) -> io::Result<u8> {
+        let mut until_check = INTERRUPT_CHECK_INTERVAL;
+        loop {
+            if self.step() == SIGILL {
+                return Ok(SIGILL);
+            }
+            if self.breakpoints.contains(&self.emulator.program_counter()) {
+                return Ok(SIGTRAP);
+            }
+            until_check -= 1;
+            if until_check == 0 {
+                if self.connection.interrupted()? {
+                    return Ok(SIGINT);
+                }
+                until_check = INTERRUPT_CHECK_INTERVAL;
+            }
+        }
+    }
+}
+
+/// The address of a software breakpoint in `Z` and `z` packets, like `0,20a,2`.
+fn breakpoint_address(args: &str) -> Option<u16> {
+    let mut fields = args.split(',');
+    if fields.next()? != "0" {
+        return None;
+    }
+    let address = parse_hex(fields.next()?)?;
+    if address > u16::MAX as u64 {
+        return None;
+    }
+    Some(address as u16)
+}
+
+/// Describes the registers to GDB, which doesn't know about the CHIP-8.
+fn target_description() -> String {
+    let mut description = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n",
+    );
+    for (n, &(name, bits)) in REGISTERS.iter().enumerate() {
+        let kind = if n == PC { " type=\"code_ptr\"" } else { "" };
+        description.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"{}\" regnum=\"{}\"{}/>\n",
+            name, bits, n, kind
+        ));
+    }
+    description.push_str("</feature>\n</target>\n");
+    description
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn error(code: u8) -> String {
+    format!("E{:02x}", code)
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    match result {
+        Some(()) => String::from("OK"),
+        None => error(1),
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => {
+                let high = (*high as char).to_digit(16)?;
+                let low = (*low as char).to_digit(16)?;
+                Some((high << 4 | low) as u8)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        checksum_of,
+        serve,
+    };
+    use crate::emulator::Emulator;
+    use std::{
+        io::{
+            Read,
+            Write,
+        },
+        net::{
+            TcpListener,
+            TcpStream,
+        },
+        thread::{
+            self,
+            JoinHandle,
+        },
+    };
+
+    // 0x200: LD V0, 0x05
+    // 0x202: ADD V0, 0x01
+    // 0x204: JP 0x202
+    const PROGRAM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    /// Starts serving an emulator running `PROGRAM` on a loopback port, returning a connection to
+    /// it and the thread that gives the emulator back once GDB has gone.
+    fn attach() -> (TcpStream, JoinHandle<Emulator>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut emulator = Emulator::new();
+            emulator.load_rom(&PROGRAM).unwrap();
+            serve(&mut emulator, &listener, 10).unwrap();
+            emulator
+        });
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    fn send(stream: &mut TcpStream, packet: &str) -> String {
+        write!(stream, "${}#{:02x}", packet, checksum_of(packet.as_bytes())).unwrap();
+        receive(stream)
+    }
+
+    fn receive(stream: &mut TcpStream) -> String {
+        let mut byte = [0];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'$' {
+                break;
+            }
+            assert_eq!(byte[0], b'+');
+        }
+        let mut data = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", checksum_of(&data))
+        );
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn registers() {
+        let (mut gdb, server) = attach();
+
+        assert_eq!(send(&mut gdb, "?"), "S05");
+        assert_eq!(send(&mut gdb, "s"), "S05");
+        assert_eq!(
+            send(&mut gdb, "g"),
+            "05000000000000000000000000000000\
+             0000\
+             0202\
+             0000\
+             0000"
+        );
+        assert_eq!(send(&mut gdb, "P3=2a"), "OK");
+        assert_eq!(send(&mut gdb, "P10=0003"), "OK");
+        assert_eq!(send(&mut gdb, "p10"), "0003");
+        assert_eq!(send(&mut gdb, "P13=3c"), "OK");
+        assert_eq!(send(&mut gdb, "P12=11"), "E01");
+        assert_eq!(send(&mut gdb, "P12=1100"), "E01");
+        assert_eq!(send(&mut gdb, "P12=0100"), "OK");
+        assert_eq!(send(&mut gdb, "p12"), "0100");
+        assert_eq!(send(&mut gdb, "p15"), "E01");
+        assert_eq!(send(&mut gdb, "D"), "OK");
+
+        let emulator = server.join().unwrap();
+        assert_eq!(emulator.registers()[0x3], 0x2A);
+        assert_eq!(emulator.i(), 0x300);
+        assert_eq!(emulator.delay_timer(), 0x3C);
+    }
+
+    #[test]
+    fn memory() {
+        let (mut gdb, server) = attach();
+
+        assert_eq!(send(&mut gdb, "m200,4"), "60057001");
+        assert_eq!(send(&mut gdb, "M300,3:0102ff"), "OK");
+        assert_eq!(send(&mut gdb, "m300,3"), "0102ff");
+        assert_eq!(send(&mut gdb, "mfff,2"), "E01");
+        assert_eq!(send(&mut gdb, "M300,2:01"), "E01");
+        gdb.write_all(b"$k#6b").unwrap();
+
+        let emulator = server.join().unwrap();
+        assert_eq!(&emulator.memory()[0x300..0x303], &[1, 2, 0xFF]);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (mut gdb, server) = attach();
+
+        assert_eq!(send(&mut gdb, "Z0,204,2"), "OK");
+        assert_eq!(send(&mut gdb, "c"), "S05");
+        assert_eq!(send(&mut gdb, "p11"), "0402");
+        // Continuing runs the loop once more before reaching the breakpoint again.
+        assert_eq!(send(&mut gdb, "c"), "S05");
+        assert_eq!(send(&mut gdb, "p0"), "07");
+        assert_eq!(send(&mut gdb, "z0,204,2"), "OK");
+        assert_eq!(send(&mut gdb, "Z1,204,2"), "");
+        assert_eq!(send(&mut gdb, "D"), "OK");
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn interrupt() {
+        let (mut gdb, server) = attach();
+
+        write!(gdb, "$c#63").unwrap();
+        gdb.write_all(&[0x03]).unwrap();
+
+        assert_eq!(receive(&mut gdb), "S02");
+        assert_eq!(send(&mut gdb, "D"), "OK");
+        let emulator = server.join().unwrap();
+        assert!([0x202, 0x204].contains(&emulator.program_counter()));
+    }
+
+    #[test]
+    fn protocol() {
+        let (mut gdb, server) = attach();
+
+        // A packet with a bad checksum is rejected.
+        gdb.write_all(b"$g#00").unwrap();
+        let mut byte = [0];
+        gdb.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'-');
+
+        assert!(send(&mut gdb, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+        let description = send(&mut gdb, "qXfer:features:read:target.xml:0,1000");
+        assert!(description.starts_with("l<?xml"));
+        assert!(description.contains("<reg name=\"pc\" bitsize=\"16\" regnum=\"17\""));
+        assert_eq!(send(&mut gdb, "vMustReplyEmpty"), "");
+        assert_eq!(send(&mut gdb, "D"), "OK");
+
+        server.join().unwrap();
+    }
+}
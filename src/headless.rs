@@ -0,0 +1,179 @@
+use std::{
+    error::Error,
+    io,
+};
+
+use crate::{
+    emulator::DISPLAY_WIDTH,
+    emulator::{
+        Emulator,
+        ExecError,
+        Step,
+    },
+    machine::Machine,
+    palette::Palette,
+    phosphor::Phosphor,
+    timing::{
+        FrameClock,
+        Timing,
+    },
+    upscale::{
+        Filter,
+        Image,
+    },
+};
+
+/// Runs the machine without a window for `frames` frames (1/60th of a second each), executing
+/// as many instructions as fit in a frame with `timing` and ticking the timers once per frame.
+pub fn run<M: Machine>(machine: &mut M, frames: u64, timing: Timing) -> Result<(), ExecError> {
+    let mut clock = FrameClock::new(timing);
+    for _ in 0..frames {
+        loop {
+            let step = machine.step()?;
+            if clock.advance(machine.cost(step, timing)) {
+                break;
+            }
+        }
+        machine.tick();
+    }
+    Ok(())
+}
+
+/// Like `run`, but passing every instruction executed to `observe`, for tracing or profiling, and
+/// the machine to `frame` at the end of every frame, for recording.
+pub fn run_observed<F, G>(
+    emulator: &mut Emulator,
+    frames: u64,
+    timing: Timing,
+    mut observe: F,
+    mut frame: G,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(Step, &Emulator) -> io::Result<()>,
+    G: FnMut(&Emulator) -> io::Result<()>,
+{
+    let mut clock = FrameClock::new(timing);
+    for _ in 0..frames {
+        loop {
+            let step = emulator.step()?;
+            observe(step, emulator)?;
+            if clock.advance(timing.cost(step, emulator)) {
+                break;
+            }
+        }
+        emulator.tick();
+        frame(emulator)?;
+    }
+    Ok(())
+}
+
+/// The machine's display as it should appear on screen, before any scaling.
+pub fn display_image<M: Machine>(
+    machine: &M,
+    palette: &Palette,
+    phosphor: Option<&Phosphor>,
+) -> Image {
+    let (width, height) = machine.display_size();
+    let mut image = Image::new(width, height, palette.background());
+    for y in 0..height {
+        for x in 0..width {
+            let color = match (machine.pixel_color(x, y), phosphor) {
+                (Some(color), _) => color,
+                (None, Some(phosphor)) => phosphor.color(x, y, palette),
+                (None, None) => palette.color(machine.pixel_at(x, y) as usize),
+            };
+            image.set(x, y, color);
+        }
+    }
+    image
+}
+
+/// The machine's display drawn with `palette` and scaled up by `filter` as a binary PPM image,
+/// the same picture the window shows. Like the window, displays wider than usual get smaller
+/// pixels.
+pub fn screenshot<M: Machine>(
+    machine: &M,
+    palette: &Palette,
+    filter: Filter,
+    scale: usize,
+) -> Vec<u8> {
+    let (width, _) = machine.display_size();
+    let scale = (scale * DISPLAY_WIDTH / width).max(1);
+    filter
+        .apply(&display_image(machine, palette, None), scale)
+        .to_ppm()
+}
+
+/// The machine's display as text, one line per row, with `#` for lit pixels and `.` for the rest.
+pub fn screen_text<M: Machine>(machine: &M) -> String {
+    let (width, height) = machine.display_size();
+    let mut text = String::with_capacity((width + 1) * height);
+    for y in 0..height {
+        for x in 0..width {
+            text.push(if machine.pixel_at(x, y) { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        run,
+        screen_text,
+        screenshot,
+    };
+    use crate::{
+        emulator::Emulator,
+        palette::Palette,
+        timing::Timing,
+        upscale::Filter,
+    };
+
+    #[test]
+    fn draws_a_digit() {
+        let mut emulator = Emulator::new();
+        // LD V0, 0x01; LD F, V0; DRW V1, V1, 5; JP 0x206
+        emulator
+            .load_rom(&[0x60, 0x01, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06])
+            .unwrap();
+
+        run(&mut emulator, 2, Timing::Instructions(10)).unwrap();
+
+        let text = screen_text(&emulator);
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(rows.len(), 32);
+        assert_eq!(&rows[0][..8], "..#.....");
+        assert_eq!(&rows[1][..8], ".##.....");
+        assert_eq!(&rows[4][..8], ".###....");
+        assert!(rows[5].chars().all(|c| c == '.'));
+    }
+
+    #[test]
+    fn screenshot_is_scaled_by_the_filter() {
+        let mut emulator = Emulator::new();
+        // LD V0, 0x01; LD F, V0; DRW V1, V1, 5; JP 0x206
+        emulator
+            .load_rom(&[0x60, 0x01, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06])
+            .unwrap();
+        run(&mut emulator, 1, Timing::Instructions(10)).unwrap();
+
+        let palette = Palette::default();
+        let ppm = screenshot(&emulator, &palette, Filter::Nearest, 2);
+        let header = b"P6\n128 64\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + 128 * 64 * 3);
+        // The top of the 1 is lit two pixels in, at (4, 0) once scaled.
+        let pixel = |x: usize, y: usize| {
+            let start = header.len() + 3 * (y * 128 + x);
+            &ppm[start..start + 3]
+        };
+        let foreground = palette.color(1);
+        let background = palette.background();
+        assert_eq!(pixel(4, 0), [foreground.r, foreground.g, foreground.b]);
+        assert_eq!(pixel(5, 1), [foreground.r, foreground.g, foreground.b]);
+        assert_eq!(pixel(3, 0), [background.r, background.g, background.b]);
+    }
+}
@@ -0,0 +1,38 @@
+use std::io::{
+    self,
+    Write,
+};
+
+use crate::{
+    coverage::Coverage,
+    emulator::{
+        Emulator,
+        Step,
+    },
+    profiler::Profiler,
+    trace::Tracer,
+};
+
+/// The tools that can watch a run instruction by instruction, each of which is optional.
+#[derive(Default)]
+pub struct Instruments {
+    pub tracer: Option<Tracer<Box<dyn Write>>>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+}
+
+impl Instruments {
+    /// Records `step`, which has just been executed by `emulator`, with every instrument.
+    pub fn record(&mut self, step: Step, emulator: &Emulator) -> io::Result<()> {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(step, emulator);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(step, emulator);
+        }
+        match self.tracer.as_mut() {
+            Some(tracer) => tracer.record(step, emulator),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use sdl2::keyboard::Keycode;
+use std::{
+    error::Error,
+    fmt,
+};
+
+use crate::emulator::KEYPAD_SIZE;
+
+/// The keys of the left-hand side of a QWERTY keyboard, laid out like the COSMAC VIP's hex keypad,
+/// and those of the right-hand side laid out the same way for CHIP-8X's second keypad:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C      7 8 9 0
+/// Q W E R  ->  4 5 6 D  <-  U I O P
+/// A S D F      7 8 9 E      J K L ;
+/// Z X C V      A 0 B F      M , . /
+/// ```
+const DEFAULT_KEYS: [(Keycode, usize); 2 * KEYPAD_SIZE] = [
+    (Keycode::Num1, 0x1),
+    (Keycode::Num2, 0x2),
+    (Keycode::Num3, 0x3),
+    (Keycode::Num4, 0xC),
+    (Keycode::Q, 0x4),
+    (Keycode::W, 0x5),
+    (Keycode::E, 0x6),
+    (Keycode::R, 0xD),
+    (Keycode::A, 0x7),
+    (Keycode::S, 0x8),
+    (Keycode::D, 0x9),
+    (Keycode::F, 0xE),
+    (Keycode::Z, 0xA),
+    (Keycode::X, 0x0),
+    (Keycode::C, 0xB),
+    (Keycode::V, 0xF),
+    (Keycode::Num7, KEYPAD_SIZE + 0x1),
+    (Keycode::Num8, KEYPAD_SIZE + 0x2),
+    (Keycode::Num9, KEYPAD_SIZE + 0x3),
+    (Keycode::Num0, KEYPAD_SIZE + 0xC),
+    (Keycode::U, KEYPAD_SIZE + 0x4),
+    (Keycode::I, KEYPAD_SIZE + 0x5),
+    (Keycode::O, KEYPAD_SIZE + 0x6),
+    (Keycode::P, KEYPAD_SIZE + 0xD),
+    (Keycode::J, KEYPAD_SIZE + 0x7),
+    (Keycode::K, KEYPAD_SIZE + 0x8),
+    (Keycode::L, KEYPAD_SIZE + 0x9),
+    (Keycode::Semicolon, KEYPAD_SIZE + 0xE),
+    (Keycode::M, KEYPAD_SIZE + 0xA),
+    (Keycode::Comma, KEYPAD_SIZE),
+    (Keycode::Period, KEYPAD_SIZE + 0xB),
+    (Keycode::Slash, KEYPAD_SIZE + 0xF),
+];
+
+/// Keys that can't be named by the character they type. Looking names up here rather than asking
+/// SDL means keymaps can be checked without initialising it.
+const NAMED_KEYS: [(&str, Keycode); 28] = [
+    ("space", Keycode::Space),
+    ("return", Keycode::Return),
+    ("enter", Keycode::Return),
+    ("tab", Keycode::Tab),
+    ("backspace", Keycode::Backspace),
+    ("up", Keycode::Up),
+    ("down", Keycode::Down),
+    ("left", Keycode::Left),
+    ("right", Keycode::Right),
+    ("lshift", Keycode::LShift),
+    ("rshift", Keycode::RShift),
+    ("lctrl", Keycode::LCtrl),
+    ("rctrl", Keycode::RCtrl),
+    ("lalt", Keycode::LAlt),
+    ("ralt", Keycode::RAlt),
+    ("keypad0", Keycode::Kp0),
+    ("keypad1", Keycode::Kp1),
+    ("keypad2", Keycode::Kp2),
+    ("keypad3", Keycode::Kp3),
+    ("keypad4", Keycode::Kp4),
+    ("keypad5", Keycode::Kp5),
+    ("keypad6", Keycode::Kp6),
+    ("keypad7", Keycode::Kp7),
+    ("keypad8", Keycode::Kp8),
+    ("keypad9", Keycode::Kp9),
+    ("keypadenter", Keycode::KpEnter),
+    ("keypadplus", Keycode::KpPlus),
+    ("keypadminus", Keycode::KpMinus),
+];
+
+/// Looks up a key by the character it types, like `Q` or `7`, or by one of the names in
+/// `NAMED_KEYS`, ignoring case and spaces.
+fn keycode_from_name(name: &str) -> Option<Keycode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // SDL's keycodes for printable keys are the lowercase characters they type.
+        return Keycode::from_i32(c.to_ascii_lowercase() as i32);
+    }
+    let normalised: String = name
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    NAMED_KEYS
+        .iter()
+        .find(|(key_name, _)| *key_name == normalised)
+        .map(|(_, keycode)| *keycode)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeymapError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeymapError {}
+
+/// Which keyboard keys press which keys of the hex keypad.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: Vec<(Keycode, usize)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            bindings: DEFAULT_KEYS.to_vec(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Parses a keymap file, where every line binds a keypad key to a keyboard key by its name,
+    /// like `A = Z` or `0 = Space`. Keys of CHIP-8X's second keypad are prefixed with its number,
+    /// like `2:A = Return`. Keypad keys that aren't mentioned keep their default binding, and a
+    /// keypad key may be bound to several keyboard keys. Everything after a `#` is a comment.
+    pub fn parse(source: &str) -> Result<Self, KeymapError> {
+        let mut bindings = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message| KeymapError {
+                line: index + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, name) = match line.find('=') {
+                Some(equals) => (line[..equals].trim(), line[equals + 1..].trim()),
+                None => return Err(error(format!("expected 'key = name' but found '{}'", line))),
+            };
+            bindings.push(binding(key, name).map_err(error)?);
+        }
+
+        Ok(Keymap::with_bindings(bindings))
+    }
+
+    /// Builds a keymap from `(keypad key, key name)` pairs, which follow the same rules as the
+    /// lines of a keymap file.
+    pub fn from_pairs<'a, I>(pairs: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let bindings = pairs
+            .into_iter()
+            .map(|(key, name)| binding(key, name))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Keymap::with_bindings(bindings))
+    }
+
+    fn with_bindings(mut bindings: Vec<(Keycode, usize)>) -> Self {
+        // Defaults for rebound keypad keys, or bound to a keyboard key that's now used for
+        // something else, are dropped.
+        let mut defaults = DEFAULT_KEYS.to_vec();
+        defaults.retain(|&(default_keycode, default_key)| {
+            bindings
+                .iter()
+                .all(|&(keycode, key)| key != default_key && keycode != default_keycode)
+        });
+        bindings.extend(defaults);
+        Keymap { bindings }
+    }
+
+    /// The keypad key `keycode` is bound to, if any.
+    pub fn key(&self, keycode: Keycode) -> Option<usize> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == keycode)
+            .map(|(_, key)| *key)
+    }
+}
+
+fn binding(key: &str, name: &str) -> Result<(Keycode, usize), String> {
+    let (keypad, digit) = match key.find(':') {
+        Some(colon) => (key[..colon].trim(), key[colon + 1..].trim()),
+        None => ("1", key),
+    };
+    let key = match (keypad, usize::from_str_radix(digit, 16)) {
+        ("1", Ok(digit)) if digit < KEYPAD_SIZE => digit,
+        ("2", Ok(digit)) if digit < KEYPAD_SIZE => KEYPAD_SIZE + digit,
+        _ => return Err(format!("'{}' is not a keypad key", key)),
+    };
+    let keycode = keycode_from_name(name).ok_or_else(|| format!("'{}' is not a key name", name))?;
+    Ok((keycode, key))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::Keymap;
+    use sdl2::keyboard::Keycode;
+
+    #[test]
+    fn default() {
+        let keymap = Keymap::default();
+
+        assert_eq!(keymap.key(Keycode::Num1), Some(0x1));
+        assert_eq!(keymap.key(Keycode::Num4), Some(0xC));
+        assert_eq!(keymap.key(Keycode::X), Some(0x0));
+        assert_eq!(keymap.key(Keycode::V), Some(0xF));
+        assert_eq!(keymap.key(Keycode::Num0), Some(0x1C));
+        assert_eq!(keymap.key(Keycode::Comma), Some(0x10));
+        assert_eq!(keymap.key(Keycode::Space), None);
+    }
+
+    #[test]
+    fn parse() {
+        let keymap = Keymap::parse(
+            "
+            # Arrow keys for games that use 2, 4, 6 and 8
+            2 = Up
+            4 = Left
+            6 = Right
+            8 = Down
+            5 = Space
+            5 = Q        # a key can have more than one binding
+            0 = keypad 0
+            2:5 = Return
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(keymap.key(Keycode::Up), Some(0x2));
+        assert_eq!(keymap.key(Keycode::Space), Some(0x5));
+        assert_eq!(keymap.key(Keycode::Q), Some(0x5));
+        assert_eq!(keymap.key(Keycode::Kp0), Some(0x0));
+        assert_eq!(keymap.key(Keycode::Return), Some(0x15));
+        assert_eq!(keymap.key(Keycode::I), None);
+        // Rebound keypad keys lose their default bindings, the rest keep theirs.
+        assert_eq!(keymap.key(Keycode::Num2), None);
+        assert_eq!(keymap.key(Keycode::W), None);
+        assert_eq!(keymap.key(Keycode::V), Some(0xF));
+    }
+
+    #[test]
+    fn from_pairs() {
+        let keymap = Keymap::from_pairs(vec![("5", "Space"), ("a", "Return")]).unwrap();
+
+        assert_eq!(keymap.key(Keycode::Space), Some(0x5));
+        assert_eq!(keymap.key(Keycode::Return), Some(0xA));
+        assert_eq!(keymap.key(Keycode::Z), None);
+        assert!(Keymap::from_pairs(vec![("G", "Space")]).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Keymap::parse("2 Up").unwrap_err().line, 1);
+        assert_eq!(Keymap::parse("\n10 = Up").unwrap_err().line, 2);
+        assert_eq!(Keymap::parse("1 = NotAKey").unwrap_err().line, 1);
+        assert_eq!(Keymap::parse("3:1 = Up").unwrap_err().line, 1);
+    }
+}
@@ -0,0 +1,27 @@
+pub mod asm;
+pub mod cdp1802;
+pub mod config;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+pub mod emulator;
+pub mod expr;
+pub mod font;
+pub mod framebuffer;
+pub mod frontend;
+pub mod gdb;
+pub mod headless;
+pub mod instruments;
+pub mod keymap;
+pub mod machine;
+pub mod palette;
+pub mod phosphor;
+pub mod platform;
+pub mod profiler;
+pub mod quirks;
+pub mod romdb;
+pub mod timing;
+pub mod trace;
+pub mod upscale;
+pub mod variant;
+pub mod vip;
@@ -0,0 +1,126 @@
+use crate::{
+    emulator::{
+        Emulator,
+        ExecError,
+        Step,
+    },
+    palette::Rgb,
+    timing::Timing,
+};
+
+/// Something the frontend can run: a machine that executes instructions one at a time, shows a
+/// monochrome display, reads a 16 key keypad and sounds a tone. Only the CHIP-8 interpreter can be
+/// debugged and instrumented, through `emulator`.
+pub trait Machine {
+    /// Executes a single instruction, reporting which one it was.
+    fn step(&mut self) -> Result<Step, ExecError>;
+
+    /// How much of a frame `step`, which has just been executed, took up with `timing`.
+    fn cost(&self, step: Step, timing: Timing) -> u64;
+
+    /// Runs whatever happens once a frame that isn't driven by instructions.
+    fn tick(&mut self);
+
+    /// How many pixels wide and high the display is.
+    fn display_size(&self) -> (usize, usize);
+
+    fn pixel_at(&self, x: usize, y: usize) -> bool;
+
+    /// The colour the pixel at `(x, y)` is shown in, if the machine decides that rather than the
+    /// frontend's palette.
+    fn pixel_color(&self, _x: usize, _y: usize) -> Option<Rgb> {
+        None
+    }
+
+    fn key_press(&mut self, key: usize);
+
+    fn key_release(&mut self, key: usize);
+
+    fn is_sound_playing(&self) -> bool;
+
+    fn reset(&mut self);
+
+    fn hard_reset(&mut self);
+
+    fn pause(&mut self);
+
+    fn resume(&mut self);
+
+    fn is_paused(&self) -> bool;
+
+    /// The CHIP-8 interpreter, if this machine is one.
+    fn emulator(&self) -> Option<&Emulator> {
+        None
+    }
+
+    fn emulator_mut(&mut self) -> Option<&mut Emulator> {
+        None
+    }
+}
+
+impl Machine for Emulator {
+    fn step(&mut self) -> Result<Step, ExecError> {
+        Emulator::step(self)
+    }
+
+    fn cost(&self, step: Step, timing: Timing) -> u64 {
+        timing.cost(step, self)
+    }
+
+    fn tick(&mut self) {
+        Emulator::tick(self)
+    }
+
+    fn display_size(&self) -> (usize, usize) {
+        let framebuffer = self.framebuffer();
+        (framebuffer.width(), framebuffer.height())
+    }
+
+    fn pixel_at(&self, x: usize, y: usize) -> bool {
+        Emulator::pixel_at(self, x, y)
+    }
+
+    fn pixel_color(&self, x: usize, y: usize) -> Option<Rgb> {
+        Emulator::pixel_color(self, x, y)
+    }
+
+    fn key_press(&mut self, key: usize) {
+        Emulator::key_press(self, key)
+    }
+
+    fn key_release(&mut self, key: usize) {
+        Emulator::key_release(self, key)
+    }
+
+    fn is_sound_playing(&self) -> bool {
+        Emulator::is_sound_playing(self)
+    }
+
+    fn reset(&mut self) {
+        Emulator::reset(self)
+    }
+
+    fn hard_reset(&mut self) {
+        Emulator::hard_reset(self)
+    }
+
+    fn pause(&mut self) {
+        Emulator::pause(self)
+    }
+
+    fn resume(&mut self) {
+        Emulator::resume(self)
+    }
+
+    fn is_paused(&self) -> bool {
+        Emulator::is_paused(self)
+    }
+
+    fn emulator(&self) -> Option<&Emulator> {
+        Some(self)
+    }
+
+    fn emulator_mut(&mut self) -> Option<&mut Emulator> {
+        Some(self)
+    }
+}
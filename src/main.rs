@@ -1,21 +1,958 @@
-mod emulator;
-
+use clap::{
+    App,
+    AppSettings,
+    Arg,
+    ArgMatches,
+    SubCommand,
+};
 use std::{
-    env,
-    fs::File,
-    io::Read,
+    error::Error,
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        BufReader,
+        BufWriter,
+        Write,
+    },
+    net::TcpListener,
+    path::Path,
+    process,
 };
 
-use crate::emulator::Emulator;
+use chip8::{
+    asm,
+    config::{
+        self,
+        Config,
+        Settings,
+    },
+    coverage::Coverage,
+    debugger::{
+        self,
+        Debugger,
+    },
+    disasm,
+    emulator::{
+        Emulator,
+        DISPLAY_HEIGHT,
+        DISPLAY_WIDTH,
+    },
+    font::Font,
+    frontend::{
+        self,
+        Options,
+    },
+    gdb,
+    headless,
+    instruments::Instruments,
+    keymap::Keymap,
+    machine::Machine,
+    palette::Palette,
+    platform::{
+        MachineConfig,
+        Platform,
+    },
+    profiler::Profiler,
+    quirks::Quirks,
+    romdb::{
+        self,
+        Entry,
+    },
+    timing::Timing,
+    trace::{
+        self,
+        Comparison,
+        TraceFilter,
+        Tracer,
+    },
+    upscale::Filter,
+    variant::Variant,
+    vip::Vip,
+};
 
 // TODO: pausing doesn't work, the Event doesn't trigger. remove that functionality
 
+/// How many instructions are executed per second unless `--speed` or the configuration file say
+/// otherwise.
+const DEFAULT_SPEED: u64 = 600;
+const FRAMES_PER_SECOND: u64 = 60;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut emulator = Emulator::new();
-    let mut file = File::open(&args[1]).expect("Unable to open the ROM");
-    let mut rom = [0u8; 0xE00];
-    file.read(&mut rom).unwrap();
-    emulator.load(&rom);
-    emulator.run();
+    let matches = app().get_matches();
+    if let Err(err) = dispatch(&matches) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let rom = Arg::with_name("ROM")
+        .help("The CHIP-8 program to load")
+        .required(true);
+    let load_address = Arg::with_name("load-address")
+        .long("load-address")
+        .value_name("HEX")
+        .help("Loads the program at this address instead of the platform's default");
+
+    let machine_args = [
+        rom.clone(),
+        load_address.clone(),
+        Arg::with_name("platform")
+            .long("platform")
+            .value_name("NAME")
+            .help("The memory layout to emulate: chip8, eti660, schip or xochip"),
+        Arg::with_name("variant")
+            .long("variant")
+            .value_name("NAME")
+            .help("The CHIP-8 variant the program is for: chip8, chip8e, chip8x, chip10 or hires"),
+        Arg::with_name("quirks")
+            .long("quirks")
+            .value_name("PRESET")
+            .help("The interpreter behaviour to emulate: default, vip, schip or xochip"),
+        Arg::with_name("memory")
+            .long("memory")
+            .value_name("SIZE")
+            .possible_values(&["2K", "4K", "64K"])
+            .help("How much memory the machine has, instead of the platform's default"),
+        Arg::with_name("stack-depth")
+            .long("stack-depth")
+            .value_name("N")
+            .help("How many subroutine calls can be nested, or 'unlimited' [default: 16]"),
+        Arg::with_name("stack-address")
+            .long("stack-address")
+            .value_name("HEX")
+            .help("Keeps the stack in memory at this address, where programs can see it"),
+        Arg::with_name("vip-layout")
+            .long("vip-layout")
+            .conflicts_with_all(&["stack-address", "stack-depth"])
+            .help("Keeps the stack and the display at the top of memory, like the COSMAC VIP"),
+        Arg::with_name("font")
+            .long("font")
+            .value_name("FONT")
+            .help("A font name: vip, dream6800, eti660, chip48, schip or octo, or a font file"),
+        Arg::with_name("font-address")
+            .long("font-address")
+            .value_name("HEX")
+            .help("Stores the font at this address instead of 0x000"),
+        Arg::with_name("speed")
+            .long("speed")
+            .value_name("IPS")
+            .help("How many instructions to execute per second [default: 600]"),
+        Arg::with_name("seed")
+            .long("seed")
+            .value_name("N")
+            .help("Seeds the random number generator so runs are reproducible"),
+    ];
+    let vip = Arg::with_name("vip")
+        .long("vip")
+        .value_name("INTERPRETER")
+        .conflicts_with_all(&[
+            "platform",
+            "variant",
+            "quirks",
+            "stack-depth",
+            "stack-address",
+            "vip-layout",
+            "font",
+            "font-address",
+            "load-address",
+            "speed",
+            "seed",
+            "vip-timing",
+        ])
+        .help(
+            "Emulates the COSMAC VIP's hardware, running the program with this CHIP-8 interpreter",
+        );
+    let frames = Arg::with_name("frames")
+        .long("frames")
+        .value_name("N")
+        .default_value("60")
+        .help("How many frames (1/60th of a second each) to run for");
+    let trace_filter_args = [
+        Arg::with_name("trace-addresses")
+            .long("trace-addresses")
+            .value_name("RANGE")
+            .help("Only traces instructions at these addresses, like 0x200-0x2FF"),
+        Arg::with_name("trace-cycles")
+            .long("trace-cycles")
+            .value_name("RANGE")
+            .help("Only traces these instructions, counting from 0, like 100-200 or 100-"),
+    ];
+
+    App::new("chip8")
+        .about("A CHIP-8 emulator")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .global(true)
+                .help("The configuration file [default: ~/.config/chip8/config.toml]"),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs a program in a window")
+                .args(&machine_args)
+                .args(&[
+                    Arg::with_name("scale").long("scale").value_name("N").help(
+                        "How many window pixels wide and high every display pixel is [default: 20]",
+                    ),
+                    Arg::with_name("palette")
+                        .long("palette")
+                        .value_name("PALETTE")
+                        .help("A palette name, or a comma-separated list of hex colours"),
+                    Arg::with_name("filter")
+                        .long("filter")
+                        .value_name("NAME")
+                        .help("The upscaling filter: nearest, scanlines, scale2x, scale3x or crt"),
+                    Arg::with_name("phosphor")
+                        .long("phosphor")
+                        .value_name("DECAY")
+                        .help("Makes cleared pixels fade out, keeping this fraction every frame"),
+                    Arg::with_name("vblank")
+                        .long("vblank")
+                        .help("Only redraws the window once per frame"),
+                    Arg::with_name("keymap")
+                        .long("keymap")
+                        .value_name("FILE")
+                        .help("A file of 'key = name' lines binding keypad keys to keyboard keys"),
+                    Arg::with_name("mute")
+                        .long("mute")
+                        .help("Doesn't play the tone"),
+                    Arg::with_name("headless")
+                        .long("headless")
+                        .help("Runs without a window and prints the final screen"),
+                    Arg::with_name("screenshot")
+                        .long("screenshot")
+                        .value_name("FILE")
+                        .help("Saves the final screen to FILE as a PPM image, filtered and scaled"),
+                    Arg::with_name("record")
+                        .long("record")
+                        .value_name("FILE")
+                        .requires("headless")
+                        .conflicts_with("vip")
+                        .help("Saves every frame to FILE as a stream of PPM images, like --screenshot"),
+                    Arg::with_name("debug")
+                        .long("debug")
+                        .conflicts_with_all(&["headless", "vip"])
+                        .help("Starts stopped in the debugger, which reads commands from the terminal"),
+                    Arg::with_name("vip-timing")
+                        .long("vip-timing")
+                        .conflicts_with("speed")
+                        .help("Makes instructions take as long as they did on the COSMAC VIP"),
+                    vip.clone(),
+                    frames.clone(),
+                    Arg::with_name("trace")
+                        .long("trace")
+                        .value_name("FILE")
+                        .conflicts_with("vip")
+                        .help("Records every instruction executed to FILE, or to stderr for -"),
+                    Arg::with_name("profile")
+                        .long("profile")
+                        .conflicts_with("vip")
+                        .help("Prints a report of what the program spent its time on when it exits"),
+                    Arg::with_name("coverage")
+                        .long("coverage")
+                        .value_name("FILE")
+                        .conflicts_with("vip")
+                        .help("Writes which addresses were executed, read and written to FILE"),
+                    Arg::with_name("coverage-format")
+                        .long("coverage-format")
+                        .value_name("FORMAT")
+                        .possible_values(&["disasm", "lcov"])
+                        .default_value("disasm")
+                        .help("Writes the coverage as an annotated disassembly or an LCOV tracefile"),
+                ])
+                .args(&trace_filter_args),
+        )
+        .subcommand(
+            SubCommand::with_name("debug")
+                .about("Runs a program in the debugger without a window")
+                .args(&machine_args),
+        )
+        .subcommand(
+            SubCommand::with_name("gdb")
+                .about("Runs a program without a window, controlled by GDB over TCP")
+                .args(&machine_args)
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .default_value("1234")
+                        .help("The local port to wait for GDB on"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Disassembles a program")
+                .args(&[rom.clone(), load_address.clone()]),
+        )
+        .subcommand(
+            SubCommand::with_name("asm")
+                .about("Assembles a program")
+                .args(&[
+                    Arg::with_name("SOURCE")
+                        .help("The assembly source file")
+                        .required(true),
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Where to write the assembled program"),
+                    load_address,
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Shows what's known about a program and how it fits in memory")
+                .args(&[
+                    rom,
+                    Arg::with_name("platform")
+                        .long("platform")
+                        .value_name("NAME")
+                        .help("The memory layout to check against"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("trace")
+                .about("Runs a program without a window, printing every instruction executed")
+                .args(&machine_args)
+                .arg(
+                    Arg::with_name("cycles")
+                        .long("cycles")
+                        .value_name("N")
+                        .default_value("1000")
+                        .help("How many instructions to execute"),
+                )
+                .args(&trace_filter_args),
+        )
+        .subcommand(
+            SubCommand::with_name("trace-diff")
+                .about("Runs a program without a window and compares it with a reference trace")
+                .args(&machine_args)
+                .args(&[
+                    Arg::with_name("REFERENCE")
+                        .help("The trace to compare with, in the format printed by 'trace'")
+                        .required(true),
+                    Arg::with_name("context")
+                        .long("context")
+                        .value_name("N")
+                        .default_value("5")
+                        .help("How many matching instructions to show before a divergence"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Runs a program without a window and checks the final screen")
+                .args(&machine_args)
+                .args(&[
+                    Arg::with_name("vip-timing")
+                        .long("vip-timing")
+                        .conflicts_with("speed")
+                        .help("Makes instructions take as long as they did on the COSMAC VIP"),
+                    vip,
+                    frames,
+                    Arg::with_name("expect")
+                        .long("expect")
+                        .value_name("FILE")
+                        .help("The expected screen, as printed by this command without --expect"),
+                ]),
+        )
+}
+
+fn dispatch(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match matches.subcommand() {
+        ("run", Some(matches)) => run(matches),
+        ("debug", Some(matches)) => debug(matches),
+        ("gdb", Some(matches)) => gdb(matches),
+        ("disasm", Some(matches)) => disasm(matches),
+        ("asm", Some(matches)) => asm(matches),
+        ("info", Some(matches)) => info(matches),
+        ("trace", Some(matches)) => trace(matches),
+        ("trace-diff", Some(matches)) => trace_diff(matches),
+        ("test", Some(matches)) => test(matches),
+        _ => unreachable!(),
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if matches.is_present("vip") {
+        return run_vip(matches);
+    }
+    let (mut emulator, settings) = machine(matches)?;
+    let timing = timing(matches, &settings)?;
+    let mut instruments = instruments(matches, &mut emulator)?;
+    let options = options(matches, &settings, timing)?;
+    if matches.is_present("headless") {
+        let mut recording = match matches.value_of("record") {
+            Some(path) => {
+                Some(BufWriter::new(File::create(path).map_err(|err| {
+                    format!("unable to create '{}': {}", path, err)
+                })?))
+            }
+            None => None,
+        };
+        headless::run_observed(
+            &mut emulator,
+            parse(matches, "frames")?,
+            timing,
+            |step, emulator| instruments.record(step, emulator),
+            |emulator| match recording.as_mut() {
+                Some(recording) => recording.write_all(&headless::screenshot(
+                    emulator,
+                    &options.palette,
+                    options.filter,
+                    options.scale,
+                )),
+                None => Ok(()),
+            },
+        )?;
+        if let Some(mut recording) = recording {
+            recording.flush()?;
+        }
+        print!("{}", headless::screen_text(&emulator));
+        save_screenshot(
+            matches,
+            &emulator,
+            &options.palette,
+            options.filter,
+            options.scale,
+        )?;
+        return finish(matches, &emulator, instruments, timing);
+    }
+
+    let (palette, filter, scale) = (options.palette.clone(), options.filter, options.scale);
+    let result = frontend::run(&mut emulator, options, &mut instruments);
+    save_screenshot(matches, &emulator, &palette, filter, scale)?;
+    finish(matches, &emulator, instruments, timing)?;
+    result
+}
+
+/// Saves `machine`'s screen to the file given with `--screenshot`, if there is one.
+fn save_screenshot<M: Machine>(
+    matches: &ArgMatches,
+    machine: &M,
+    palette: &Palette,
+    filter: Filter,
+    scale: usize,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = matches.value_of("screenshot") {
+        fs::write(path, headless::screenshot(machine, palette, filter, scale))
+            .map_err(|err| format!("unable to write '{}': {}", path, err))?;
+    }
+    Ok(())
+}
+
+/// Runs a program on the emulated COSMAC VIP, in a window or without one.
+fn run_vip(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut vip = vip(matches)?;
+    let settings = settings(matches, &read_rom(matches)?)?;
+    let mut options = options(matches, &settings, Timing::Vip)?;
+    if matches.is_present("headless") {
+        headless::run(&mut vip, parse(matches, "frames")?, Timing::Vip)?;
+        print!("{}", headless::screen_text(&vip));
+        return save_screenshot(
+            matches,
+            &vip,
+            &options.palette,
+            options.filter,
+            options.scale,
+        );
+    }
+
+    // Redrawing after every CDP1802 instruction would be far too slow.
+    options.vblank_only = true;
+    let (palette, filter, scale) = (options.palette.clone(), options.filter, options.scale);
+    let result = frontend::run(&mut vip, options, &mut Instruments::default());
+    save_screenshot(matches, &vip, &palette, filter, scale)?;
+    result
+}
+
+/// The window options asked for by `run`'s options and the ROM's settings.
+fn options(
+    matches: &ArgMatches,
+    settings: &Settings,
+    timing: Timing,
+) -> Result<Options, Box<dyn Error>> {
+    let mut options = Options {
+        timing,
+        vblank_only: matches.is_present("vblank"),
+        mute: matches.is_present("mute"),
+        debug: matches.is_present("debug"),
+        ..Options::default()
+    };
+    if let Some(scale) = parse_or(matches, "scale", settings.scale)? {
+        options.scale = scale;
+    }
+    if let Some(spec) = matches.value_of("palette").or(settings.palette.as_deref()) {
+        options.palette = Palette::from_spec(spec)?;
+    }
+    if let Some(name) = matches.value_of("filter") {
+        options.filter = name.parse()?;
+    }
+    if matches.is_present("phosphor") {
+        options.phosphor_decay = Some(parse(matches, "phosphor")?);
+    }
+    if let Some(path) = matches.value_of("keymap") {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("unable to read '{}': {}", path, err))?;
+        options.keymap = Keymap::parse(&source).map_err(|err| format!("{}: {}", path, err))?;
+    } else if let Some(keymap) = settings.keymap.as_ref() {
+        options.keymap = Keymap::from_pairs(
+            keymap
+                .iter()
+                .map(|(key, name)| (key.as_str(), name.as_str())),
+        )
+        .map_err(|err| format!("invalid keymap in the configuration file: {}", err))?;
+    }
+    Ok(options)
+}
+
+/// The instruments asked for by `run`'s options.
+fn instruments(
+    matches: &ArgMatches,
+    emulator: &mut Emulator,
+) -> Result<Instruments, Box<dyn Error>> {
+    let output: Option<Box<dyn Write>> = match matches.value_of("trace") {
+        Some("-") => Some(Box::new(io::stderr())),
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|err| format!("unable to create '{}': {}", path, err))?;
+            Some(Box::new(BufWriter::new(file)))
+        }
+        None => None,
+    };
+    Ok(Instruments {
+        tracer: match output {
+            Some(output) => Some(Tracer::new(output, trace_filter(matches)?)),
+            None => None,
+        },
+        profiler: if matches.is_present("profile") {
+            Some(Profiler::new())
+        } else {
+            None
+        },
+        coverage: if matches.is_present("coverage") {
+            Some(Coverage::new(emulator))
+        } else {
+            None
+        },
+    })
+}
+
+/// Reports what the instruments recorded once a run is over.
+fn finish(
+    matches: &ArgMatches,
+    emulator: &Emulator,
+    instruments: Instruments,
+    timing: Timing,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(mut tracer) = instruments.tracer {
+        tracer.flush()?;
+    }
+    if let Some(profiler) = instruments.profiler {
+        eprintln!(
+            "{}",
+            profiler.report(emulator, timing.instructions_per_frame())
+        );
+    }
+    if let Some(coverage) = instruments.coverage {
+        let source = matches.value_of("ROM").unwrap();
+        let start = emulator.load_address();
+        let end = start.saturating_add(read_rom(matches)?.len() as u16);
+        let text = match matches.value_of("coverage-format").unwrap() {
+            "lcov" => coverage.lcov(source, start, end),
+            _ => coverage.annotated_disassembly(emulator.memory(), start, end),
+        };
+        let path = matches.value_of("coverage").unwrap();
+        fs::write(path, text).map_err(|err| format!("unable to write '{}': {}", path, err))?;
+        eprintln!("Coverage: {}", coverage.summary(start, end));
+    }
+    Ok(())
+}
+
+fn debug(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let (mut emulator, settings) = machine(matches)?;
+    let cycles_per_frame = cycles_per_frame(matches, &settings)?;
+    let stdin = io::stdin();
+    debugger::repl(
+        &mut emulator,
+        &mut Debugger::new(),
+        stdin.lock(),
+        io::stdout(),
+        cycles_per_frame,
+    )?;
+    Ok(())
+}
+
+fn gdb(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let (mut emulator, settings) = machine(matches)?;
+    let cycles_per_frame = cycles_per_frame(matches, &settings)?;
+    let listener = TcpListener::bind(("127.0.0.1", parse::<u16>(matches, "port")?))?;
+    eprintln!(
+        "Waiting for GDB on {}, connect with 'target remote {0}'",
+        listener.local_addr()?
+    );
+    gdb::serve(&mut emulator, &listener, cycles_per_frame)?;
+    Ok(())
+}
+
+fn disasm(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let rom = read_rom(matches)?;
+    let start = match matches.value_of("load-address") {
+        Some(address) => parse_address(address)?,
+        None => Platform::default().load_address(),
+    };
+    for line in disasm::disassemble_program(&rom, start) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn asm(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = matches.value_of("SOURCE").unwrap();
+    let source =
+        fs::read_to_string(path).map_err(|err| format!("unable to read '{}': {}", path, err))?;
+    let start = match matches.value_of("load-address") {
+        Some(address) => parse_address(address)?,
+        None => Platform::default().load_address(),
+    };
+    let program = asm::assemble(&source, start).map_err(|err| format!("{}: {}", path, err))?;
+    let output = matches.value_of("output").unwrap();
+    fs::write(output, program).map_err(|err| format!("unable to write '{}': {}", output, err))?;
+    Ok(())
+}
+
+fn info(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let rom = read_rom(matches)?;
+    let entry = romdb::lookup(&rom);
+    let recommended = entry.as_ref().map(|entry| &entry.settings);
+    let platform: Platform = match matches
+        .value_of("platform")
+        .or_else(|| recommended.and_then(|settings| settings.platform.as_deref()))
+    {
+        Some(name) => name.parse()?,
+        None => Platform::default(),
+    };
+    let start = platform.load_address() as usize;
+    let capacity = platform.memory_size() - start;
+    let lines = disasm::disassemble_program(&rom, start as u16);
+    let data = lines
+        .iter()
+        .filter(|line| line.text.starts_with("DW") || line.text.starts_with("DB"))
+        .count();
+
+    match entry.as_ref() {
+        Some(entry) => println!("Title:        {}", describe(entry)),
+        None => println!("Title:        not in the ROM database"),
+    }
+    println!("SHA-1:        {}", config::rom_hash(&rom));
+    if let Some(settings) = recommended {
+        if let Some(variant) = settings.variant.as_ref() {
+            println!("Variant:      {}", variant);
+        }
+        if let Some(quirks) = settings.quirks.as_ref() {
+            println!("Quirks:       {}", quirks);
+        }
+        if let Some(speed) = settings.speed {
+            println!("Speed:        {} instructions per second", speed);
+        }
+        if let Some(palette) = settings.palette.as_ref() {
+            println!("Palette:      {}", palette);
+        }
+        if let Some(font) = settings.font.as_ref() {
+            println!("Font:         {}", font);
+        }
+        if let Some(keymap) = settings.keymap.as_ref() {
+            let bindings: Vec<String> = keymap
+                .iter()
+                .map(|(key, name)| format!("{} = {}", key, name))
+                .collect();
+            println!("Keymap:       {}", bindings.join(", "));
+        }
+    }
+    println!("Size:         {} bytes", rom.len());
+    println!("Platform:     {}", platform);
+    println!(
+        "Loaded at:    {:#05X}-{:#05X}",
+        start,
+        start + rom.len().max(1) - 1
+    );
+    if rom.len() <= capacity {
+        println!("Free space:   {} bytes", capacity - rom.len());
+    } else {
+        println!(
+            "Free space:   none, {} bytes too large",
+            rom.len() - capacity
+        );
+    }
+    println!("Instructions: {}", lines.len() - data);
+    println!("Data words:   {}", data);
+    Ok(())
+}
+
+fn trace(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let (mut emulator, settings) = machine(matches)?;
+    let cycles_per_frame = cycles_per_frame(matches, &settings)?;
+    let cycles: u64 = parse(matches, "cycles")?;
+    let stdout = io::stdout();
+    let mut tracer = Tracer::new(stdout.lock(), trace_filter(matches)?);
+    for cycle in 0..cycles {
+        let step = emulator.step()?;
+        tracer.record(step, &emulator)?;
+        if (cycle + 1) % cycles_per_frame == 0 {
+            emulator.tick();
+        }
+    }
+    Ok(())
+}
+
+fn trace_diff(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let (mut emulator, settings) = machine(matches)?;
+    let cycles_per_frame = cycles_per_frame(matches, &settings)?;
+    let path = matches.value_of("REFERENCE").unwrap();
+    let reference =
+        File::open(path).map_err(|err| format!("unable to read '{}': {}", path, err))?;
+    let comparison = trace::compare(
+        &mut emulator,
+        BufReader::new(reference),
+        cycles_per_frame,
+        parse(matches, "context")?,
+    )?;
+    match comparison {
+        Comparison::Matched { cycles } => {
+            println!("Matched all {} instructions of the reference", cycles);
+            Ok(())
+        }
+        Comparison::Diverged(divergence) => {
+            print!("{}", divergence);
+            Err(format!("diverged from the reference at cycle {}", divergence.cycle).into())
+        }
+    }
+}
+
+fn trace_filter(matches: &ArgMatches) -> Result<TraceFilter, Box<dyn Error>> {
+    Ok(TraceFilter {
+        addresses: matches
+            .value_of("trace-addresses")
+            .map(trace::parse_address_range)
+            .transpose()?,
+        cycles: matches
+            .value_of("trace-cycles")
+            .map(trace::parse_cycle_range)
+            .transpose()?,
+    })
+}
+
+fn test(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let screen = if matches.is_present("vip") {
+        let mut vip = vip(matches)?;
+        headless::run(&mut vip, parse(matches, "frames")?, Timing::Vip)?;
+        headless::screen_text(&vip)
+    } else {
+        let (mut emulator, settings) = machine(matches)?;
+        headless::run(
+            &mut emulator,
+            parse(matches, "frames")?,
+            timing(matches, &settings)?,
+        )?;
+        headless::screen_text(&emulator)
+    };
+
+    match matches.value_of("expect") {
+        Some(path) => {
+            let expected = fs::read_to_string(path)
+                .map_err(|err| format!("unable to read '{}': {}", path, err))?;
+            if expected.trim_end() != screen.trim_end() {
+                print!("{}", screen);
+                return Err(format!("the screen doesn't match '{}'", path).into());
+            }
+            Ok(())
+        }
+        None => {
+            print!("{}", screen);
+            Ok(())
+        }
+    }
+}
+
+/// Creates an emulator set up by the machine options and the configuration file, with the ROM
+/// loaded. Also returns the ROM's settings from the configuration file, for the other options.
+fn machine(matches: &ArgMatches) -> Result<(Emulator, Settings), Box<dyn Error>> {
+    let rom = read_rom(matches)?;
+    let settings = settings(matches, &rom)?;
+
+    let platform: Platform = match matches
+        .value_of("platform")
+        .or(settings.platform.as_deref())
+    {
+        Some(name) => name.parse()?,
+        None => Platform::default(),
+    };
+    let mut config = platform.config();
+    if let Some(size) = matches.value_of("memory") {
+        config.memory_size = match size {
+            "2K" => MachineConfig::MEMORY_2K,
+            "4K" => MachineConfig::MEMORY_4K,
+            _ => MachineConfig::MEMORY_64K,
+        };
+    }
+    match matches.value_of("stack-depth") {
+        Some("unlimited") => config.stack_depth = None,
+        Some(_) => config.stack_depth = Some(parse(matches, "stack-depth")?),
+        None => {}
+    }
+    if let Some(address) = matches.value_of("stack-address") {
+        config.stack_address = Some(parse_address(address)?);
+    }
+    if matches.is_present("vip-layout") {
+        config = config.with_vip_layout();
+    }
+    let variant = match matches.value_of("variant").or(settings.variant.as_deref()) {
+        Some(name) => name.parse::<Variant>()?,
+        None => Variant::default(),
+    };
+    if config.vip_layout && variant.display_size() != (DISPLAY_WIDTH, DISPLAY_HEIGHT) {
+        return Err(format!(
+            "the {} variant's display doesn't fit in the COSMAC VIP's memory layout",
+            variant
+        )
+        .into());
+    }
+    if let Some(address) = variant.load_address() {
+        config.load_address = address;
+    }
+    if let Some(address) = matches.value_of("load-address") {
+        config.load_address = parse_address(address)?;
+    }
+    let mut emulator = Emulator::with_config(config);
+    emulator.set_variant(variant);
+    if let Some(preset) = matches.value_of("quirks").or(settings.quirks.as_deref()) {
+        emulator.set_quirks(preset.parse::<Quirks>()?);
+    }
+    if matches.is_present("seed") {
+        emulator.seed(parse(matches, "seed")?);
+    }
+    let font = match matches.value_of("font").or(settings.font.as_deref()) {
+        Some(spec) => load_font(spec)?,
+        None => Font::default(),
+    };
+    let font_address = match matches.value_of("font-address") {
+        Some(address) => parse_address(address)?,
+        None => emulator.font_address(),
+    };
+    emulator
+        .set_font(font, font_address)
+        .map_err(|err| format!("unable to load the font: {}", err))?;
+
+    emulator
+        .load_rom(&rom)
+        .map_err(|err| format!("unable to load the ROM: {}", err))?;
+    Ok((emulator, settings))
+}
+
+/// The settings for `rom` from the configuration file and the ROM database.
+fn settings(matches: &ArgMatches, rom: &[u8]) -> Result<Settings, Box<dyn Error>> {
+    let config = match matches.value_of("config") {
+        Some(path) => Config::load(Path::new(path))?,
+        None => Config::load_default()?,
+    };
+    let entry = romdb::lookup(rom);
+    if let Some(entry) = entry.as_ref() {
+        eprintln!("Recognised {}", describe(entry));
+    }
+    Ok(config.settings_for(rom, entry.as_ref().map(|entry| &entry.settings)))
+}
+
+/// Creates a COSMAC VIP running the interpreter given with `--vip`, with the ROM loaded.
+fn vip(matches: &ArgMatches) -> Result<Vip, Box<dyn Error>> {
+    let path = matches.value_of("vip").unwrap();
+    let interpreter =
+        fs::read(path).map_err(|err| format!("unable to read '{}': {}", path, err))?;
+    let memory_size = match matches.value_of("memory") {
+        Some("2K") => MachineConfig::MEMORY_2K,
+        Some("64K") => return Err("the COSMAC VIP has at most 4K of memory".into()),
+        _ => MachineConfig::MEMORY_4K,
+    };
+    let mut vip = Vip::new(&interpreter, memory_size)
+        .map_err(|err| format!("unable to load the interpreter: {}", err))?;
+    vip.load_rom(&read_rom(matches)?)
+        .map_err(|err| format!("unable to load the ROM: {}", err))?;
+    Ok(vip)
+}
+
+/// The title and author of a program in the ROM database.
+fn describe(entry: &Entry) -> String {
+    match entry.author.as_ref() {
+        Some(author) => format!("{} by {}", entry.title, author),
+        None => entry.title.clone(),
+    }
+}
+
+/// The built-in font called `spec`, or else the font in the file at `spec`.
+fn load_font(spec: &str) -> Result<Font, Box<dyn Error>> {
+    match spec.parse::<Font>() {
+        Ok(font) => Ok(font),
+        Err(err) if !Path::new(spec).is_file() => Err(err.into()),
+        Err(_) => {
+            let bytes =
+                fs::read(spec).map_err(|err| format!("unable to read '{}': {}", spec, err))?;
+            Ok(Font::from_bytes(&bytes)?)
+        }
+    }
+}
+
+/// How long instructions take: as long as on the COSMAC VIP if asked, or else all the same, with
+/// as many executed every frame as the speed says.
+fn timing(matches: &ArgMatches, settings: &Settings) -> Result<Timing, Box<dyn Error>> {
+    if matches.is_present("vip-timing") {
+        return Ok(Timing::Vip);
+    }
+    Ok(Timing::Instructions(cycles_per_frame(matches, settings)?))
+}
+
+fn cycles_per_frame(matches: &ArgMatches, settings: &Settings) -> Result<u64, Box<dyn Error>> {
+    let speed = parse_or(matches, "speed", settings.speed)?.unwrap_or(DEFAULT_SPEED);
+    Ok((speed / FRAMES_PER_SECOND).max(1))
+}
+
+fn read_rom(matches: &ArgMatches) -> Result<Vec<u8>, Box<dyn Error>> {
+    let path = matches.value_of("ROM").unwrap();
+    Ok(fs::read(path).map_err(|err| format!("unable to read '{}': {}", path, err))?)
+}
+
+/// Parses the value of the option `name`, which must have a value or a default.
+fn parse<T>(matches: &ArgMatches, name: &str) -> Result<T, Box<dyn Error>>
+where
+    T: std::str::FromStr,
+{
+    let value = matches.value_of(name).unwrap();
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for --{}", value, name).into())
+}
+
+/// Parses the value of the option `name` if it was given, or else falls back to `setting`.
+fn parse_or<T>(
+    matches: &ArgMatches,
+    name: &str,
+    setting: Option<T>,
+) -> Result<Option<T>, Box<dyn Error>>
+where
+    T: std::str::FromStr,
+{
+    if matches.is_present(name) {
+        parse(matches, name).map(Some)
+    } else {
+        Ok(setting)
+    }
+}
+
+fn parse_address(text: &str) -> Result<u16, Box<dyn Error>> {
+    asm::parse_hex(text).ok_or_else(|| format!("'{}' is not a hexadecimal address", text).into())
 }
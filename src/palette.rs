@@ -0,0 +1,252 @@
+use std::{
+    error::Error,
+    fmt,
+};
+
+/// A colour in the RGB colour space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    /// Parses a colour written as `RRGGBB`, optionally prefixed with `#`, `0x` or `0X`.
+    pub fn from_hex(hex: &str) -> Result<Self, PaletteError> {
+        let hex_digits = hex.trim();
+        let digits = hex_digits
+            .strip_prefix('#')
+            .or_else(|| hex_digits.strip_prefix("0x"))
+            .or_else(|| hex_digits.strip_prefix("0X"))
+            .unwrap_or(hex_digits);
+        if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(PaletteError::InvalidColor(hex.to_string()));
+        }
+        let value = u32::from_str_radix(digits, 16).unwrap();
+
+        Ok(Rgb::new(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaletteError {
+    InvalidColor(String),
+    TooFewColors(usize),
+    UnknownPalette(String),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::InvalidColor(color) => {
+                write!(f, "'{}' is not a colour of the form #RRGGBB", color)
+            }
+            PaletteError::TooFewColors(count) => write!(
+                f,
+                "a palette needs at least a background and a foreground colour, got {}",
+                count
+            ),
+            PaletteError::UnknownPalette(name) => write!(
+                f,
+                "unknown palette '{}', expected one of: {}",
+                name,
+                PRESETS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl Error for PaletteError {}
+
+// Built-in palettes, in the order they are cycled through. The first colour is the background and
+// the second the foreground; any further colours are used by display modes with more than one
+// plane, where a pixel's colour index is made up of one bit per plane.
+const PRESETS: [(&str, &[Rgb]); 5] = [
+    (
+        "classic",
+        &[Rgb::new(0x00, 0x00, 0x00), Rgb::new(0xFF, 0xFF, 0xFF)],
+    ),
+    (
+        "green",
+        &[Rgb::new(0x0A, 0x1A, 0x0A), Rgb::new(0x33, 0xFF, 0x66)],
+    ),
+    (
+        "amber",
+        &[Rgb::new(0x1A, 0x10, 0x00), Rgb::new(0xFF, 0xB0, 0x00)],
+    ),
+    (
+        "lcd",
+        &[
+            Rgb::new(0x9B, 0xBC, 0x0F),
+            Rgb::new(0x0F, 0x38, 0x0F),
+            Rgb::new(0x8B, 0xAC, 0x0F),
+            Rgb::new(0x30, 0x62, 0x30),
+        ],
+    ),
+    (
+        "octo",
+        &[
+            Rgb::new(0x99, 0x66, 0x00),
+            Rgb::new(0xFF, 0xCC, 0x00),
+            Rgb::new(0xFF, 0x66, 0x00),
+            Rgb::new(0x66, 0x22, 0x00),
+        ],
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    name: String,
+    colors: Vec<Rgb>,
+}
+
+impl Palette {
+    /// Looks up a built-in palette by name, or builds a custom one if `spec` is a comma-separated
+    /// list of hex colours such as `#000000,#FFFFFF`.
+    pub fn from_spec(spec: &str) -> Result<Self, PaletteError> {
+        if spec.contains(',') || spec.starts_with('#') {
+            let colors = spec
+                .split(',')
+                .map(Rgb::from_hex)
+                .collect::<Result<Vec<_>, _>>()?;
+            return Palette::custom(colors);
+        }
+
+        PRESETS
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(spec.trim()))
+            .map(Palette::preset)
+            .ok_or_else(|| PaletteError::UnknownPalette(spec.to_string()))
+    }
+
+    pub fn custom(colors: Vec<Rgb>) -> Result<Self, PaletteError> {
+        if colors.len() < 2 {
+            return Err(PaletteError::TooFewColors(colors.len()));
+        }
+
+        Ok(Palette {
+            name: String::from("custom"),
+            colors,
+        })
+    }
+
+    fn preset(index: usize) -> Self {
+        let (name, colors) = PRESETS[index];
+        Palette {
+            name: name.to_string(),
+            colors: colors.to_vec(),
+        }
+    }
+
+    /// The built-in palette following this one, wrapping around at the end. Custom palettes are
+    /// followed by the first built-in palette.
+    pub fn next(&self) -> Self {
+        let index = PRESETS
+            .iter()
+            .position(|(name, _)| *name == self.name)
+            .map_or(0, |index| (index + 1) % PRESETS.len());
+        Palette::preset(index)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    /// The colour for a pixel whose planes are set according to `index`. Palettes with fewer
+    /// colours than the display mode needs fall back to the foreground colour.
+    pub fn color(&self, index: usize) -> Rgb {
+        match index {
+            0 => self.background(),
+            _ => *self.colors.get(index).unwrap_or(&self.colors[1]),
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::preset(0)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        Palette,
+        PaletteError,
+        Rgb,
+    };
+
+    #[test]
+    fn from_hex() {
+        assert_eq!(Rgb::from_hex("#FF8000"), Ok(Rgb::new(0xFF, 0x80, 0x00)));
+        assert_eq!(Rgb::from_hex("0x0a1b2c"), Ok(Rgb::new(0x0A, 0x1B, 0x2C)));
+        assert_eq!(Rgb::from_hex("0XFF0000"), Ok(Rgb::new(0xFF, 0x00, 0x00)));
+        assert!(Rgb::from_hex("##FF0000").is_err());
+        assert!(Rgb::from_hex("#0xFF0000").is_err());
+        assert!(Rgb::from_hex("#FFF").is_err());
+        assert!(Rgb::from_hex("#GGGGGG").is_err());
+    }
+
+    #[test]
+    fn from_spec_preset() {
+        let palette = Palette::from_spec("Amber").unwrap();
+
+        assert_eq!(palette.name(), "amber");
+        assert_eq!(palette.color(1), Rgb::new(0xFF, 0xB0, 0x00));
+    }
+
+    #[test]
+    fn from_spec_custom() {
+        let palette = Palette::from_spec("#101010,#F0F0F0,#FF0000").unwrap();
+
+        assert_eq!(palette.name(), "custom");
+        assert_eq!(palette.background(), Rgb::new(0x10, 0x10, 0x10));
+        assert_eq!(palette.color(2), Rgb::new(0xFF, 0x00, 0x00));
+        // Missing plane colours fall back to the foreground
+        assert_eq!(palette.color(3), palette.color(1));
+    }
+
+    #[test]
+    fn from_spec_errors() {
+        assert_eq!(
+            Palette::from_spec("#000000"),
+            Err(PaletteError::TooFewColors(1))
+        );
+        assert_eq!(
+            Palette::from_spec("sepia"),
+            Err(PaletteError::UnknownPalette(String::from("sepia")))
+        );
+    }
+
+    #[test]
+    fn next_wraps_around() {
+        let mut palette = Palette::default();
+        for _ in 0..5 {
+            palette = palette.next();
+        }
+
+        assert_eq!(palette, Palette::default());
+        assert_eq!(
+            Palette::from_spec("#000000,#FFFFFF").unwrap().next().name(),
+            "classic"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{
+    emulator::{
+        DISPLAY_HEIGHT,
+        DISPLAY_WIDTH,
+    },
+    palette::{
+        Palette,
+        Rgb,
+    },
+};
+
+// Intensities below this are indistinguishable from the background once quantised to 8 bits.
+const CUTOFF: f32 = 1.0 / 255.0;
+
+/// Simulates the afterglow of a CRT phosphor by keeping a per-pixel brightness that is refreshed
+/// whenever a pixel is lit and fades out geometrically once it is cleared. Games that erase and
+/// redraw their sprites every frame then show a steady image instead of flickering.
+pub struct Phosphor {
+    decay: f32,
+    intensity: Vec<Vec<f32>>,
+}
+
+impl Phosphor {
+    /// `decay` is the fraction of its brightness an unlit pixel keeps on every update, so `0.0`
+    /// disables the effect and values close to `1.0` give a long trail.
+    pub fn new(decay: f32) -> Self {
+        Phosphor::with_size(decay, DISPLAY_WIDTH, DISPLAY_HEIGHT)
+    }
+
+    /// A phosphor for a display `width` pixels wide and `height` high.
+    pub fn with_size(decay: f32, width: usize, height: usize) -> Self {
+        Phosphor {
+            decay: decay.clamp(0.0, 1.0),
+            intensity: vec![vec![0.0; width]; height],
+        }
+    }
+
+    /// Blends the next frame into the persisted image. `is_lit` reports whether the pixel at
+    /// `(x, y)` is set in the frame being displayed.
+    pub fn update<F>(&mut self, is_lit: F)
+    where
+        F: Fn(usize, usize) -> bool,
+    {
+        for (y, row) in self.intensity.iter_mut().enumerate() {
+            for (x, intensity) in row.iter_mut().enumerate() {
+                *intensity = if is_lit(x, y) {
+                    1.0
+                } else if *intensity * self.decay < CUTOFF {
+                    0.0
+                } else {
+                    *intensity * self.decay
+                };
+            }
+        }
+    }
+
+    /// The brightness of the pixel at `(x, y)` between `0.0` (off) and `1.0` (fully lit).
+    pub fn intensity(&self, x: usize, y: usize) -> f32 {
+        self.intensity[y][x]
+    }
+
+    /// The colour of the pixel at `(x, y)`, mixed between the palette's background and
+    /// foreground according to its brightness.
+    pub fn color(&self, x: usize, y: usize, palette: &Palette) -> Rgb {
+        let intensity = self.intensity(x, y);
+        let background = palette.background();
+        let foreground = palette.color(1);
+        let mix =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * intensity).round() as u8;
+
+        Rgb::new(
+            mix(background.r, foreground.r),
+            mix(background.g, foreground.g),
+            mix(background.b, foreground.b),
+        )
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::Phosphor;
+    use crate::palette::{
+        Palette,
+        Rgb,
+    };
+
+    #[test]
+    fn lit_pixels_are_fully_bright() {
+        let mut phosphor = Phosphor::new(0.5);
+
+        phosphor.update(|x, y| x == 3 && y == 4);
+
+        assert_eq!(phosphor.intensity(3, 4), 1.0);
+        assert_eq!(phosphor.intensity(4, 3), 0.0);
+    }
+
+    #[test]
+    fn cleared_pixels_decay() {
+        let mut phosphor = Phosphor::new(0.5);
+
+        phosphor.update(|_, _| true);
+        phosphor.update(|_, _| false);
+        assert_eq!(phosphor.intensity(0, 0), 0.5);
+
+        phosphor.update(|_, _| false);
+        assert_eq!(phosphor.intensity(0, 0), 0.25);
+
+        for _ in 0..8 {
+            phosphor.update(|_, _| false);
+        }
+        assert_eq!(phosphor.intensity(0, 0), 0.0);
+    }
+
+    #[test]
+    fn no_decay_clears_immediately() {
+        let mut phosphor = Phosphor::new(0.0);
+
+        phosphor.update(|_, _| true);
+        phosphor.update(|_, _| false);
+
+        assert_eq!(phosphor.intensity(10, 10), 0.0);
+    }
+
+    #[test]
+    fn color_blends_between_background_and_foreground() {
+        let palette = Palette::from_spec("#000000,#FF8040").unwrap();
+        let mut phosphor = Phosphor::new(0.5);
+
+        phosphor.update(|_, _| true);
+        assert_eq!(phosphor.color(0, 0, &palette), Rgb::new(0xFF, 0x80, 0x40));
+
+        phosphor.update(|_, _| false);
+        assert_eq!(phosphor.color(0, 0, &palette), Rgb::new(0x80, 0x40, 0x20));
+    }
+}
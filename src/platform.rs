@@ -0,0 +1,181 @@
+use std::{
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
+/// The machines and interpreters CHIP-8 programs were written for, which differ in how much
+/// memory they give a program and where in it the program is loaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    /// The original interpreter on the COSMAC VIP, and everything compatible with it.
+    #[default]
+    Chip8,
+    /// The ETI-660, whose interpreter loads programs at 0x600.
+    Eti660,
+    /// SUPER-CHIP on the HP 48 calculators.
+    SuperChip,
+    /// Octo's XO-CHIP extension, with 64K of memory.
+    XoChip,
+}
+
+/// The resources of a CHIP-8 machine and where things live in its memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MachineConfig {
+    /// The total amount of addressable memory, in bytes.
+    pub memory_size: usize,
+    /// How many subroutine calls can be nested, or `None` for as many as there's room for, which
+    /// is useful for debugging programs that overflow the stack.
+    pub stack_depth: Option<usize>,
+    /// The address programs are loaded at and start executing from.
+    pub load_address: u16,
+    /// Where the stack is kept in memory, two bytes to an entry with the outermost call first, or
+    /// `None` to keep it out of the program's reach as most interpreters do.
+    pub stack_address: Option<u16>,
+    /// Whether the stack and the display are kept at the top of memory, where the COSMAC VIP's
+    /// interpreter keeps them. This takes the place of `stack_address`, and only has room for a
+    /// 64x32 display.
+    pub vip_layout: bool,
+}
+
+impl MachineConfig {
+    /// The 2K of memory of the smallest COSMAC VIPs.
+    pub const MEMORY_2K: usize = 0x800;
+    /// The 4K of memory most interpreters give programs.
+    pub const MEMORY_4K: usize = 0x1000;
+    /// The 64K of memory of XO-CHIP.
+    pub const MEMORY_64K: usize = 0x10000;
+
+    /// This machine with the stack and the display in memory where the COSMAC VIP's interpreter
+    /// keeps them, for programs that read or write them directly. The last 0x160 bytes of memory
+    /// are the interpreter's, so with 4K programs can only use memory up to 0xE9F. The stack grows
+    /// down from 0x131 bytes below the top, 0xECF with 4K, and is 12 entries deep, and the display
+    /// takes up the last 0x100 bytes.
+    pub fn with_vip_layout(self) -> Self {
+        MachineConfig {
+            stack_depth: Some(12),
+            stack_address: None,
+            vip_layout: true,
+            ..self
+        }
+    }
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Platform::default().config()
+    }
+}
+
+const PLATFORMS: [(&str, Platform); 4] = [
+    ("chip8", Platform::Chip8),
+    ("eti660", Platform::Eti660),
+    ("schip", Platform::SuperChip),
+    ("xochip", Platform::XoChip),
+];
+
+impl Platform {
+    /// The machine programs for this platform expect.
+    pub fn config(self) -> MachineConfig {
+        MachineConfig {
+            memory_size: self.memory_size(),
+            stack_depth: Some(16),
+            load_address: self.load_address(),
+            stack_address: None,
+            vip_layout: false,
+        }
+    }
+
+    /// The total amount of addressable memory, in bytes.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::XoChip => MachineConfig::MEMORY_64K,
+            _ => MachineConfig::MEMORY_4K,
+        }
+    }
+
+    /// The address programs are loaded at and start executing from.
+    pub fn load_address(self) -> u16 {
+        match self {
+            Platform::Eti660 => 0x600,
+            _ => 0x200,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownPlatform(String);
+
+impl fmt::Display for UnknownPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown platform '{}', expected one of: {}",
+            self.0,
+            PLATFORMS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+impl Error for UnknownPlatform {}
+
+impl FromStr for Platform {
+    type Err = UnknownPlatform;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalised: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if normalised == "superchip" {
+            return Ok(Platform::SuperChip);
+        }
+        PLATFORMS
+            .iter()
+            .find(|(platform_name, _)| *platform_name == normalised)
+            .map(|(_, platform)| *platform)
+            .ok_or_else(|| UnknownPlatform(name.to_string()))
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = PLATFORMS
+            .iter()
+            .find(|(_, platform)| platform == self)
+            .unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::Platform;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("CHIP-8".parse(), Ok(Platform::Chip8));
+        assert_eq!("eti-660".parse(), Ok(Platform::Eti660));
+        assert_eq!("XO-CHIP".parse(), Ok(Platform::XoChip));
+        assert_eq!("SUPER-CHIP".parse(), Ok(Platform::SuperChip));
+        assert!("chip-16".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn program_space() {
+        assert_eq!(
+            Platform::Chip8.memory_size() - Platform::Chip8.load_address() as usize,
+            0xE00
+        );
+        assert_eq!(Platform::Eti660.load_address(), 0x600);
+        assert_eq!(Platform::XoChip.memory_size(), 0x10000);
+        assert_eq!(Platform::Eti660.config().load_address, 0x600);
+        assert_eq!(Platform::Chip8.config().stack_depth, Some(16));
+    }
+}
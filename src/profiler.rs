@@ -0,0 +1,216 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt::Write,
+};
+
+use crate::{
+    disasm::disassemble,
+    emulator::{
+        Emulator,
+        Step,
+    },
+};
+
+/// How many of the hottest addresses the report lists.
+const HOTTEST_ADDRESSES: usize = 10;
+const FRAMES_PER_SECOND: u64 = 60;
+
+/// Counts what a program spends its instructions on.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    instructions: u64,
+    addresses: HashMap<u16, u64>,
+    /// Executions of every kind of instruction, keyed by its `instruction_class`.
+    classes: BTreeMap<String, u64>,
+    /// How often every subroutine was called, by its address.
+    calls: BTreeMap<u16, u64>,
+    /// How many instructions were spent waiting for a key to be pressed.
+    key_waits: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Records `step`, which has just been executed by `emulator`.
+    pub fn record(&mut self, step: Step, emulator: &Emulator) {
+        self.instructions += 1;
+        *self.addresses.entry(step.address).or_insert(0) += 1;
+        *self
+            .classes
+            .entry(instruction_class(step.opcode))
+            .or_insert(0) += 1;
+        if step.opcode & 0xF000 == 0x2000 {
+            *self.calls.entry(step.opcode & 0x0FFF).or_insert(0) += 1;
+        }
+        // LD Vx, K executes again and again until a key is pressed.
+        if step.opcode & 0xF0FF == 0xF00A && emulator.program_counter() == step.address {
+            self.key_waits += 1;
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// How many times the instruction at `address` was executed.
+    pub fn executions_at(&self, address: u16) -> u64 {
+        self.addresses.get(&address).copied().unwrap_or(0)
+    }
+
+    /// The report printed at the end of a profiled run. `emulator` provides the opcodes of the
+    /// hottest instructions, and `cycles_per_frame` turns instruction counts into time.
+    pub fn report(&self, emulator: &Emulator, cycles_per_frame: u64) -> String {
+        let mut report = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let seconds = |count: u64| count as f64 / (cycles_per_frame * FRAMES_PER_SECOND) as f64;
+        writeln!(
+            report,
+            "Profile of {} instructions ({:.1} s)",
+            self.instructions,
+            seconds(self.instructions)
+        )
+        .unwrap();
+
+        report.push_str("\nHottest addresses:\n");
+        let mut addresses: Vec<(u16, u64)> = self
+            .addresses
+            .iter()
+            .map(|(&address, &count)| (address, count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(address, count) in addresses.iter().take(HOTTEST_ADDRESSES) {
+            let opcode = fetch(emulator, address);
+            writeln!(
+                report,
+                "  {:>10}  {:5.1}%  {:#05X}: {:04X}  {}",
+                count,
+                percent(count),
+                address,
+                opcode,
+                disassemble(opcode)
+            )
+            .unwrap();
+        }
+
+        report.push_str("\nInstruction mix:\n");
+        let mut classes: Vec<(&String, &u64)> = self.classes.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (class, &count) in classes {
+            writeln!(
+                report,
+                "  {:>10}  {:5.1}%  {}",
+                count,
+                percent(count),
+                class
+            )
+            .unwrap();
+        }
+
+        report.push_str("\nSubroutine calls:\n");
+        if self.calls.is_empty() {
+            report.push_str("  none\n");
+        }
+        let mut calls: Vec<(&u16, &u64)> = self.calls.iter().collect();
+        calls.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (address, count) in calls {
+            writeln!(report, "  {:>10}  {:#05X}", count, address).unwrap();
+        }
+
+        write!(
+            report,
+            "\nWaiting for a key: {} instructions, {:.1}% ({:.1} s)",
+            self.key_waits,
+            percent(self.key_waits),
+            seconds(self.key_waits)
+        )
+        .unwrap();
+        report
+    }
+}
+
+/// The kind of instruction `opcode` is, as its pattern in Cowgod's reference followed by its
+/// mnemonic, like `Dxyn DRW` or `8xy4 ADD`.
+fn instruction_class(opcode: u16) -> String {
+    let pattern = match opcode & 0xF000 {
+        0x0000 if opcode == 0x00E0 || opcode == 0x00EE => format!("{:04X}", opcode),
+        0x0000 | 0x1000 | 0x2000 | 0xA000 | 0xB000 => format!("{:X}nnn", opcode >> 12),
+        0x3000 | 0x4000 | 0x6000 | 0x7000 | 0xC000 => format!("{:X}xkk", opcode >> 12),
+        0xD000 => String::from("Dxyn"),
+        0xE000 | 0xF000 => format!("{:X}x{:02X}", opcode >> 12, opcode & 0x00FF),
+        _ => format!("{:X}xy{:X}", opcode >> 12, opcode & 0x000F),
+    };
+    let mnemonic = disassemble(opcode);
+    format!("{} {}", pattern, mnemonic.split(' ').next().unwrap())
+}
+
+fn fetch(emulator: &Emulator, address: u16) -> u16 {
+    let memory = emulator.memory();
+    let address = address as usize;
+    let high = memory.get(address).copied().unwrap_or(0) as u16;
+    let low = memory.get(address + 1).copied().unwrap_or(0) as u16;
+    high << 8 | low
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        instruction_class,
+        Profiler,
+    };
+    use crate::emulator::Emulator;
+
+    // 0x200: LD V0, 0x03
+    // 0x202: CALL 0x20C
+    // 0x204: ADD V0, 0xFF
+    // 0x206: SE V0, 0x00
+    // 0x208: JP 0x202
+    // 0x20A: LD V1, K
+    // 0x20C: RET
+    const PROGRAM: [u8; 14] = [
+        0x60, 0x03, 0x22, 0x0C, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02, 0xF1, 0x0A, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn counts() {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&PROGRAM).unwrap();
+        let mut profiler = Profiler::new();
+
+        // Three times around the loop, the last one leaving it early, then 5 instructions waiting
+        // for a key.
+        for _ in 0..(1 + 5 + 5 + 4 + 5) {
+            let step = emulator.step().unwrap();
+            profiler.record(step, &emulator);
+        }
+
+        assert_eq!(profiler.instructions(), 20);
+        assert_eq!(profiler.executions_at(0x202), 3);
+        assert_eq!(profiler.executions_at(0x20A), 5);
+        assert_eq!(profiler.calls[&0x20C], 3);
+        assert_eq!(profiler.classes["7xkk ADD"], 3);
+        assert_eq!(profiler.key_waits, 5);
+
+        let report = profiler.report(&emulator, 10);
+        assert!(report.starts_with("Profile of 20 instructions (0.0 s)"));
+        assert!(report.contains("\n           5   25.0%  0x20A: F10A  LD V1, K\n"));
+        assert!(report.contains("\n           3  0x20C\n"));
+        assert!(report.ends_with("Waiting for a key: 5 instructions, 25.0% (0.0 s)"));
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(instruction_class(0x00E0), "00E0 CLS");
+        assert_eq!(instruction_class(0x1234), "1nnn JP");
+        assert_eq!(instruction_class(0x6A12), "6xkk LD");
+        assert_eq!(instruction_class(0x8AB6), "8xy6 SHR");
+        assert_eq!(instruction_class(0xD125), "Dxyn DRW");
+        assert_eq!(instruction_class(0xF233), "Fx33 LD");
+        assert_eq!(instruction_class(0xE1FF), "ExFF DW");
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
+/// Behaviours that differ between CHIP-8 interpreters. Programs written for one interpreter often
+/// rely on its particular behaviour, so these have to match the program for it to run correctly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6` and `8xyE` shift Vy and store the result in Vx, rather than shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// `Fx55` and `Fx65` leave I pointing just past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    /// `Bnnn` jumps to nnn plus Vx, where x is the highest nibble of nnn, rather than plus V0.
+    pub jump_uses_vx: bool,
+    /// `8xy1`, `8xy2` and `8xy3` reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// Sprites drawn partly off screen are cut off at the edges rather than wrapping around.
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP 48.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+    };
+
+    /// Octo's XO-CHIP.
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+    };
+}
+
+const PRESETS: [(&str, Quirks); 4] = [
+    (
+        "default",
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+        },
+    ),
+    ("vip", Quirks::VIP),
+    ("schip", Quirks::SUPER_CHIP),
+    ("xochip", Quirks::XO_CHIP),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownPreset(String);
+
+impl fmt::Display for UnknownPreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown quirk preset '{}', expected one of: {}",
+            self.0,
+            PRESETS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+impl Error for UnknownPreset {}
+
+impl FromStr for Quirks {
+    type Err = UnknownPreset;
+
+    /// Looks up a preset by name.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalised: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        PRESETS
+            .iter()
+            .find(|(preset_name, _)| *preset_name == normalised)
+            .map(|(_, quirks)| *quirks)
+            .ok_or_else(|| UnknownPreset(name.to_string()))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::Quirks;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("default".parse(), Ok(Quirks::default()));
+        assert_eq!("VIP".parse(), Ok(Quirks::VIP));
+        assert_eq!("xo-chip".parse(), Ok(Quirks::XO_CHIP));
+        assert!("chip-48".parse::<Quirks>().is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use serde::Deserialize;
+
+use crate::config::{
+    rom_hash,
+    Settings,
+};
+
+/// The bundled database, compiled into the binary.
+const DATABASE: &str = include_str!("../data/roms.toml");
+
+/// A program in the ROM database.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Entry {
+    /// The lowercase hex SHA-1 hash of the program.
+    pub sha1: String,
+    pub title: String,
+    pub author: Option<String>,
+    /// The platform, variant, quirks, speed, keymap, palette and font the program runs best with.
+    #[serde(flatten)]
+    pub settings: Settings,
+}
+
+#[derive(Deserialize)]
+struct Database {
+    rom: Vec<Entry>,
+}
+
+/// Every program in the database.
+pub fn entries() -> Vec<Entry> {
+    let database: Database = toml::from_str(DATABASE).expect("The ROM database is invalid");
+    database.rom
+}
+
+/// Finds `rom` in the database by its hash.
+pub fn lookup(rom: &[u8]) -> Option<Entry> {
+    let hash = rom_hash(rom);
+    entries().into_iter().find(|entry| entry.sha1 == hash)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        entries,
+        lookup,
+    };
+    use crate::{
+        font::Font,
+        keymap::Keymap,
+        palette::Palette,
+        platform::Platform,
+        quirks::Quirks,
+        variant::Variant,
+    };
+
+    #[test]
+    fn entries_are_valid() {
+        let entries = entries();
+        assert!(!entries.is_empty());
+
+        for entry in &entries {
+            assert_eq!(entry.sha1.len(), 40, "{}", entry.title);
+            assert_eq!(
+                entry.sha1,
+                entry.sha1.to_ascii_lowercase(),
+                "{}",
+                entry.title
+            );
+            let settings = &entry.settings;
+            if let Some(platform) = &settings.platform {
+                assert!(platform.parse::<Platform>().is_ok(), "{}", entry.title);
+            }
+            if let Some(variant) = &settings.variant {
+                assert!(variant.parse::<Variant>().is_ok(), "{}", entry.title);
+            }
+            if let Some(quirks) = &settings.quirks {
+                assert!(quirks.parse::<Quirks>().is_ok(), "{}", entry.title);
+            }
+            if let Some(palette) = &settings.palette {
+                assert!(Palette::from_spec(palette).is_ok(), "{}", entry.title);
+            }
+            if let Some(font) = &settings.font {
+                assert!(font.parse::<Font>().is_ok(), "{}", entry.title);
+            }
+            if let Some(keymap) = &settings.keymap {
+                let pairs = keymap
+                    .iter()
+                    .map(|(key, name)| (key.as_str(), name.as_str()));
+                assert!(Keymap::from_pairs(pairs).is_ok(), "{}", entry.title);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_bundled_roms() {
+        let entry = lookup(include_bytes!("../examples/pong.ch8")).unwrap();
+        assert_eq!(entry.title, "Pong");
+        assert_eq!(entry.settings.quirks.as_deref(), Some("vip"));
+        assert_eq!(entry.settings.speed, Some(540));
+
+        assert_eq!(lookup(&[0x12, 0x00]), None);
+    }
+}
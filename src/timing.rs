@@ -0,0 +1,186 @@
+use crate::emulator::{
+    Emulator,
+    Step,
+};
+
+/// Machine cycles the COSMAC VIP's CDP1802 runs in a frame: its 1.76 MHz clock divided by the 8
+/// clock pulses of a machine cycle, and by 60 frames a second.
+pub const VIP_CYCLES_PER_FRAME: u64 = 3668;
+/// Machine cycles taken from every frame by the CDP1861's display DMA, 8 bytes on each of its 128
+/// lines, and the interrupt routine that sets it up and ticks the timers.
+pub const VIP_INTERRUPT_CYCLES: u64 = 128 * 8 + 46;
+/// Machine cycles the interpreter spends fetching and decoding every instruction.
+const VIP_FETCH_CYCLES: u64 = 68;
+/// The cost of a typical instruction, to estimate how many fit in a frame.
+const VIP_TYPICAL_CYCLES: u64 = VIP_FETCH_CYCLES + 30;
+
+/// How long instructions take, which decides how many of them are executed every frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    /// Every instruction takes the same time, with this many executed every frame.
+    Instructions(u64),
+    /// Every instruction takes as many machine cycles as the COSMAC VIP's interpreter took to run
+    /// it, with the display interrupt taking its share of every frame, so programs written for the
+    /// VIP run at their original speed.
+    Vip,
+}
+
+impl Timing {
+    /// How much of a frame `step`, which has just been executed by `emulator`, took up, in the
+    /// units of `budget`.
+    pub fn cost(self, step: Step, emulator: &Emulator) -> u64 {
+        match self {
+            Timing::Instructions(_) => 1,
+            Timing::Vip => vip_cycles(step, emulator),
+        }
+    }
+
+    /// How much time every frame has for executing instructions.
+    pub fn budget(self) -> u64 {
+        match self {
+            Timing::Instructions(count) => count.max(1),
+            Timing::Vip => VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES,
+        }
+    }
+
+    /// How many instructions are executed every frame, on average where it varies.
+    pub fn instructions_per_frame(self) -> u64 {
+        match self {
+            Timing::Instructions(count) => count.max(1),
+            Timing::Vip => self.budget() / VIP_TYPICAL_CYCLES,
+        }
+    }
+}
+
+/// Approximately how many machine cycles the COSMAC VIP's interpreter took to execute `step`,
+/// which has just been executed by `emulator`. Skips cost more when they're taken, and sprites
+/// more the further they are from a byte boundary, as the interpreter shifts every row into place
+/// one bit at a time.
+pub fn vip_cycles(step: Step, emulator: &Emulator) -> u64 {
+    let opcode = step.opcode;
+    let x = (opcode >> 8 & 0xF) as usize;
+    let skipped = emulator.program_counter() == step.address.wrapping_add(4);
+    let skip = |cycles: u64| if skipped { cycles + 4 } else { cycles };
+    let execution = match opcode & 0xF000 {
+        0x0000 if opcode == 0x00E0 => 24 + 3078,
+        0x0000 if opcode == 0x00EE => 10,
+        0x0000 => emulator.machine_code_cycles(),
+        0x1000 => 12,
+        0x2000 => 26,
+        0x3000 | 0x4000 => skip(10),
+        0x5000 | 0x9000 => skip(14),
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0xA000 => 12,
+        0xB000 => 22,
+        0xC000 => 36,
+        0xD000 => {
+            let rows = (opcode & 0xF) as u64;
+            let shift = (emulator.registers()[x] % 8) as u64;
+            let per_row = if shift == 0 { 34 } else { 46 + 8 * shift };
+            26 + rows * per_row
+        }
+        0xE000 => skip(14),
+        _ => match opcode & 0xFF {
+            0x07 | 0x15 | 0x18 => 10,
+            0x0A => 19,
+            0x1E => 16,
+            0x29 => 16,
+            0x33 => 80 + 16 * digit_sum(emulator.registers()[x]),
+            0x55 | 0x65 => 14 + 14 * (x as u64 + 1),
+            _ => 0,
+        },
+    };
+    VIP_FETCH_CYCLES + execution
+}
+
+/// The sum of the decimal digits of `value`. The interpreter works out every digit for `LD B, Vx`
+/// by repeated subtraction, so the bigger the digits the longer it takes.
+fn digit_sum(value: u8) -> u64 {
+    (value / 100 + value / 10 % 10 + value % 10) as u64
+}
+
+/// Decides when frames end as the instructions in them are executed.
+pub struct FrameClock {
+    timing: Timing,
+    remaining: u64,
+}
+
+impl FrameClock {
+    pub fn new(timing: Timing) -> Self {
+        FrameClock {
+            timing,
+            remaining: timing.budget(),
+        }
+    }
+
+    /// Counts an instruction that took `cost` of the frame, returning whether it was the last one
+    /// of the frame. Time an instruction runs over the end of a frame is taken from the next.
+    pub fn advance(&mut self, cost: u64) -> bool {
+        if cost < self.remaining {
+            self.remaining -= cost;
+            return false;
+        }
+        let overrun = cost - self.remaining;
+        self.remaining = self.timing.budget().saturating_sub(overrun).max(1);
+        true
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        vip_cycles,
+        FrameClock,
+        Timing,
+        VIP_FETCH_CYCLES,
+    };
+    use crate::emulator::Emulator;
+
+    fn cycles(program: &[u8], steps: usize) -> Vec<u64> {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(program).unwrap();
+        (0..steps)
+            .map(|_| {
+                let step = emulator.step().unwrap();
+                vip_cycles(step, &emulator)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn instruction_cycles() {
+        // LD V0, 0x08; SE V0, 0x08; SE V0, 0x00; LD V1, 0x03; DRW V0, V1, 2; DRW V1, V1, 2
+        let costs = cycles(
+            &[
+                0x60, 0x08, 0x30, 0x08, 0x00, 0x00, 0x30, 0x00, 0x61, 0x03, 0xD0, 0x12, 0xD1, 0x12,
+            ],
+            6,
+        );
+
+        assert_eq!(
+            costs
+                .iter()
+                .map(|cost| cost - VIP_FETCH_CYCLES)
+                .collect::<Vec<_>>(),
+            vec![6, 14, 10, 6, 26 + 2 * 34, 26 + 2 * (46 + 24)]
+        );
+    }
+
+    #[test]
+    fn frames() {
+        let mut clock = FrameClock::new(Timing::Instructions(3));
+        let ends: Vec<bool> = (0..6).map(|_| clock.advance(1)).collect();
+        assert_eq!(ends, vec![false, false, true, false, false, true]);
+
+        let mut clock = FrameClock::new(Timing::Vip);
+        let budget = Timing::Vip.budget();
+        assert!(!clock.advance(budget - 10));
+        assert!(clock.advance(100));
+        // The 90 cycles that ran over are taken from the next frame.
+        assert!(!clock.advance(budget - 91));
+        assert!(clock.advance(1));
+    }
+}
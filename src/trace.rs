@@ -0,0 +1,597 @@
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    error::Error,
+    fmt,
+    io::{
+        self,
+        BufRead,
+        Write,
+    },
+    ops::RangeInclusive,
+};
+
+use crate::{
+    asm::parse_number,
+    disasm::disassemble,
+    emulator::{
+        Emulator,
+        Step,
+    },
+};
+
+/// Register names, as they're written in trace lines.
+const REGISTER_NAMES: [&str; 16] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+];
+
+/// Which instructions are traced. Anything left out isn't filtered on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// The addresses of the instructions to trace.
+    pub addresses: Option<RangeInclusive<u16>>,
+    /// The cycles to trace, counting the first instruction as cycle 0.
+    pub cycles: Option<RangeInclusive<u64>>,
+}
+
+impl TraceFilter {
+    fn matches(&self, cycle: u64, address: u16) -> bool {
+        if let Some(cycles) = &self.cycles {
+            if !cycles.contains(&cycle) {
+                return false;
+            }
+        }
+        match &self.addresses {
+            Some(addresses) => addresses.contains(&address),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RangeError(String);
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid range '{}', expected START-END", self.0)
+    }
+}
+
+impl Error for RangeError {}
+
+/// Parses an inclusive range of addresses like `0x200-0x2FF`, or a single address.
+pub fn parse_address_range(range: &str) -> Result<RangeInclusive<u16>, RangeError> {
+    let error = || RangeError(range.to_string());
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let start = parse_number(start.trim()).ok_or_else(error)?;
+    let end = parse_number(end.trim()).ok_or_else(error)?;
+    if start > end {
+        return Err(error());
+    }
+    Ok(start..=end)
+}
+
+/// Parses an inclusive range of cycles like `100-200`, or `100-` for every cycle from 100 on.
+pub fn parse_cycle_range(range: &str) -> Result<RangeInclusive<u64>, RangeError> {
+    let error = || RangeError(range.to_string());
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let start = start.trim().parse().map_err(|_| error())?;
+    let end = match end.trim() {
+        "" => u64::MAX,
+        end => end.parse().map_err(|_| error())?,
+    };
+    if start > end {
+        return Err(error());
+    }
+    Ok(start..=end)
+}
+
+/// The trace line for the `cycle`th instruction, `step`, showing the state of `emulator` after it
+/// was executed:
+///
+/// ```text
+/// 0000000000 PC=0200 OP=6005 V=05000000000000000000000000000000 I=0000 SP=0000 DT=00 ST=00  LD V0, 0x05
+/// ```
+///
+/// The fields have the same widths on every line, so traces from different runs line up in a
+/// diff, as long as they're under ten billion instructions long and the stack stays under 0x10000
+/// entries deep.
+pub fn format_line(cycle: u64, step: Step, emulator: &Emulator) -> String {
+    let registers: String = emulator
+        .registers()
+        .iter()
+        .map(|value| format!("{:02X}", value))
+        .collect();
+    format!(
+        "{:010} PC={:04X} OP={:04X} V={} I={:04X} SP={:04X} DT={:02X} ST={:02X}  {}",
+        cycle,
+        step.address,
+        step.opcode,
+        registers,
+        emulator.i(),
+        emulator.stack_pointer(),
+        emulator.delay_timer(),
+        emulator.sound_timer(),
+        disassemble(step.opcode)
+    )
+}
+
+/// Writes a line to `output` for every executed instruction that passes the filter.
+pub struct Tracer<W: Write> {
+    output: W,
+    filter: TraceFilter,
+    cycle: u64,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, filter: TraceFilter) -> Self {
+        Tracer {
+            output,
+            filter,
+            cycle: 0,
+        }
+    }
+
+    /// Records `step`, which has just been executed by `emulator`.
+    pub fn record(&mut self, step: Step, emulator: &Emulator) -> io::Result<()> {
+        if self.filter.matches(self.cycle, step.address) {
+            writeln!(self.output, "{}", format_line(self.cycle, step, emulator))?;
+        }
+        self.cycle += 1;
+        Ok(())
+    }
+
+    /// How many instructions have been recorded, whether they were traced or not.
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// The state recorded by a line of a trace. Only the program counter is required, so that a
+/// reference trace from another emulator only has to include what that emulator can log. Fields
+/// are written as `KEY=VALUE` in hex, like the lines of `format_line`, with the registers either
+/// all together as `V=...` or one by one as `V0=05`. The cycle comes first if it's given, and
+/// anything after the fields, like the mnemonic, is ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Record {
+    pub cycle: Option<u64>,
+    pub address: u16,
+    pub opcode: Option<u16>,
+    pub registers: [Option<u8>; 16],
+    pub i: Option<u16>,
+    pub stack_pointer: Option<u16>,
+    pub delay_timer: Option<u8>,
+    pub sound_timer: Option<u8>,
+}
+
+impl Record {
+    /// Records the whole state of `emulator` after it executed `step`.
+    pub fn capture(step: Step, emulator: &Emulator) -> Self {
+        let mut registers = [None; 16];
+        for (register, &value) in registers.iter_mut().zip(emulator.registers()) {
+            *register = Some(value);
+        }
+        Record {
+            cycle: None,
+            address: step.address,
+            opcode: Some(step.opcode),
+            registers,
+            i: Some(emulator.i()),
+            stack_pointer: Some(emulator.stack_pointer() as u16),
+            delay_timer: Some(emulator.delay_timer()),
+            sound_timer: Some(emulator.sound_timer()),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace().peekable();
+        let cycle = match words.peek() {
+            Some(word) if word.bytes().all(|byte| byte.is_ascii_digit()) => {
+                let cycle = word
+                    .parse()
+                    .map_err(|_| format!("invalid cycle '{}'", word))?;
+                words.next();
+                Some(cycle)
+            }
+            _ => None,
+        };
+
+        let mut address = None;
+        let mut record = Record {
+            cycle,
+            ..Record::default()
+        };
+        for word in words {
+            let (key, value) = match word.split_once('=') {
+                Some(field) => field,
+                None => break,
+            };
+            let invalid = || format!("invalid value '{}' for {}", value, key);
+            let key = key.to_ascii_uppercase();
+            match key.as_str() {
+                "PC" => address = Some(parse_hex(value).ok_or_else(invalid)?),
+                "OP" => record.opcode = Some(parse_hex(value).ok_or_else(invalid)?),
+                "I" => record.i = Some(parse_hex(value).ok_or_else(invalid)?),
+                "SP" => record.stack_pointer = Some(parse_hex(value).ok_or_else(invalid)?),
+                "DT" => record.delay_timer = Some(parse_hex(value).ok_or_else(invalid)?),
+                "ST" => record.sound_timer = Some(parse_hex(value).ok_or_else(invalid)?),
+                "V" => {
+                    if value.len() != 32 {
+                        return Err(invalid());
+                    }
+                    for (x, register) in record.registers.iter_mut().enumerate() {
+                        *register = Some(parse_hex(&value[2 * x..2 * x + 2]).ok_or_else(invalid)?);
+                    }
+                }
+                // Other emulators may log things this one doesn't know about, which are skipped.
+                _ => {
+                    if let Some(x) = REGISTER_NAMES.iter().position(|&name| name == key) {
+                        record.registers[x] = Some(parse_hex(value).ok_or_else(invalid)?);
+                    }
+                }
+            }
+        }
+        record.address = address.ok_or_else(|| String::from("missing PC"))?;
+        Ok(record)
+    }
+
+    /// How `self` differs from `expected`, one line per field. Fields `expected` leaves out aren't
+    /// compared.
+    pub fn differences(&self, expected: &Record) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: &str, actual: Option<u16>, expected: Option<u16>, width: usize| {
+            if let Some(expected) = expected {
+                if actual != Some(expected) {
+                    let show = |value: u16| format!("{:#0w$X}", value, w = width + 2);
+                    differences.push(format!(
+                        "{}: expected {}, got {}",
+                        name,
+                        show(expected),
+                        actual.map_or(String::from("nothing"), show)
+                    ));
+                }
+            }
+        };
+        compare("PC", Some(self.address), Some(expected.address), 3);
+        compare("OP", self.opcode, expected.opcode, 4);
+        for (x, name) in REGISTER_NAMES.iter().enumerate() {
+            compare(
+                name,
+                self.registers[x].map(u16::from),
+                expected.registers[x].map(u16::from),
+                2,
+            );
+        }
+        compare("I", self.i, expected.i, 3);
+        compare("SP", self.stack_pointer, expected.stack_pointer, 1);
+        compare(
+            "DT",
+            self.delay_timer.map(u16::from),
+            expected.delay_timer.map(u16::from),
+            2,
+        );
+        compare(
+            "ST",
+            self.sound_timer.map(u16::from),
+            expected.sound_timer.map(u16::from),
+            2,
+        );
+        differences
+    }
+}
+
+fn parse_hex<T: TryFrom<u64>>(hex: &str) -> Option<T> {
+    let value = u64::from_str_radix(hex.trim_start_matches("0x"), 16).ok()?;
+    T::try_from(value).ok()
+}
+
+#[derive(Debug)]
+pub enum DiffError {
+    Io(io::Error),
+    /// A line of the reference trace that couldn't be parsed, numbered from 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiffError::Io(err) => write!(f, "unable to read the reference trace: {}", err),
+            DiffError::Parse { line, message } => {
+                write!(f, "line {} of the reference trace: {}", line, message)
+            }
+        }
+    }
+}
+
+impl Error for DiffError {}
+
+impl From<io::Error> for DiffError {
+    fn from(err: io::Error) -> Self {
+        DiffError::Io(err)
+    }
+}
+
+/// Where a run first differed from the reference trace.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub cycle: u64,
+    /// Our trace lines for the instructions just before the divergence, which all matched.
+    pub context: Vec<String>,
+    /// The reference trace's line for the instruction.
+    pub expected: String,
+    /// Our trace line for the instruction, or the error that stopped us executing it.
+    pub actual: String,
+    pub differences: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Diverged at cycle {}", self.cycle)?;
+        for line in &self.context {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "- {}", self.expected)?;
+        writeln!(f, "+ {}", self.actual)?;
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a run compared with a reference trace.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Every instruction in the reference trace was matched.
+    Matched {
+        cycles: u64,
+    },
+    Diverged(Divergence),
+}
+
+/// Runs `emulator` alongside the `reference` trace, ticking the timers once every
+/// `cycles_per_frame` instructions, until the trace ends or the run differs from it. The lines of
+/// our trace for the `context` instructions before a divergence are kept to show how it was
+/// reached. Blank lines and lines starting with `#` in the reference are skipped, and if the
+/// reference starts at a later cycle, the instructions before it are executed without comparing
+/// them.
+pub fn compare<R: BufRead>(
+    emulator: &mut Emulator,
+    reference: R,
+    cycles_per_frame: u64,
+    context: usize,
+) -> Result<Comparison, DiffError> {
+    let mut recent = VecDeque::with_capacity(context + 1);
+    let mut cycle = 0;
+    let mut remaining_cycles = cycles_per_frame;
+    for (index, line) in reference.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let expected = Record::parse(trimmed).map_err(|message| DiffError::Parse {
+            line: index + 1,
+            message,
+        })?;
+        let target = expected.cycle.unwrap_or(cycle);
+        if target < cycle {
+            return Err(DiffError::Parse {
+                line: index + 1,
+                message: format!("cycle {} comes after cycle {}", target, cycle - 1),
+            });
+        }
+
+        loop {
+            let result = emulator.step();
+            let step = match result {
+                Ok(step) => step,
+                Err(err) => {
+                    return Ok(Comparison::Diverged(Divergence {
+                        cycle,
+                        context: recent.into(),
+                        expected: trimmed.to_string(),
+                        actual: format!("error: {}", err),
+                        differences: Vec::new(),
+                    }))
+                }
+            };
+            let actual = format_line(cycle, step, emulator);
+            let differences = if cycle == target {
+                Record::capture(step, emulator).differences(&expected)
+            } else {
+                Vec::new()
+            };
+            if !differences.is_empty() {
+                return Ok(Comparison::Diverged(Divergence {
+                    cycle,
+                    context: recent.into(),
+                    expected: trimmed.to_string(),
+                    actual,
+                    differences,
+                }));
+            }
+
+            if context > 0 {
+                if recent.len() == context {
+                    recent.pop_front();
+                }
+                recent.push_back(actual);
+            }
+            remaining_cycles -= 1;
+            if remaining_cycles == 0 {
+                emulator.tick();
+                remaining_cycles = cycles_per_frame;
+            }
+            cycle += 1;
+            if cycle > target {
+                break;
+            }
+        }
+    }
+    Ok(Comparison::Matched { cycles: cycle })
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        compare,
+        format_line,
+        parse_address_range,
+        parse_cycle_range,
+        Comparison,
+        Record,
+        TraceFilter,
+        Tracer,
+    };
+    use crate::emulator::Emulator;
+
+    // 0x200: LD V0, 0x05
+    // 0x202: ADD V0, 0x01
+    // 0x204: JP 0x202
+    const PROGRAM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    fn trace(filter: TraceFilter, cycles: usize) -> Vec<String> {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&PROGRAM).unwrap();
+        let mut output = Vec::new();
+        let mut tracer = Tracer::new(&mut output, filter);
+        for _ in 0..cycles {
+            let step = emulator.step().unwrap();
+            tracer.record(step, &emulator).unwrap();
+        }
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn format() {
+        let lines = trace(TraceFilter::default(), 2);
+
+        assert_eq!(
+            lines,
+            vec![
+                "0000000000 PC=0200 OP=6005 V=05000000000000000000000000000000 I=0000 \
+                 SP=0000 DT=00 ST=00  LD V0, 0x05",
+                "0000000001 PC=0202 OP=7001 V=06000000000000000000000000000000 I=0000 \
+                 SP=0000 DT=00 ST=00  ADD V0, 0x01",
+            ]
+        );
+    }
+
+    #[test]
+    fn format_deep_stack() {
+        let mut emulator = Emulator::new();
+        // CALL 0x200
+        emulator.load_rom(&[0x22, 0x00]).unwrap();
+        let lines: Vec<String> = (0..16)
+            .map(|cycle| {
+                let step = emulator.step().unwrap();
+                format_line(cycle, step, &emulator)
+            })
+            .collect();
+
+        assert!(lines[15].contains(" SP=0010 "));
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+    }
+
+    #[test]
+    fn filters() {
+        let lines = trace(
+            TraceFilter {
+                addresses: Some(0x202..=0x202),
+                cycles: Some(2..=6),
+            },
+            10,
+        );
+
+        let cycles: Vec<&str> = lines.iter().map(|line| &line[..10]).collect();
+        assert_eq!(cycles, vec!["0000000003", "0000000005"]);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_address_range("0x200-0x2FF"), Ok(0x200..=0x2FF));
+        assert_eq!(parse_address_range("0x204"), Ok(0x204..=0x204));
+        assert_eq!(parse_cycle_range("100-200"), Ok(100..=200));
+        assert_eq!(parse_cycle_range("100-"), Ok(100..=u64::MAX));
+        assert!(parse_address_range("0x300-0x200").is_err());
+        assert!(parse_cycle_range("ten-20").is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let record = Record::parse(
+            "00000001 PC=0202 OP=7001 V=06000000000000000000000000000000 I=0000 SP=0 DT=00 \
+             ST=00  ADD V0, 0x01",
+        )
+        .unwrap();
+        assert_eq!(record.cycle, Some(1));
+        assert_eq!(record.address, 0x202);
+        assert_eq!(record.opcode, Some(0x7001));
+        assert_eq!(record.registers[0x0], Some(0x06));
+        assert_eq!(record.sound_timer, Some(0x00));
+
+        let record = Record::parse("pc=0x20A v3=ff i=2ea cpu=chip8 LD V3, 0xFF").unwrap();
+        assert_eq!(record.cycle, None);
+        assert_eq!(record.address, 0x20A);
+        assert_eq!(record.registers[0x3], Some(0xFF));
+        assert_eq!(record.registers[0x0], None);
+        assert_eq!(record.i, Some(0x2EA));
+
+        assert!(Record::parse("V0=05").is_err());
+        assert!(Record::parse("PC=10000").is_err());
+        assert!(Record::parse("PC=200 V=0500").is_err());
+    }
+
+    fn run_compare(reference: &str) -> Comparison {
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&PROGRAM).unwrap();
+        compare(&mut emulator, reference.as_bytes(), 10, 2).unwrap()
+    }
+
+    #[test]
+    fn matching() {
+        let reference = trace(TraceFilter::default(), 6).join("\n");
+
+        assert_eq!(run_compare(&reference), Comparison::Matched { cycles: 6 });
+        // Partial lines, comments and a reference starting later all match too.
+        assert_eq!(
+            run_compare("# from another emulator\nPC=200 V0=05\n\n4 PC=204 I=0"),
+            Comparison::Matched { cycles: 5 }
+        );
+    }
+
+    #[test]
+    fn divergence() {
+        let comparison = run_compare("PC=200\nPC=202\nPC=204\nPC=202 V0=08 I=300");
+
+        let divergence = match comparison {
+            Comparison::Diverged(divergence) => divergence,
+            _ => panic!("expected a divergence"),
+        };
+        assert_eq!(divergence.cycle, 3);
+        assert_eq!(divergence.context.len(), 2);
+        assert!(divergence.context[1].starts_with("0000000002 PC=0204"));
+        assert_eq!(divergence.expected, "PC=202 V0=08 I=300");
+        assert!(divergence.actual.starts_with("0000000003 PC=0202"));
+        assert_eq!(
+            divergence.differences,
+            vec![
+                "V0: expected 0x08, got 0x07",
+                "I: expected 0x300, got 0x000"
+            ]
+        );
+    }
+}
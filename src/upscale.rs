@@ -0,0 +1,409 @@
+use std::{
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
+use crate::palette::Rgb;
+
+/// An RGB image, stored row by row. The frontend builds one from the emulator's display and the
+/// filters below turn it into a larger one, without relying on any graphics hardware.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, fill: Rgb) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Rgb {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Rgb) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// The pixel at `(x + dx, y + dy)`, with coordinates outside of the image clamped to its edges.
+    fn neighbour(&self, x: usize, y: usize, dx: isize, dy: isize) -> Rgb {
+        let clamp = |value: usize, delta: isize, limit: usize| {
+            (value as isize + delta).max(0).min(limit as isize - 1) as usize
+        };
+        self.get(clamp(x, dx, self.width), clamp(y, dy, self.height))
+    }
+
+    /// The image as tightly packed RGBA bytes, ready to be uploaded to a texture.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| vec![pixel.r, pixel.g, pixel.b, 0xFF])
+            .collect()
+    }
+
+    /// The image as a binary PPM file, which most image tools read, and which can be written one
+    /// after another to record a video.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        ppm.extend(
+            self.pixels
+                .iter()
+                .flat_map(|pixel| vec![pixel.r, pixel.g, pixel.b]),
+        );
+        ppm
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// Every pixel becomes a solid block.
+    #[default]
+    Nearest,
+    /// Blocks with every other line darkened, like the gaps between a CRT's scanlines.
+    Scanlines,
+    /// The Scale2x (EPX) pixel-art scaler, which rounds off diagonal edges.
+    Scale2x,
+    /// The Scale3x pixel-art scaler, Scale2x's counterpart for a factor of three.
+    Scale3x,
+    /// Scanlines combined with an aperture grille of red, green and blue phosphor stripes.
+    Crt,
+}
+
+const FILTERS: [(&str, Filter); 5] = [
+    ("nearest", Filter::Nearest),
+    ("scanlines", Filter::Scanlines),
+    ("scale2x", Filter::Scale2x),
+    ("scale3x", Filter::Scale3x),
+    ("crt", Filter::Crt),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownFilter(String);
+
+impl fmt::Display for UnknownFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown filter '{}', expected one of: {}",
+            self.0,
+            FILTERS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+impl Error for UnknownFilter {}
+
+impl FromStr for Filter {
+    type Err = UnknownFilter;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        FILTERS
+            .iter()
+            .find(|(filter_name, _)| filter_name.eq_ignore_ascii_case(name.trim()))
+            .map(|(_, filter)| *filter)
+            .ok_or_else(|| UnknownFilter(name.to_string()))
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = FILTERS.iter().find(|(_, filter)| filter == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+impl Filter {
+    /// The filter following this one, wrapping around at the end.
+    pub fn next(self) -> Self {
+        let index = FILTERS
+            .iter()
+            .position(|(_, filter)| *filter == self)
+            .unwrap();
+        FILTERS[(index + 1) % FILTERS.len()].1
+    }
+
+    /// Scales `source` up by `scale` in both dimensions. The pixel-art scalers work in fixed
+    /// factors, so their output is enlarged further with nearest neighbour scaling and scales
+    /// that aren't a multiple of their factor are rounded up to the next one.
+    pub fn apply(self, source: &Image, scale: usize) -> Image {
+        let scale = scale.max(1);
+        match self {
+            Filter::Nearest => nearest(source, scale),
+            Filter::Scanlines => scanlines(nearest(source, scale), scale),
+            Filter::Scale2x => nearest(&scale2x(source), scale.div_ceil(2)),
+            Filter::Scale3x => nearest(&scale3x(source), scale.div_ceil(3)),
+            Filter::Crt => shadow_mask(scanlines(nearest(source, scale), scale)),
+        }
+    }
+}
+
+fn nearest(source: &Image, scale: usize) -> Image {
+    let mut output = Image::new(
+        source.width * scale,
+        source.height * scale,
+        Rgb::new(0, 0, 0),
+    );
+    for y in 0..output.height {
+        for x in 0..output.width {
+            output.set(x, y, source.get(x / scale, y / scale));
+        }
+    }
+    output
+}
+
+fn darken(color: Rgb, numerator: u16, denominator: u16) -> Rgb {
+    let channel = |value: u8| (value as u16 * numerator / denominator) as u8;
+    Rgb::new(channel(color.r), channel(color.g), channel(color.b))
+}
+
+fn scanlines(mut image: Image, scale: usize) -> Image {
+    // Without at least two lines per source pixel whole rows of the display would disappear.
+    if scale < 2 {
+        return image;
+    }
+    for y in (1..image.height).step_by(2) {
+        for x in 0..image.width {
+            let color = image.get(x, y);
+            image.set(x, y, darken(color, 1, 2));
+        }
+    }
+    image
+}
+
+fn shadow_mask(mut image: Image) -> Image {
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let color = darken(image.get(x, y), 2, 3);
+            let full = image.get(x, y);
+            let masked = match x % 3 {
+                0 => Rgb::new(full.r, color.g, color.b),
+                1 => Rgb::new(color.r, full.g, color.b),
+                _ => Rgb::new(color.r, color.g, full.b),
+            };
+            image.set(x, y, masked);
+        }
+    }
+    image
+}
+
+fn scale2x(source: &Image) -> Image {
+    let mut output = Image::new(source.width * 2, source.height * 2, Rgb::new(0, 0, 0));
+    for y in 0..source.height {
+        for x in 0..source.width {
+            //   A
+            // C P B
+            //   D
+            let p = source.get(x, y);
+            let a = source.neighbour(x, y, 0, -1);
+            let b = source.neighbour(x, y, 1, 0);
+            let c = source.neighbour(x, y, -1, 0);
+            let d = source.neighbour(x, y, 0, 1);
+
+            let (mut e0, mut e1, mut e2, mut e3) = (p, p, p, p);
+            if a != d && c != b {
+                if c == a {
+                    e0 = a;
+                }
+                if a == b {
+                    e1 = b;
+                }
+                if c == d {
+                    e2 = c;
+                }
+                if d == b {
+                    e3 = d;
+                }
+            }
+
+            output.set(2 * x, 2 * y, e0);
+            output.set(2 * x + 1, 2 * y, e1);
+            output.set(2 * x, 2 * y + 1, e2);
+            output.set(2 * x + 1, 2 * y + 1, e3);
+        }
+    }
+    output
+}
+
+fn scale3x(source: &Image) -> Image {
+    let mut output = Image::new(source.width * 3, source.height * 3, Rgb::new(0, 0, 0));
+    for y in 0..source.height {
+        for x in 0..source.width {
+            // A B C
+            // D E F
+            // G H I
+            let a = source.neighbour(x, y, -1, -1);
+            let b = source.neighbour(x, y, 0, -1);
+            let c = source.neighbour(x, y, 1, -1);
+            let d = source.neighbour(x, y, -1, 0);
+            let e = source.get(x, y);
+            let f = source.neighbour(x, y, 1, 0);
+            let g = source.neighbour(x, y, -1, 1);
+            let h = source.neighbour(x, y, 0, 1);
+            let i = source.neighbour(x, y, 1, 1);
+
+            let mut block = [e; 9];
+            if b != h && d != f {
+                if d == b {
+                    block[0] = d;
+                }
+                if (d == b && e != c) || (b == f && e != a) {
+                    block[1] = b;
+                }
+                if b == f {
+                    block[2] = f;
+                }
+                if (d == b && e != g) || (d == h && e != a) {
+                    block[3] = d;
+                }
+                if (b == f && e != i) || (h == f && e != c) {
+                    block[5] = f;
+                }
+                if d == h {
+                    block[6] = d;
+                }
+                if (d == h && e != i) || (h == f && e != g) {
+                    block[7] = h;
+                }
+                if h == f {
+                    block[8] = f;
+                }
+            }
+
+            for (index, &color) in block.iter().enumerate() {
+                output.set(3 * x + index % 3, 3 * y + index / 3, color);
+            }
+        }
+    }
+    output
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        Filter,
+        Image,
+    };
+    use crate::palette::Rgb;
+
+    const OFF: Rgb = Rgb::new(0, 0, 0);
+    const ON: Rgb = Rgb::new(0xFF, 0xFF, 0xFF);
+
+    fn image(rows: &[&str]) -> Image {
+        let mut image = Image::new(rows[0].len(), rows.len(), OFF);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, pixel) in row.chars().enumerate() {
+                if pixel == '#' {
+                    image.set(x, y, ON);
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn nearest() {
+        let output = Filter::Nearest.apply(&image(&["#.", ".#"]), 3);
+
+        assert_eq!(
+            output,
+            image(&["###...", "###...", "###...", "...###", "...###", "...###"])
+        );
+    }
+
+    #[test]
+    fn scanlines_darken_odd_rows() {
+        let output = Filter::Scanlines.apply(&image(&["#"]), 2);
+
+        assert_eq!(output.get(0, 0), ON);
+        assert_eq!(output.get(1, 1), Rgb::new(0x7F, 0x7F, 0x7F));
+    }
+
+    #[test]
+    fn scale2x_rounds_diagonals() {
+        let output = Filter::Scale2x.apply(&image(&["#..", ".#.", "..#"]), 2);
+
+        // The unlit pixel above the diagonal gains the corner facing the line...
+        assert_eq!(output.get(2, 1), ON);
+        // ...but keeps the one facing away from it
+        assert_eq!(output.get(3, 0), OFF);
+        // Pixels on the line itself stay solid
+        assert_eq!(output.get(2, 2), ON);
+        assert_eq!(output.get(3, 3), ON);
+    }
+
+    #[test]
+    fn scale3x_keeps_isolated_pixels_square() {
+        let output = Filter::Scale3x.apply(&image(&["...", ".#.", "..."]), 3);
+
+        assert_eq!(
+            output,
+            Filter::Nearest.apply(&image(&["...", ".#.", "..."]), 3)
+        );
+    }
+
+    #[test]
+    fn scalers_are_enlarged_to_the_requested_scale() {
+        let source = image(&["#.", ".#"]);
+
+        assert_eq!(Filter::Scale2x.apply(&source, 6).width(), 12);
+        assert_eq!(Filter::Scale3x.apply(&source, 6).height(), 12);
+        assert_eq!(Filter::Scale3x.apply(&source, 1).width(), 6);
+    }
+
+    #[test]
+    fn scalers_round_other_scales_up() {
+        let source = image(&["#.", ".#"]);
+
+        assert_eq!(Filter::Scale2x.apply(&source, 3).width(), 8);
+        assert_eq!(Filter::Scale3x.apply(&source, 4).height(), 12);
+    }
+
+    #[test]
+    fn crt_masks_alternate_channels() {
+        let output = Filter::Crt.apply(&image(&["#"]), 3);
+
+        assert_eq!(output.get(0, 0), Rgb::new(0xFF, 0xAA, 0xAA));
+        assert_eq!(output.get(1, 0), Rgb::new(0xAA, 0xFF, 0xAA));
+        assert_eq!(output.get(2, 0), Rgb::new(0xAA, 0xAA, 0xFF));
+    }
+
+    #[test]
+    fn to_rgba() {
+        let mut source = Image::new(2, 1, OFF);
+        source.set(1, 0, Rgb::new(1, 2, 3));
+
+        assert_eq!(source.to_rgba(), vec![0, 0, 0, 0xFF, 1, 2, 3, 0xFF]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        let mut filter = Filter::default();
+        for _ in 0..5 {
+            assert_eq!(filter.to_string().parse(), Ok(filter));
+            filter = filter.next();
+        }
+
+        assert_eq!(filter, Filter::Nearest);
+        assert!("bilinear".parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
+use crate::{
+    emulator::{
+        Emulator,
+        ExecError,
+        DISPLAY_HEIGHT,
+        DISPLAY_WIDTH,
+        KEYPAD_SIZE,
+    },
+    palette::Rgb,
+};
+
+/// Instructions a CHIP-8 variant adds to the instruction set, or changes the meaning of. An
+/// emulator with an extension offers it every instruction before executing it itself.
+pub trait Extension: Send {
+    /// Executes `opcode` if it's one of this extension's instructions, returning whether it was.
+    /// The program counter has already moved past it.
+    fn execute(&mut self, emulator: &mut Emulator, opcode: u16) -> Result<bool, ExecError>;
+
+    /// Returns whatever state the extension keeps to how it was when the program started.
+    fn reset(&mut self) {}
+
+    /// The colour the pixel at `(x, y)` is shown in, which is `lit` or not, if the variant has
+    /// colour hardware.
+    fn pixel_color(&self, _x: usize, _y: usize, _lit: bool) -> Option<Rgb> {
+        None
+    }
+}
+
+/// The variants of CHIP-8 that extend it with instructions of their own or a different display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    /// CHIP-8 as it is.
+    #[default]
+    Chip8,
+    /// CHIP-8E, with instructions for relative jumps, comparisons and saving and loading ranges of
+    /// registers.
+    Chip8E,
+    /// CHIP-8X, for a VIP with the VP-590 colour board and a second keypad. Programs are loaded at
+    /// 0x300, after its larger interpreter.
+    Chip8X,
+    /// CHIP-10, with a 128x64 display.
+    Chip10,
+    /// Hi-res CHIP-8, with a 64x64 display. Programs start with a jump to 0x260, where the
+    /// original interpreter was patched, and their code proper starts at 0x2C0.
+    HiRes,
+}
+
+const VARIANTS: [(&str, Variant); 5] = [
+    ("chip8", Variant::Chip8),
+    ("chip8e", Variant::Chip8E),
+    ("chip8x", Variant::Chip8X),
+    ("chip10", Variant::Chip10),
+    ("hires", Variant::HiRes),
+];
+
+impl Variant {
+    /// How many pixels wide and high the display is.
+    pub fn display_size(self) -> (usize, usize) {
+        match self {
+            Variant::Chip10 => (128, 64),
+            Variant::HiRes => (DISPLAY_WIDTH, 64),
+            _ => (DISPLAY_WIDTH, DISPLAY_HEIGHT),
+        }
+    }
+
+    /// Where the variant loads programs, if it doesn't load them where the machine normally does.
+    pub fn load_address(self) -> Option<u16> {
+        match self {
+            Variant::Chip8X => Some(0x300),
+            _ => None,
+        }
+    }
+
+    /// The instructions this variant adds, if it adds any.
+    pub fn extension(self) -> Option<Box<dyn Extension>> {
+        match self {
+            Variant::Chip8E => Some(Box::new(Chip8E::default())),
+            Variant::Chip8X => Some(Box::new(Chip8X::default())),
+            Variant::HiRes => Some(Box::new(HiRes)),
+            _ => None,
+        }
+    }
+}
+
+/// The instructions of CHIP-8E.
+#[derive(Default)]
+struct Chip8E {
+    /// The address of the `Fx4F` waiting for the delay timer to run out, if one is.
+    waiting_at: Option<u16>,
+}
+
+impl Extension for Chip8E {
+    fn execute(&mut self, emulator: &mut Emulator, opcode: u16) -> Result<bool, ExecError> {
+        let address = emulator.program_counter().wrapping_sub(2);
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let kk = opcode & 0xFF;
+        let v = *emulator.registers();
+        match opcode & 0xF00F {
+            // 5xy1 - SGT Vx, Vy
+            0x5001 if v[x] > v[y] => emulator.set_program_counter(address + 4),
+            0x5001 => {}
+            // 5xy2 - LD [I], Vx-Vy
+            0x5002 => {
+                let i = emulator.i();
+                let memory = emulator.memory_mut();
+                let size = memory.len();
+                for (offset, value) in v[x..=y.max(x)].iter().enumerate() {
+                    memory[(i as usize + offset) % size] = *value;
+                }
+                emulator.set_i(i.wrapping_add((y.max(x) - x + 1) as u16));
+            }
+            // 5xy3 - LD Vx-Vy, [I]
+            0x5003 => {
+                let i = emulator.i();
+                for register in x..=y.max(x) {
+                    let memory = emulator.memory();
+                    let value = memory[(i as usize + register - x) % memory.len()];
+                    emulator.set_register(register, value);
+                }
+                emulator.set_i(i.wrapping_add((y.max(x) - x + 1) as u16));
+            }
+            _ => match opcode {
+                // 00ED - STOP
+                0x00ED => emulator.set_program_counter(address),
+                // 0151 - Waits until the delay timer runs out
+                0x0151 if emulator.delay_timer() > 0 => emulator.set_program_counter(address),
+                0x0151 => {}
+                // 0188 - Skips the next instruction
+                0x0188 => emulator.set_program_counter(address + 4),
+                // BBkk - Jumps back kk bytes
+                _ if opcode & 0xFF00 == 0xBB00 => {
+                    emulator.set_program_counter(address.wrapping_sub(kk))
+                }
+                // BFkk - Jumps forward kk bytes
+                _ if opcode & 0xFF00 == 0xBF00 => {
+                    emulator.set_program_counter(address.wrapping_add(kk))
+                }
+                // Fx1B - Skips Vx bytes
+                _ if opcode & 0xF0FF == 0xF01B => {
+                    emulator.set_program_counter(address + 2 + v[x] as u16)
+                }
+                // Fx4F - Sets the delay timer to Vx and waits until it runs out
+                _ if opcode & 0xF0FF == 0xF04F => {
+                    if self.waiting_at != Some(address) {
+                        emulator.set_delay_timer(v[x]);
+                    }
+                    if emulator.delay_timer() > 0 {
+                        self.waiting_at = Some(address);
+                        emulator.set_program_counter(address);
+                    } else {
+                        self.waiting_at = None;
+                    }
+                }
+                _ => return Ok(false),
+            },
+        }
+        Ok(true)
+    }
+}
+
+/// The VP-590's colours, by the 3 bit codes CHIP-8X programs give them in.
+const CHIP8X_COLORS: [Rgb; 8] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0xFF, 0x00, 0x00),
+    Rgb::new(0x00, 0x00, 0xFF),
+    Rgb::new(0xFF, 0x00, 0xFF),
+    Rgb::new(0x00, 0xFF, 0x00),
+    Rgb::new(0xFF, 0xFF, 0x00),
+    Rgb::new(0x00, 0xFF, 0xFF),
+    Rgb::new(0xFF, 0xFF, 0xFF),
+];
+
+/// The background colours `02A0` cycles through, in order, starting from the one shown at first.
+const CHIP8X_BACKGROUNDS: [Rgb; 4] = [
+    Rgb::new(0x00, 0x00, 0x80),
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0x00, 0x80, 0x00),
+    Rgb::new(0x80, 0x00, 0x00),
+];
+
+/// The foreground colour every zone starts out with: red.
+const CHIP8X_FOREGROUND: u8 = 1;
+
+/// How many pixels wide and high a colour zone is. `Bxy0` colours zones in blocks of four rows.
+const ZONE_WIDTH: usize = 8;
+const ZONE_BLOCK_HEIGHT: usize = 4;
+
+const ZONE_COLUMNS: usize = DISPLAY_WIDTH / ZONE_WIDTH;
+
+/// The instructions of CHIP-8X, and the colours it shows the display in. The foreground colour is
+/// set separately for every row of each 8 pixel wide column of the display.
+struct Chip8X {
+    background: usize,
+    zones: [u8; ZONE_COLUMNS * DISPLAY_HEIGHT],
+}
+
+impl Default for Chip8X {
+    fn default() -> Self {
+        Chip8X {
+            background: 0,
+            zones: [CHIP8X_FOREGROUND; ZONE_COLUMNS * DISPLAY_HEIGHT],
+        }
+    }
+}
+
+impl Chip8X {
+    fn set_zone(&mut self, column: usize, row: usize, color: u8) {
+        self.zones[row % DISPLAY_HEIGHT * ZONE_COLUMNS + column % ZONE_COLUMNS] = color;
+    }
+}
+
+impl Extension for Chip8X {
+    fn execute(&mut self, emulator: &mut Emulator, opcode: u16) -> Result<bool, ExecError> {
+        let address = emulator.program_counter().wrapping_sub(2);
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = (opcode & 0xF) as usize;
+        let v = *emulator.registers();
+        match opcode & 0xF000 {
+            // 02A0 - Cycles the background colour
+            0x0000 if opcode == 0x02A0 => {
+                self.background = (self.background + 1) % CHIP8X_BACKGROUNDS.len()
+            }
+            // 5xy1 - Adds Vy to Vx digit by digit, for BCD-like scores kept in octal nibbles
+            0x5000 if n == 1 => emulator.set_register(x, ((v[x] & 0x77) + (v[y] & 0x77)) & 0x77),
+            // Bxy0 - Colours the zones spanned by Vx (left zone and width) and Vx+1 (top block of
+            // four rows and height) with Vy
+            0xB000 if n == 0 => {
+                let (horizontal, vertical) = (v[x], v[(x + 1) % v.len()]);
+                let left = (horizontal >> 4) as usize;
+                let top = (vertical >> 4) as usize;
+                for column in left..=left + (horizontal & 0xF) as usize {
+                    for block in top..=top + (vertical & 0xF) as usize {
+                        for line in 0..ZONE_BLOCK_HEIGHT {
+                            self.set_zone(column, block * ZONE_BLOCK_HEIGHT + line, v[y] & 7);
+                        }
+                    }
+                }
+            }
+            // Bxyn - Colours n rows of the zone at pixel Vx, Vx+1 with Vy
+            0xB000 => {
+                let (column, top) = (v[x] as usize / ZONE_WIDTH, v[(x + 1) % v.len()] as usize);
+                for row in top..top + n {
+                    self.set_zone(column, row, v[y] & 7);
+                }
+            }
+            // ExF2 - Skips the next instruction if key Vx of the second keypad is pressed
+            // ExF5 - Skips the next instruction if it isn't
+            0xE000 if opcode & 0xFF == 0xF2 || opcode & 0xFF == 0xF5 => {
+                let pressed = emulator.is_key_pressed(KEYPAD_SIZE + (v[x] & 0xF) as usize);
+                if pressed == (opcode & 0xFF == 0xF2) {
+                    emulator.set_program_counter(address + 4);
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn reset(&mut self) {
+        *self = Chip8X::default();
+    }
+
+    fn pixel_color(&self, x: usize, y: usize, lit: bool) -> Option<Rgb> {
+        Some(if lit {
+            CHIP8X_COLORS[self.zones[y * ZONE_COLUMNS + x / ZONE_WIDTH] as usize]
+        } else {
+            CHIP8X_BACKGROUNDS[self.background]
+        })
+    }
+}
+
+/// Hi-res CHIP-8's instructions: the jump to the interpreter patch at the start of every program,
+/// which skips straight to the program's code, and a clear screen of its own.
+struct HiRes;
+
+impl Extension for HiRes {
+    fn execute(&mut self, emulator: &mut Emulator, opcode: u16) -> Result<bool, ExecError> {
+        let address = emulator.program_counter().wrapping_sub(2);
+        match opcode {
+            // 1260 - Turns on hi-res mode and starts the program
+            0x1260 if address == emulator.load_address() => {
+                emulator.set_program_counter(emulator.load_address() + 0xC0)
+            }
+            // 0230 - CLS
+            0x0230 => emulator.framebuffer_mut().clear(),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownVariant(String);
+
+impl fmt::Display for UnknownVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown variant '{}', expected one of: {}",
+            self.0,
+            VARIANTS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+impl Error for UnknownVariant {}
+
+impl FromStr for Variant {
+    type Err = UnknownVariant;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let normalised: String = name
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        VARIANTS
+            .iter()
+            .find(|(variant_name, _)| *variant_name == normalised)
+            .map(|(_, variant)| *variant)
+            .ok_or_else(|| UnknownVariant(name.to_string()))
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = VARIANTS
+            .iter()
+            .find(|(_, variant)| variant == self)
+            .unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        Extension,
+        Variant,
+    };
+    use crate::emulator::{
+        Emulator,
+        ExecError,
+    };
+
+    fn emulator(variant: Variant, program: &[u8]) -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.set_variant(variant);
+        emulator.load_rom(program).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("CHIP-8E".parse(), Ok(Variant::Chip8E));
+        assert_eq!("chip-10".parse(), Ok(Variant::Chip10));
+        assert_eq!("HiRes".parse(), Ok(Variant::HiRes));
+        assert_eq!("CHIP-8X".parse(), Ok(Variant::Chip8X));
+        assert!("chip-48".parse::<Variant>().is_err());
+        assert_eq!(Variant::Chip8E.to_string(), "chip8e");
+    }
+
+    #[test]
+    fn chip8e() {
+        // LD V0, 0x05; LD V1, 0x03; SGT V0, V1; STOP; LD I, 0x300; LD [I], V0-V1; LD V1, V0
+        // LD I, 0x300; LD V0-V1, [I]; BF04; STOP; 0188; STOP; BBxx
+        let mut emulator = emulator(
+            Variant::Chip8E,
+            &[
+                0x60, 0x05, 0x61, 0x03, 0x50, 0x11, 0x00, 0xED, 0xA3, 0x00, 0x50, 0x12, 0x81, 0x00,
+                0xA3, 0x00, 0x50, 0x13, 0xBF, 0x04, 0x00, 0xED, 0x01, 0x88, 0x00, 0xED, 0xBB, 0x1A,
+            ],
+        );
+
+        for _ in 0..11 {
+            emulator.step().unwrap();
+        }
+
+        assert_eq!(emulator.read_memory(0x300, 2), Ok(&[0x05, 0x03][..]));
+        assert_eq!(emulator.registers()[..2], [0x05, 0x03]);
+        assert_eq!(emulator.i(), 0x302);
+        assert_eq!(emulator.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn chip8e_waits() {
+        // LD V0, 0x02; Fx4F
+        let mut emulator = emulator(Variant::Chip8E, &[0x60, 0x02, 0xF0, 0x4F]);
+        emulator.step().unwrap();
+
+        emulator.step().unwrap();
+        assert_eq!(emulator.program_counter(), 0x202);
+        emulator.tick();
+        emulator.step().unwrap();
+        assert_eq!(emulator.delay_timer(), 1);
+        emulator.tick();
+        emulator.step().unwrap();
+        assert_eq!(emulator.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn chip8x() {
+        // LD V0, 0x35; LD V1, 0x16; 5xy1 V0, V1; 02A0; LD V2, 0x12; LD V3, 0x01; LD V4, 0x04
+        // B240; LD V5, 0x08; LD V6, 0x1F; LD V7, 0x06; B572; LD V8, 0x03; E8F2; 00E0; E8F5
+        let mut emulator = Emulator::new();
+        emulator.set_variant(Variant::Chip8X);
+        emulator.set_load_address(Variant::Chip8X.load_address().unwrap());
+        emulator
+            .load_rom(&[
+                0x60, 0x35, 0x61, 0x16, 0x50, 0x11, 0x02, 0xA0, 0x62, 0x12, 0x63, 0x01, 0x64, 0x04,
+                0xB2, 0x40, 0x65, 0x08, 0x66, 0x1F, 0x67, 0x06, 0xB5, 0x72, 0x68, 0x03, 0xE8, 0xF2,
+                0x00, 0xE0, 0xE8, 0xF5,
+            ])
+            .unwrap();
+        emulator.key_press(0x13);
+
+        for _ in 0..15 {
+            emulator.step().unwrap();
+        }
+
+        assert_eq!(emulator.registers()[0], 0x43);
+        // Skipped the CLS, but not the instruction after the ExF5.
+        assert_eq!(emulator.program_counter(), 0x320);
+        assert_eq!(
+            emulator.pixel_color(0, 0),
+            Some(super::CHIP8X_BACKGROUNDS[1])
+        );
+        emulator.framebuffer_mut().draw_sprite(0, 0, &[0xFF; 32]);
+        emulator.framebuffer_mut().draw_sprite(8, 0, &[0xFF; 32]);
+        emulator.framebuffer_mut().draw_sprite(24, 0, &[0xFF; 32]);
+        // Columns 1-3, rows 0-7
+        assert_eq!(emulator.pixel_color(0, 0), Some(super::CHIP8X_COLORS[1]));
+        assert_eq!(emulator.pixel_color(8, 7), Some(super::CHIP8X_COLORS[4]));
+        assert_eq!(emulator.pixel_color(24, 7), Some(super::CHIP8X_COLORS[4]));
+        assert_eq!(emulator.pixel_color(8, 8), Some(super::CHIP8X_COLORS[1]));
+        // Column 1, rows 31 and 0
+        assert_eq!(emulator.pixel_color(15, 31), Some(super::CHIP8X_COLORS[6]));
+        assert_eq!(emulator.pixel_color(15, 0), Some(super::CHIP8X_COLORS[6]));
+        assert_eq!(emulator.pixel_color(15, 1), Some(super::CHIP8X_COLORS[4]));
+
+        emulator.reset();
+        assert_eq!(
+            emulator.pixel_color(0, 0),
+            Some(super::CHIP8X_BACKGROUNDS[0])
+        );
+    }
+
+    #[test]
+    fn hires() {
+        let mut program = vec![0x12, 0x60];
+        program.resize(0xC0, 0);
+        // LD V0, 0x3C; LD F, V0; DRW V0, V0, 5
+        program.extend_from_slice(&[0x60, 0x3C, 0xF0, 0x29, 0xD0, 0x05]);
+        let mut emulator = emulator(Variant::HiRes, &program);
+
+        for _ in 0..4 {
+            emulator.step().unwrap();
+        }
+
+        assert_eq!(emulator.framebuffer().height(), 64);
+        assert!(emulator.pixel_at(60, 60));
+    }
+
+    #[test]
+    fn chip10() {
+        let emulator = emulator(Variant::Chip10, &[0x00, 0xE0]);
+
+        assert_eq!(emulator.framebuffer().width(), 128);
+        assert_eq!(emulator.framebuffer().height(), 64);
+    }
+
+    #[test]
+    fn custom_extension() {
+        struct Nop;
+
+        impl Extension for Nop {
+            fn execute(&mut self, _: &mut Emulator, opcode: u16) -> Result<bool, ExecError> {
+                Ok(opcode == 0xFFFF)
+            }
+        }
+
+        let mut emulator = Emulator::new();
+        emulator.load_rom(&[0xFF, 0xFF, 0xFF, 0xFE]).unwrap();
+        emulator.set_extension(Some(Box::new(Nop)));
+
+        assert!(emulator.step().is_ok());
+        assert!(matches!(
+            emulator.step(),
+            Err(ExecError::UnrecognisedOpcode { .. })
+        ));
+    }
+}
@@ -0,0 +1,417 @@
+use crate::{
+    cdp1802::{
+        Bus,
+        Cdp1802,
+    },
+    emulator::{
+        ExecError,
+        LoadError,
+        Step,
+        DISPLAY_HEIGHT,
+        DISPLAY_WIDTH,
+    },
+    machine::Machine,
+    timing::Timing,
+};
+
+// COSMAC VIP constants
+
+/// Where the CHIP-8 interpreter is loaded, and how much room it has before the program.
+pub const INTERPRETER_SIZE: usize = 0x200;
+/// Where CHIP-8 programs are loaded.
+pub const PROGRAM_ADDRESS: usize = 0x200;
+
+// CDP1861 video timing, in machine cycles and scanlines
+
+const CYCLES_PER_LINE: u64 = 14;
+const LINES_PER_FRAME: u64 = 262;
+const CYCLES_PER_FRAME: u64 = CYCLES_PER_LINE * LINES_PER_FRAME;
+/// The first of the 128 lines the CDP1861 reads from memory by DMA, 8 bytes at a time.
+const FIRST_DISPLAY_LINE: u64 = 80;
+const DISPLAY_LINES: usize = 128;
+const DMA_CYCLES: u64 = 8;
+/// How long before the first DMA the CDP1861 interrupts the CPU, for it to point R0 at the
+/// display.
+const INTERRUPT_LEAD_CYCLES: u64 = 29;
+/// For how many lines before the display starts and before it ends EF1 is asserted.
+const EF1_LINES: u64 = 4;
+
+/// An emulation of the COSMAC VIP's hardware, which ran CHIP-8 programs with an interpreter
+/// written in CDP1802 machine code: the CPU, its RAM, the CDP1861 video chip that reads the
+/// display from memory by DMA, the hex keypad and its latch, and the tone generator driven by Q.
+/// It boots an interpreter loaded at 0x000, which runs the program loaded at 0x200, so it behaves
+/// exactly as that interpreter did, quirks and all.
+///
+/// There's no monitor ROM. The machine starts as the monitor leaves it when handing over to a
+/// program in RAM: with R0 as the program counter at 0x000, and the top page of RAM in R1.1.
+pub struct Vip {
+    cpu: Cdp1802,
+    memory: Vec<u8>,
+    interpreter: Vec<u8>,
+    program: Vec<u8>,
+    /// How many times each key is being held down, like the emulator's keypad.
+    keyboard: [u8; 16],
+    /// The key last written to the keypad's latch with `OUT 2`, whose state EF3 shows.
+    key: u8,
+    /// Whether the CDP1861 is on, after `INP 1` and until `OUT 1`.
+    display_on: bool,
+    /// The lines the CDP1861 has read from memory, 64 pixels each.
+    lines: [u64; DISPLAY_LINES],
+    /// How many machine cycles into the current frame the machine is.
+    frame_cycle: u64,
+    /// How many lines of the current frame have been read from memory.
+    dma_lines: usize,
+    has_interrupted: bool,
+    /// The machine cycles taken by the last instruction.
+    last_cycles: u64,
+    is_paused: bool,
+}
+
+impl Vip {
+    /// Creates a VIP with `memory_size` bytes of RAM and `interpreter` loaded at 0x000.
+    pub fn new(interpreter: &[u8], memory_size: usize) -> Result<Self, LoadError> {
+        if interpreter.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if interpreter.len() > INTERPRETER_SIZE {
+            return Err(LoadError::TooLarge {
+                size: interpreter.len(),
+                capacity: INTERPRETER_SIZE,
+            });
+        }
+
+        let mut vip = Vip {
+            cpu: Cdp1802::new(),
+            memory: vec![0; memory_size],
+            interpreter: interpreter.to_vec(),
+            program: Vec::new(),
+            keyboard: [0; 16],
+            key: 0,
+            display_on: false,
+            lines: [0; DISPLAY_LINES],
+            frame_cycle: 0,
+            dma_lines: 0,
+            has_interrupted: false,
+            last_cycles: 0,
+            is_paused: false,
+        };
+        vip.hard_reset();
+        Ok(vip)
+    }
+
+    /// Copies `rom` into memory at 0x200 and restarts the machine.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        let capacity = self.memory.len().saturating_sub(PROGRAM_ADDRESS);
+        if rom.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if rom.len() > capacity {
+            return Err(LoadError::TooLarge {
+                size: rom.len(),
+                capacity,
+            });
+        }
+
+        self.program = rom.to_vec();
+        self.hard_reset();
+        Ok(())
+    }
+
+    pub fn cpu(&self) -> &Cdp1802 {
+        &self.cpu
+    }
+
+    /// All of RAM, from address 0.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Whether the CDP1861 has been turned on.
+    pub fn is_display_on(&self) -> bool {
+        self.display_on
+    }
+
+    /// Services the DMA and interrupt requests the CDP1861 has made by now. Like the CDP1802,
+    /// this only happens between instructions.
+    fn service_video(&mut self) {
+        let first_dma = FIRST_DISPLAY_LINE * CYCLES_PER_LINE;
+        if self.display_on
+            && !self.has_interrupted
+            && self.cpu.ie
+            && self.frame_cycle >= first_dma - INTERRUPT_LEAD_CYCLES
+        {
+            self.has_interrupted = true;
+            self.cpu.t = self.cpu.x << 4 | self.cpu.p;
+            self.cpu.p = 1;
+            self.cpu.x = 2;
+            self.cpu.ie = false;
+            self.cpu.idle = false;
+            self.frame_cycle += 1;
+        }
+        while self.dma_lines < DISPLAY_LINES
+            && self.frame_cycle >= first_dma + self.dma_lines as u64 * CYCLES_PER_LINE
+        {
+            self.lines[self.dma_lines] = if self.display_on {
+                let mut bytes = [0; 8];
+                for byte in bytes.iter_mut() {
+                    *byte = self.memory[self.cpu.r[0] as usize % self.memory.len()];
+                    self.cpu.r[0] = self.cpu.r[0].wrapping_add(1);
+                }
+                self.cpu.idle = false;
+                self.frame_cycle += DMA_CYCLES;
+                u64::from_be_bytes(bytes)
+            } else {
+                0
+            };
+            self.dma_lines += 1;
+        }
+        if self.frame_cycle >= CYCLES_PER_FRAME {
+            self.frame_cycle -= CYCLES_PER_FRAME;
+            self.dma_lines = 0;
+            self.has_interrupted = false;
+        }
+    }
+
+    /// Whether EF1 is asserted, which it is for the last few lines before the display starts and
+    /// before it ends.
+    fn ef1(&self) -> bool {
+        let line = self.frame_cycle / CYCLES_PER_LINE;
+        let end = FIRST_DISPLAY_LINE + DISPLAY_LINES as u64;
+        self.display_on
+            && ((FIRST_DISPLAY_LINE - EF1_LINES..FIRST_DISPLAY_LINE).contains(&line)
+                || (end - EF1_LINES..end).contains(&line))
+    }
+}
+
+/// What the VIP's CDP1802 is connected to: RAM, mirrored across the whole address space, the
+/// CDP1861, which is turned on with `INP 1` and off with `OUT 1`, and the keypad latch on port 2.
+struct VipBus<'a> {
+    memory: &'a mut [u8],
+    keyboard: &'a [u8; 16],
+    key: &'a mut u8,
+    display_on: &'a mut bool,
+    ef1: bool,
+}
+
+impl Bus for VipBus<'_> {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[address as usize % self.memory.len()]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let address = address as usize % self.memory.len();
+        self.memory[address] = value;
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => *self.display_on = false,
+            2 => *self.key = value & 0xF,
+            _ => {}
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            *self.display_on = true;
+        }
+        0
+    }
+
+    fn flag(&mut self, line: u8) -> bool {
+        match line {
+            1 => self.ef1,
+            3 => self.keyboard[*self.key as usize] > 0,
+            _ => false,
+        }
+    }
+}
+
+impl Machine for Vip {
+    /// Executes a single CDP1802 instruction, whose opcode is the byte at the program counter,
+    /// after servicing any interrupt or DMA due before it.
+    fn step(&mut self) -> Result<Step, ExecError> {
+        self.service_video();
+        let address = self.cpu.r[self.cpu.p as usize];
+        let opcode = self.memory[address as usize % self.memory.len()] as u16;
+        let ef1 = self.ef1();
+        let mut bus = VipBus {
+            memory: &mut self.memory,
+            keyboard: &self.keyboard,
+            key: &mut self.key,
+            display_on: &mut self.display_on,
+            ef1,
+        };
+        // An idle CPU does nothing until the next interrupt or DMA.
+        self.last_cycles = if self.cpu.idle {
+            2
+        } else {
+            self.cpu.step(&mut bus)
+        };
+        self.frame_cycle += self.last_cycles;
+        Ok(Step { address, opcode })
+    }
+
+    /// The machine cycles the last instruction took. Time taken by the CDP1861 is already part of
+    /// the frame budget of `Timing::Vip`, which is the only timing the VIP has.
+    fn cost(&self, _step: Step, _timing: Timing) -> u64 {
+        self.last_cycles
+    }
+
+    /// Does nothing: the interpreter counts its timers down itself, on every display interrupt.
+    fn tick(&mut self) {}
+
+    fn display_size(&self) -> (usize, usize) {
+        (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+    }
+
+    /// Whether the pixel at column `x` of row `y` of the 64x32 CHIP-8 display is lit. The
+    /// interpreter shows every row on 4 lines, of which the first is used.
+    fn pixel_at(&self, x: usize, y: usize) -> bool {
+        assert!(x < DISPLAY_WIDTH && y < DISPLAY_HEIGHT);
+
+        let lines_per_row = DISPLAY_LINES / DISPLAY_HEIGHT;
+        self.lines[y * lines_per_row] >> (DISPLAY_WIDTH - 1 - x) & 1 == 1
+    }
+
+    /// Presses `key` on the VIP's keypad. It only has one, so keys of the second are ignored.
+    fn key_press(&mut self, key: usize) {
+        if let Some(presses) = self.keyboard.get_mut(key) {
+            *presses = presses.saturating_add(1);
+        }
+    }
+
+    fn key_release(&mut self, key: usize) {
+        if let Some(presses) = self.keyboard.get_mut(key) {
+            *presses = presses.saturating_sub(1);
+        }
+    }
+
+    /// Whether the tone generator is sounding, which it does while Q is set.
+    fn is_sound_playing(&self) -> bool {
+        self.cpu.q
+    }
+
+    /// Presses the VIP's reset switch and runs again, which restarts the interpreter without
+    /// touching memory.
+    fn reset(&mut self) {
+        self.cpu = Cdp1802::new();
+        self.cpu.r[1] = (self.memory.len() as u16 - 1) & 0xFF00;
+        self.key = 0;
+        self.display_on = false;
+        self.lines = [0; DISPLAY_LINES];
+        self.frame_cycle = 0;
+        self.dma_lines = 0;
+        self.has_interrupted = false;
+        self.last_cycles = 0;
+    }
+
+    /// Clears memory, reloads the interpreter and the program and restarts.
+    fn hard_reset(&mut self) {
+        self.reset();
+        for byte in self.memory.iter_mut() {
+            *byte = 0;
+        }
+        self.memory[..self.interpreter.len()].copy_from_slice(&self.interpreter);
+        let end = PROGRAM_ADDRESS + self.program.len();
+        self.memory[PROGRAM_ADDRESS..end].copy_from_slice(&self.program);
+    }
+
+    fn pause(&mut self) {
+        self.is_paused = true;
+    }
+
+    fn resume(&mut self) {
+        self.is_paused = false;
+    }
+
+    fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::{
+        Vip,
+        CYCLES_PER_FRAME,
+    };
+    use crate::{
+        emulator::LoadError,
+        machine::Machine,
+    };
+
+    // 0x00: LDI 0x04; PLO R3; SEP R3            Switches the program counter to R3
+    // 0x04: LDI 0x31; PLO R1; LDI 0x00; PHI R1    R1 = 0x0031, the interrupt routine
+    // 0x0A: LDI 0x0E; PHI R2; LDI 0xFF; PLO R2    R2 = 0x0EFF, the stack
+    // 0x10: SEX R2; INP 1                         Turns the display on
+    // 0x12: LDI 0x0F; PHI R4; LDI 0x00; PLO R4
+    // 0x18: LDI 0xFF; STR R4                      Lights the first 8 pixels
+    // 0x1B: LDI 0x0F; STR R2; OUT 2; DEC R2       Latches key 0xF
+    // 0x20: B3 0x20                               Waits while it's held down
+    // 0x22: SEQ; BR 0x23                          Sounds the tone
+    //
+    // 0x30: RET
+    // 0x31: DEC R2; SAV
+    // 0x33: LDI 0x0F; PHI R0; LDI 0x00; PLO R0    Points R0 at the display for the DMA
+    // 0x39: BR 0x30
+    fn interpreter() -> Vec<u8> {
+        let mut interpreter = vec![
+            0xF8, 0x04, 0xA3, 0xD3, 0xF8, 0x31, 0xA1, 0xF8, 0x00, 0xB1, 0xF8, 0x0E, 0xB2, 0xF8,
+            0xFF, 0xA2, 0xE2, 0x69, 0xF8, 0x0F, 0xB4, 0xF8, 0x00, 0xA4, 0xF8, 0xFF, 0x54, 0xF8,
+            0x0F, 0x52, 0x62, 0x22, 0x36, 0x20, 0x7B, 0x30, 0x23,
+        ];
+        interpreter.resize(0x30, 0);
+        interpreter.extend_from_slice(&[
+            0x70, 0x22, 0x78, 0xF8, 0x0F, 0xB0, 0xF8, 0x00, 0xA0, 0x30, 0x30,
+        ]);
+        interpreter
+    }
+
+    fn run_frames(vip: &mut Vip, frames: u64) {
+        let mut cycles = 0;
+        while cycles < frames * CYCLES_PER_FRAME {
+            vip.step().unwrap();
+            cycles += vip.last_cycles;
+        }
+    }
+
+    #[test]
+    fn new() {
+        assert_eq!(Vip::new(&[], 4096).err(), Some(LoadError::Empty));
+        assert_eq!(
+            Vip::new(&[0; 0x201], 4096).err(),
+            Some(LoadError::TooLarge {
+                size: 0x201,
+                capacity: 0x200
+            })
+        );
+
+        let vip = Vip::new(&[0x7B], 2048).unwrap();
+
+        assert_eq!(vip.memory()[0], 0x7B);
+        assert_eq!(vip.cpu().r[1], 0x0700);
+    }
+
+    #[test]
+    fn boots_the_interpreter() {
+        let mut vip = Vip::new(&interpreter(), 4096).unwrap();
+        vip.load_rom(&[0x12, 0x00]).unwrap();
+        vip.key_press(0xF);
+
+        run_frames(&mut vip, 2);
+
+        assert!(vip.is_display_on());
+        assert_eq!(vip.memory()[0x200], 0x12);
+        assert!((0..8).all(|x| vip.pixel_at(x, 0)));
+        assert!(!vip.pixel_at(8, 0));
+        assert!(!vip.pixel_at(0, 1));
+        assert!(!vip.is_sound_playing());
+
+        vip.key_release(0xF);
+        run_frames(&mut vip, 1);
+
+        assert!(vip.is_sound_playing());
+    }
+}